@@ -79,12 +79,12 @@ impl TreeArena {
     }
 
     #[instrument(level = "trace", skip(self))]
-    pub fn iter(&self) -> TreeIterator {
+    pub fn iter(&self) -> TreeIterator<'_> {
         TreeIterator::new(self)
     }
 
     #[instrument(level = "trace", skip(self))]
-    pub fn iter_postorder(&self) -> PostOrderIterator {
+    pub fn iter_postorder(&self) -> PostOrderIterator<'_> {
         PostOrderIterator::new(self)
     }
 
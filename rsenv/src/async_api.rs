@@ -0,0 +1,62 @@
+//! Async wrappers for embedding rsenv in async applications (feature `async`).
+//!
+//! The underlying operations are blocking file system walks, they run on tokio's blocking
+//! thread pool and report progress through a channel.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use tokio::sync::mpsc;
+use tracing::instrument;
+
+use crate::arena::TreeArena;
+use crate::builder::TreeBuilder;
+use crate::errors::{TreeError, TreeResult};
+use crate::{build_env_with_progress, BuildOptions};
+
+pub type BuildEnvResult = TreeResult<(BTreeMap<String, String>, Vec<PathBuf>, bool)>;
+
+/// Progress events of `build_env_stream`.
+#[derive(Debug)]
+pub enum BuildEvent {
+    /// A file of the hierarchy is about to be read
+    FileRead(PathBuf),
+    /// The build has completed, this is always the last event
+    Finished(BuildEnvResult),
+}
+
+/// Builds the environment of `file_path` in the background and streams progress events.
+///
+/// Must be called from within a tokio runtime.
+#[instrument(level = "debug")]
+pub fn build_env_stream(file_path: &Path) -> mpsc::UnboundedReceiver<BuildEvent> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let file_path = file_path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let result = build_env_with_progress(&file_path, &BuildOptions::default(), |path| {
+            let _ = tx.send(BuildEvent::FileRead(path.to_path_buf()));
+        });
+        let _ = tx.send(BuildEvent::Finished(result));
+    });
+    rx
+}
+
+/// Async variant of `build_env`.
+#[instrument(level = "debug")]
+pub async fn build_env_async(file_path: &Path) -> BuildEnvResult {
+    let mut rx = build_env_stream(file_path);
+    while let Some(event) = rx.recv().await {
+        if let BuildEvent::Finished(result) = event {
+            return result;
+        }
+    }
+    Err(TreeError::InternalError("Build task ended without result".to_string()))
+}
+
+/// Async variant of `TreeBuilder::build_from_directory`.
+#[instrument(level = "debug")]
+pub async fn build_trees_async(directory_path: &Path) -> TreeResult<Vec<TreeArena>> {
+    let directory_path = directory_path.to_path_buf();
+    tokio::task::spawn_blocking(move || TreeBuilder::new().build_from_directory(&directory_path))
+        .await
+        .map_err(|e| TreeError::InternalError(format!("Build task failed: {}", e)))?
+}
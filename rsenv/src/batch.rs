@@ -0,0 +1,86 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use serde_json::{json, Map, Value};
+use tracing::{debug, instrument};
+
+use crate::errors::{TreeError, TreeResult};
+use crate::util::file::{read_dir_files, WalkIssue, WalkPolicy};
+use crate::util::path::PathExt;
+use crate::{build_env_with_options, extract_env, BuildOptions};
+
+/// Result of building a single leaf in `build_all`.
+#[derive(Debug)]
+pub struct LeafBuild {
+    pub leaf: PathBuf,
+    pub result: TreeResult<BTreeMap<String, String>>,
+}
+
+/// Returns all env files below `dir` which are not the parent of another env file.
+///
+/// Unreadable files are skipped and reported as issues.
+#[instrument(level = "debug")]
+pub fn find_leaves(dir: &Path) -> TreeResult<(Vec<PathBuf>, Vec<WalkIssue>)> {
+    let (files, issues) = read_dir_files(dir, WalkPolicy::SkipWithWarning)?;
+    let env_files: Vec<PathBuf> = files.into_iter()
+        .map(|(path, _)| path)
+        .filter(|path| path.is_env_file())
+        .map(|path| path.to_canonical())
+        .collect::<TreeResult<_>>()?;
+
+    let mut parents = HashSet::new();
+    for file in &env_files {
+        // files with broken parents are still leaves, their build reports the error
+        if let Ok((_, file_parents)) = extract_env(file) {
+            parents.extend(file_parents);
+        }
+    }
+    let mut leaves: Vec<PathBuf> = env_files.into_iter().filter(|f| !parents.contains(f)).collect();
+    leaves.sort();
+    Ok((leaves, issues))
+}
+
+/// Builds every leaf below `dir` in parallel. A failing leaf does not stop the others, its
+/// error is returned in its `LeafBuild`. Results are sorted by leaf path.
+///
+/// Unreadable files are skipped and reported as issues, see `find_leaves`.
+#[instrument(level = "debug")]
+pub fn build_all(dir: &Path, options: &BuildOptions) -> TreeResult<(Vec<LeafBuild>, Vec<WalkIssue>)> {
+    let (leaves, issues) = find_leaves(dir)?;
+    let workers = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let chunk_size = leaves.len().div_ceil(workers).max(1);
+    debug!("Building {} leaves with {} workers", leaves.len(), workers);
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = leaves.chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || {
+                chunk.iter()
+                    .map(|leaf| LeafBuild {
+                        leaf: leaf.clone(),
+                        result: build_env_with_options(leaf, options).map(|(variables, _, _)| variables),
+                    })
+                    .collect::<Vec<_>>()
+            }))
+            .collect();
+        let mut builds = Vec::new();
+        for handle in handles {
+            builds.extend(handle.join().map_err(|_| TreeError::InternalError("Build worker panicked".to_string()))?);
+        }
+        Ok((builds, issues))
+    })
+}
+
+/// JSON document keyed by leaf path (formatted by `key`), holding either `variables` or
+/// `error` per leaf.
+pub fn builds_to_json(builds: &[LeafBuild], key: impl Fn(&Path) -> String) -> Value {
+    let mut document = Map::new();
+    for build in builds {
+        let entry = match &build.result {
+            Ok(variables) => json!({ "variables": variables }),
+            Err(e) => json!({ "error": e.to_string() }),
+        };
+        document.insert(key(&build.leaf), entry);
+    }
+    Value::Object(document)
+}
@@ -1,20 +1,22 @@
 use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::BufReader;
-use std::io::BufRead;
 use std::path::{Path, PathBuf};
+use generational_arena::Index;
 use regex::Regex;
 use tracing::instrument;
-use walkdir::WalkDir;
 
 use crate::errors::{TreeError, TreeResult};
 use crate::arena::{TreeArena, NodeData};
-use crate::util::path::PathExt;
+use crate::util::file::{read_dir_files, WalkIssue, WalkPolicy};
+use crate::util::path::{ensure_within, PathExt};
+use crate::parse_parent_spec;
 
 pub struct TreeBuilder {
     relationship_cache: HashMap<PathBuf, Vec<PathBuf>>,
     visited_paths: HashSet<PathBuf>,
     parent_regex: Regex,
+    policy: WalkPolicy,
+    confine_to: Option<PathBuf>,
+    issues: Vec<WalkIssue>,
 }
 
 impl Default for TreeBuilder {
@@ -29,9 +31,30 @@ impl TreeBuilder {
             relationship_cache: HashMap::new(),
             visited_paths: HashSet::new(),
             parent_regex: Regex::new(r"# rsenv: (.+)").unwrap(),
+            policy: WalkPolicy::default(),
+            confine_to: None,
+            issues: Vec::new(),
         }
     }
 
+    /// Sets how unreadable files are handled while scanning directories.
+    pub fn with_policy(mut self, policy: WalkPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Refuses to scan directories or follow parents outside `root`, see
+    /// `BuildOptions::confine_to`.
+    pub fn with_confinement(mut self, root: Option<PathBuf>) -> Self {
+        self.confine_to = root;
+        self
+    }
+
+    /// Files skipped during the last scan.
+    pub fn issues(&self) -> &[WalkIssue] {
+        &self.issues
+    }
+
     #[instrument(level = "debug", skip(self))]
     pub fn build_from_directory(&mut self, directory_path: &Path) -> TreeResult<Vec<TreeArena>> {
         if !directory_path.exists() {
@@ -43,6 +66,9 @@ impl TreeBuilder {
                 reason: "Not a directory".to_string()
             });
         }
+        if let Some(root) = &self.confine_to {
+            ensure_within(directory_path, root, None)?;
+        }
 
         // Scan directory and build relationship cache
         self.scan_directory(directory_path)?;
@@ -62,33 +88,29 @@ impl TreeBuilder {
 
     #[instrument(level = "debug", skip(self))]
     fn scan_directory(&mut self, directory_path: &Path) -> TreeResult<()> {
-        for entry in WalkDir::new(directory_path) {
-            let entry = entry.map_err(|e| TreeError::PathResolution {
-                path: directory_path.to_path_buf(),
-                reason: e.to_string(),
-            })?;
-
-            if entry.file_type().is_file() {
-                self.process_file(entry.path())?;
-            }
+        let (files, issues) = read_dir_files(directory_path, self.policy)?;
+        self.issues = issues;
+        for (path, lines) in files {
+            self.process_file(&path, &lines)?;
         }
         Ok(())
     }
 
-    #[instrument(level = "debug", skip(self))]
-    fn process_file(&mut self, path: &Path) -> TreeResult<()> {
-        let file = File::open(path).map_err(TreeError::FileReadError)?;
-        let reader = BufReader::new(file);
+    #[instrument(level = "debug", skip(self, lines))]
+    fn process_file(&mut self, path: &Path, lines: &[String]) -> TreeResult<()> {
         let abs_path = path.to_canonical()?;
         let current_dir = abs_path.parent()
             .ok_or_else(|| TreeError::InvalidParent(path.to_path_buf()))?;
 
-        for line in reader.lines() {
-            let line = line.map_err(TreeError::FileReadError)?;
-            if let Some(caps) = self.parent_regex.captures(&line) {
-                let parent_relative = caps.get(1).unwrap().as_str();
+        for line in lines {
+            if let Some(caps) = self.parent_regex.captures(line) {
+                let (parent_relative, _) = parse_parent_spec(caps.get(1).unwrap().as_str())
+                    .map_err(|reason| TreeError::InvalidFormat { path: path.to_path_buf(), reason })?;
                 let parent_path = current_dir.join(parent_relative);
                 let parent_canonical = parent_path.to_canonical()?;
+                if let Some(root) = &self.confine_to {
+                    ensure_within(&parent_canonical, root, Some(&abs_path))?;
+                }
 
                 self.relationship_cache
                     .entry(parent_canonical)
@@ -111,13 +133,21 @@ impl TreeBuilder {
     #[instrument(level = "debug", skip(self))]
     fn build_tree(&mut self, root_path: &Path) -> TreeResult<TreeArena> {
         let mut tree = TreeArena::new();
-        let mut stack = vec![(root_path.to_path_buf(), None)];
+        // every file comes with its chain of parents down from the root, to report cycles
+        let mut stack: Vec<(PathBuf, Option<Index>, Vec<PathBuf>)> = vec![(root_path.to_path_buf(), None, Vec::new())];
         self.visited_paths.clear();
 
-        while let Some((current_path, parent_idx)) = stack.pop() {
-            // Check for cycles
+        while let Some((current_path, parent_idx, ancestors)) = stack.pop() {
+            if let Some(start) = ancestors.iter().position(|file| file == &current_path) {
+                // reported from child to parent, like `build_env` does
+                let mut path = ancestors[start..].to_vec();
+                path.push(current_path);
+                path.reverse();
+                return Err(TreeError::CyclicDependency { path });
+            }
+            // reached again without a cycle, so it is the child of several parents
             if !self.visited_paths.insert(current_path.clone()) {
-                return Err(TreeError::CycleDetected(current_path));
+                return Err(TreeError::MultipleParents(current_path));
             }
 
             let node_data = NodeData {
@@ -131,8 +161,10 @@ impl TreeBuilder {
 
             // Add children to stack
             if let Some(children) = self.relationship_cache.get(&current_path) {
+                let mut chain = ancestors;
+                chain.push(current_path.clone());
                 for child in children {
-                    stack.push((child.clone(), Some(current_idx)));
+                    stack.push((child.clone(), Some(current_idx), chain.clone()));
                 }
             }
         }
@@ -0,0 +1,59 @@
+use std::path::Path;
+
+use tracing::instrument;
+
+use crate::errors::{TreeError, TreeResult};
+use crate::{get_files_with_options, BuildOptions};
+use crate::util::hash::fnv1a_hex;
+use crate::util::path::PathExt;
+
+/// Fingerprint of a hierarchy: changes whenever a file of the hierarchy is added, removed or
+/// edited. Used to decide whether a cached build of `leaf` is still valid.
+#[instrument(level = "debug")]
+pub fn hierarchy_hash(leaf: &Path, options: &BuildOptions) -> TreeResult<String> {
+    let mut data = Vec::new();
+    for file in get_files_with_options(leaf, options)? {
+        data.extend_from_slice(file.to_utf8_str()?.as_bytes());
+        data.push(0);
+        data.extend(std::fs::read(&file).map_err(TreeError::FileReadError)?);
+        data.push(0);
+    }
+    Ok(fnv1a_hex(&data))
+}
+
+/// Shell stanza for the managed `.envrc` section which sources a cached build of `leaf`.
+///
+/// The cache lives in `${XDG_CACHE_HOME:-$HOME/.cache}/rsenv`. Its first line holds the
+/// `hierarchy_hash` it was built from, `rsenv build` only runs when the hash changed. The
+/// build goes to a temporary file which replaces the cache only if it succeeded, so neither a
+/// failed nor an interrupted build is cached. Without a valid cache nothing is sourced.
+#[instrument(level = "debug")]
+pub fn cached_envrc_stanza(leaf: &Path) -> TreeResult<String> {
+    let leaf = leaf.to_canonical()?;
+    let leaf_str = leaf.to_utf8_str()?;
+    Ok(format!(
+        "rsenv_leaf={leaf}\n\
+         rsenv_cache=\"${{XDG_CACHE_HOME:-$HOME/.cache}}/rsenv/{name}.env\"\n\
+         rsenv_hash=\"$(rsenv hash \"$rsenv_leaf\")\"\n\
+         if [ -n \"$rsenv_hash\" ] && [ \"$(head -n 1 \"$rsenv_cache\" 2>/dev/null)\" != \"# $rsenv_hash\" ]; then\n\
+         \x20   mkdir -p \"$(dirname \"$rsenv_cache\")\"\n\
+         \x20   rsenv_tmp=\"$(mktemp \"$rsenv_cache.XXXXXX\")\"\n\
+         \x20   if {{ echo \"# $rsenv_hash\"; rsenv build \"$rsenv_leaf\"; }} > \"$rsenv_tmp\"; then\n\
+         \x20       mv \"$rsenv_tmp\" \"$rsenv_cache\"\n\
+         \x20   else\n\
+         \x20       rm -f \"$rsenv_tmp\"\n\
+         \x20   fi\n\
+         fi\n\
+         if [ -n \"$rsenv_hash\" ] && [ \"$(head -n 1 \"$rsenv_cache\" 2>/dev/null)\" = \"# $rsenv_hash\" ]; then\n\
+         \x20   source \"$rsenv_cache\"\n\
+         else\n\
+         \x20   echo \"rsenv: cannot build $rsenv_leaf\" >&2\n\
+         fi\n",
+        leaf = shell_quote(leaf_str),
+        name = fnv1a_hex(leaf_str.as_bytes()),
+    ))
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
@@ -0,0 +1,105 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use tracing::{debug, instrument};
+
+use crate::errors::{TreeError, TreeResult};
+
+/// External programs rsenv integrates with and the features depending on them.
+const INTEGRATIONS: &[(&str, &str)] = &[
+    ("direnv", "automatic loading of .envrc (envrc, select, select-leaf)"),
+    ("vim", "edit, edit-leaf, tree-edit"),
+    ("git", "build --git-rev"),
+    ("docker", "exec --docker, exec --compose-service"),
+    ("sops", "decryption of SOPS-encrypted env files (*.enc.env)"),
+];
+
+/// Availability of an external program on this machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capability {
+    pub program: &'static str,
+    pub features: &'static str,
+    /// Location in `PATH`, `None` if the program is missing
+    pub path: Option<PathBuf>,
+}
+
+impl Capability {
+    pub fn is_available(&self) -> bool {
+        self.path.is_some()
+    }
+}
+
+static CAPABILITIES: OnceLock<Vec<Capability>> = OnceLock::new();
+
+/// Returns the availability of all integrations. `PATH` is searched once per process.
+pub fn capabilities() -> &'static [Capability] {
+    CAPABILITIES.get_or_init(|| {
+        INTEGRATIONS
+            .iter()
+            .map(|(program, features)| Capability {
+                program,
+                features,
+                path: find_program(program),
+            })
+            .collect()
+    })
+}
+
+/// Returns the location of `program`, failing with a descriptive error if it is not installed.
+///
+/// Commands call this before starting work, so a missing program does not fail mid-operation.
+#[instrument(level = "debug")]
+pub fn require_program(program: &str) -> TreeResult<PathBuf> {
+    let path = match capabilities().iter().find(|c| c.program == program) {
+        Some(capability) => capability.path.clone(),
+        None => find_program(program),
+    };
+    path.ok_or_else(|| TreeError::ProgramNotFound(program.to_string()))
+}
+
+/// Searches `PATH` for an executable named `program`. Paths containing a separator are
+/// checked directly.
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    if program.contains(std::path::MAIN_SEPARATOR) {
+        let path = PathBuf::from(program);
+        return is_executable(&path).then_some(path);
+    }
+    let found = env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(program))
+            .find(|candidate| is_executable(candidate))
+    });
+    debug!("{} -> {:?}", program, found);
+    found
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file() || path.with_extension("exe").is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_program_in_path() {
+        assert!(find_program("sh").is_some());
+        assert!(find_program("rsenv-does-not-exist").is_none());
+    }
+
+    #[test]
+    fn test_require_missing_program() {
+        let result = require_program("rsenv-does-not-exist");
+        assert!(matches!(result, Err(TreeError::ProgramNotFound(p)) if p == "rsenv-does-not-exist"));
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use tracing::{debug, instrument};
+
+use crate::errors::TreeResult;
+use crate::strip_export;
+use crate::util::file::{read_text_file, write_text_file, TextFormat};
+
+/// Variables written by `capture_env`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CaptureReport {
+    /// Variables appended to the file
+    pub added: Vec<String>,
+    /// Variables whose existing `export` line was replaced
+    pub updated: Vec<String>,
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any sequence of characters.
+pub fn matches_pattern(name: &str, pattern: &str) -> bool {
+    match pattern.split_once('*') {
+        None => name == pattern,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else { return false };
+            (0..=name.len())
+                .filter(|i| name.is_char_boundary(*i))
+                .any(|i| matches_pattern(&name[i..], rest))
+        }
+    }
+}
+
+/// Writes the variables of `environment` matching any of `patterns` into the env file `leaf`.
+///
+/// Existing assignments of a captured variable are replaced in place, new variables are
+/// appended below a comment naming the patterns they were captured with. The file is created
+/// if it does not exist; BOM and line endings of an existing file are kept.
+#[instrument(level = "debug", skip(environment))]
+pub fn capture_env(
+    leaf: &Path,
+    environment: impl IntoIterator<Item = (String, String)>,
+    patterns: &[String],
+) -> TreeResult<CaptureReport> {
+    let captured: BTreeMap<String, String> = environment.into_iter()
+        .filter(|(name, _)| patterns.iter().any(|pattern| matches_pattern(name, pattern)))
+        .collect();
+    debug!("Capturing {:?}", captured.keys());
+
+    let (mut lines, format) = if leaf.exists() {
+        read_text_file(leaf)?
+    } else {
+        (Vec::new(), TextFormat { trailing_newline: true, ..Default::default() })
+    };
+
+    let mut report = CaptureReport::default();
+    let mut appended = Vec::new();
+    for (name, value) in &captured {
+        let export = format!("export {}={}", name, shell_value(value));
+        let existing = lines.iter().position(|line| {
+            strip_export(line)
+                .and_then(|export| export.split_once('='))
+                .is_some_and(|(key, _)| key.trim() == name)
+        });
+        match existing {
+            Some(index) => {
+                lines[index] = export;
+                report.updated.push(name.clone());
+            }
+            None => {
+                appended.push(export);
+                report.added.push(name.clone());
+            }
+        }
+    }
+    if !appended.is_empty() {
+        lines.push(format!("# captured from environment ({})", patterns.join(" ")));
+        lines.extend(appended);
+    }
+    if !captured.is_empty() {
+        write_text_file(leaf, &lines, format)?;
+    }
+    Ok(report)
+}
+
+/// Double-quotes `value` unless it consists of characters the shell takes literally.
+fn shell_value(value: &str) -> String {
+    let literal = !value.is_empty()
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || "_-./:@%+,".contains(c));
+    if literal {
+        return value.to_string();
+    }
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("AWS_REGION", "AWS_*"));
+        assert!(matches_pattern("AWS_REGION", "*REGION"));
+        assert!(matches_pattern("AWS_REGION", "AWS_REGION"));
+        assert!(matches_pattern("MY_AWS_KEY", "*AWS*"));
+        assert!(!matches_pattern("MY_AWS_REGION", "AWS_*"));
+    }
+
+    #[test]
+    fn test_shell_value() {
+        assert_eq!(shell_value("eu-west-1"), "eu-west-1");
+        assert_eq!(shell_value("a b"), "\"a b\"");
+        assert_eq!(shell_value("$HOME\"x"), "\"\\$HOME\\\"x\"");
+        assert_eq!(shell_value(""), "\"\"");
+    }
+}
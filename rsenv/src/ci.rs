@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+
+use crate::errors::{TreeError, TreeResult};
+use crate::util::hash::fnv1a_hex;
+use crate::util::value::unquote;
+
+/// Name parts marking a variable as secret, e.g. `DB_PASSWORD` or `GITHUB_TOKEN`.
+const SECRET_NAME_PARTS: &[&str] = &["SECRET", "TOKEN", "PASSWORD", "PASSWD", "PASS", "KEY", "CREDENTIALS", "PRIVATE"];
+
+/// Whether `key` looks like it holds a secret. Matches whole `_`-separated parts
+/// case-insensitively, so `API_KEY` matches but `MONKEY` does not.
+pub fn is_secret_name(key: &str) -> bool {
+    key.split('_')
+        .any(|part| SECRET_NAME_PARTS.iter().any(|secret| part.eq_ignore_ascii_case(secret)))
+}
+
+/// Formats variables for `$GITHUB_ENV` or `$GITHUB_OUTPUT`.
+///
+/// Values are unquoted like the shell would. Multiline values use the heredoc syntax with a
+/// delimiter derived from the value, so it cannot occur inside it.
+pub fn format_github_env(variables: &BTreeMap<String, String>) -> String {
+    let mut contents = String::new();
+    for (key, value) in variables {
+        let value = unquote(value);
+        if value.contains('\n') {
+            let delimiter = format!("RSENV_EOF_{}", fnv1a_hex(value.as_bytes()));
+            contents.push_str(&format!("{}<<{}\n{}\n{}\n", key, delimiter, value, delimiter));
+        } else {
+            contents.push_str(&format!("{}={}\n", key, value));
+        }
+    }
+    contents
+}
+
+/// `::add-mask::` workflow commands for all variables with secret names, see `is_secret_name`.
+/// They must be printed to stdout of the step before the values are used.
+pub fn github_masks(variables: &BTreeMap<String, String>) -> Vec<String> {
+    variables.iter()
+        .filter(|(key, _)| is_secret_name(key))
+        .flat_map(|(_, value)| unquote(value).lines().map(str::to_string).collect::<Vec<_>>())
+        .filter(|line| !line.is_empty())
+        .map(|line| format!("::add-mask::{}", line))
+        .collect()
+}
+
+/// Formats variables as GitLab `dotenv` report artifact (`KEY=value` per line, values unquoted).
+///
+/// GitLab does not support multiline values in dotenv reports, they fail with
+/// `TreeError::UnsupportedValue`.
+pub fn format_gitlab_dotenv(variables: &BTreeMap<String, String>) -> TreeResult<String> {
+    let mut contents = String::new();
+    for (key, value) in variables {
+        let value = unquote(value);
+        if value.contains('\n') {
+            return Err(TreeError::UnsupportedValue {
+                key: key.clone(),
+                reason: "GitLab dotenv reports do not support multiline values".to_string(),
+            });
+        }
+        contents.push_str(&format!("{}={}\n", key, value));
+    }
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_secret_name() {
+        assert!(is_secret_name("API_KEY"));
+        assert!(is_secret_name("db_password"));
+        assert!(is_secret_name("GITHUB_TOKEN"));
+        assert!(!is_secret_name("MONKEY"));
+        assert!(!is_secret_name("KEYBOARD_LAYOUT"));
+    }
+}
@@ -1,9 +1,15 @@
 use clap::{Parser, Subcommand, ValueHint};
 use clap_complete::Shell;
+use crate::cli::exit;
+use crate::export::ExportFormat;
+use crate::graph::GraphFormat;
+use crate::hook::HookShell;
+use crate::{OnExisting, ValueMode, DEFAULT_MAX_DEPTH};
 
 #[derive(Parser, Debug, PartialEq)]
 #[command(author, version, about, long_about = None)] // Read from `Cargo.toml`
 #[command(arg_required_else_help = true)]
+#[command(after_help = exit::HELP)]
 /// A hierarchical environment variable manager for configuration files
 pub struct Cli {
     /// Name of the configuration to operate on (optional)
@@ -21,17 +27,82 @@ pub struct Cli {
     #[arg(long = "info")]
     pub info: bool,
 
+    /// Run as if rsenv was started in <DIR> (relative paths are resolved against it)
+    #[arg(short = 'C', value_name = "DIR", global = true, value_hint = ValueHint::DirPath)]
+    pub directory: Option<String>,
+
+    /// Show absolute paths instead of paths relative to the project directory
+    #[arg(long, global = true)]
+    pub absolute: bool,
+
+    /// Refuse to read or write files outside the project directory (symlinks are resolved)
+    #[arg(long, global = true)]
+    pub confined: bool,
+
+    /// Show values of secret-like variables in human-readable output (diffs, --explain)
+    #[arg(long, global = true)]
+    pub show_secrets: bool,
+
+    /// Additionally treat variables matching this glob as secret, e.g. '*_SALT' (repeatable)
+    #[arg(long, value_name = "GLOB", global = true)]
+    pub secret_pattern: Vec<String>,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
-#[derive(Subcommand, Debug, PartialEq)]
+#[derive(Subcommand, Debug, PartialEq, Clone)]
 pub enum Commands {
     /// Build and display the complete set of environment variables
     Build {
         /// Path to the last linked environment file (leaf node in hierarchy)
         #[arg(value_hint = ValueHint::FilePath)]
         source_path: String,
+        /// Prefix all variable names, e.g. MYAPP_
+        #[arg(long)]
+        prefix: Option<String>,
+        /// Rename references to prefixed variables inside values ($VAR, ${VAR})
+        #[arg(long, requires = "prefix")]
+        rewrite_refs: bool,
+        /// Maximum number of files from the leaf up to a root
+        #[arg(long, default_value_t = DEFAULT_MAX_DEPTH)]
+        max_depth: usize,
+        /// Normalization of values
+        #[arg(long, value_enum, default_value_t = ValueMode::Verbatim)]
+        values: ValueMode,
+        /// Fail if a file declares parents below its first variable
+        #[arg(long)]
+        strict_header: bool,
+        /// Read the files from this git revision instead of the working tree
+        #[arg(long, value_name = "REV")]
+        git_rev: Option<String>,
+        /// Override a variable without touching the files, can be repeated
+        #[arg(long = "set", value_name = "VAR=VALUE", value_parser = parse_assignment)]
+        overrides: Vec<(String, String)>,
+        /// Pipe the output into this shell command instead of printing it, e.g. 'kubectl apply -f -'
+        #[arg(long, value_name = "CMD")]
+        exec: Option<String>,
+        /// Keep running and build again whenever a file of the hierarchy changes
+        #[arg(long, conflicts_with_all = ["git_rev", "format", "verbose"])]
+        watch: bool,
+        /// Annotate every variable with the file it was taken from
+        #[arg(long, conflicts_with_all = ["prefix", "git_rev", "format", "watch"])]
+        explain: bool,
+        /// Report the number of ignored lines per file on stderr
+        #[arg(short, long)]
+        verbose: bool,
+        /// Output format, json cannot be combined with --prefix or --git-rev
+        #[arg(long, value_enum, default_value_t = BuildFormat::Text)]
+        format: BuildFormat,
+    },
+    /// Build every leaf below a directory in parallel, fails if any leaf fails
+    BuildAll {
+        /// Root directory containing environment files
+        #[arg(value_hint = ValueHint::DirPath)]
+        source_dir: String,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
     },
     /// Write environment variables to .envrc file (requires direnv)
     Envrc {
@@ -41,14 +112,38 @@ pub enum Commands {
         /// path to .envrc file
         #[arg(value_hint = ValueHint::FilePath)]
         envrc_path: Option<String>,
+        /// Overwrite the rsenv section even if it was edited manually
+        #[arg(long)]
+        force: bool,
+        /// Do not show the changed variables
+        #[arg(short, long)]
+        quiet: bool,
+        /// Write a stanza which sources a cached build and only rebuilds when the hierarchy changed
+        #[arg(long)]
+        cached: bool,
+    },
+    /// Merge duplicate rsenv sections of a .envrc file into one, keeping the newest variables
+    EnvrcRepair {
+        /// path to .envrc file
+        #[arg(value_hint = ValueHint::FilePath, default_value = ".envrc")]
+        envrc_path: String,
     },
-    /// List all files in the environment hierarchy
+    /// Print a fingerprint of the environment hierarchy, which changes whenever one of its files changes
+    Hash {
+        /// Path to the last linked environment file (leaf node in hierarchy)
+        #[arg(value_hint = ValueHint::FilePath)]
+        source_path: String,
+    },
+    /// List all files in the environment hierarchy, the leaf first and parents in declaration order
     Files {
         /// Path to the last linked environment file (leaf node in hierarchy)
         #[arg(value_hint = ValueHint::FilePath)]
         source_path: String,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
     },
-    /// Edit an environment file and all its parent files
+    /// Edit an environment file and all its parent files (vim: side by side, other editors: one after another)
     EditLeaf {
         /// Path to the last linked environment file (leaf node in hierarchy)
         #[arg(value_hint = ValueHint::FilePath)]
@@ -77,29 +172,309 @@ pub enum Commands {
         /// Environment files to link (root -> parent -> child)
         #[arg(value_hint = ValueHint::FilePath, num_args = 1..)]
         nodes: Vec<String>,
+        /// What to do with parents a file already has
+        #[arg(long, value_enum, default_value_t = OnExisting::Replace)]
+        on_existing: OnExisting,
     },
     /// Show all branches (linear representation)
     Branches {
         /// Root directory containing environment files
         #[arg(value_hint = ValueHint::DirPath)]
         source_dir: String,
+        /// Fail on unreadable files instead of skipping them with a warning
+        #[arg(long)]
+        strict: bool,
     },
     /// Show all trees (hierarchical representation)
     Tree {
         /// Root directory containing environment files
         #[arg(value_hint = ValueHint::DirPath)]
         source_dir: String,
+        /// Fail on unreadable files instead of skipping them with a warning
+        #[arg(long)]
+        strict: bool,
     },
     /// Edit all environment hierarchies side-by-side (requires vim)
     TreeEdit {
         /// Root directory containing environment files
         #[arg(value_hint = ValueHint::DirPath)]
         source_dir: String,
+        /// Fail on unreadable files instead of skipping them with a warning
+        #[arg(long)]
+        strict: bool,
     },
     /// List all leaf environment files
     Leaves {
         /// Root directory containing environment files
         #[arg(value_hint = ValueHint::DirPath)]
         source_dir: String,
+        /// Fail on unreadable files instead of skipping them with a warning
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Print whether the files below a directory form a DAG (a file with several parents)
+    IsDag {
+        /// Root directory containing environment files
+        #[arg(value_hint = ValueHint::DirPath)]
+        source_dir: String,
+        /// Print nothing, answer with the exit code only
+        #[arg(long)]
+        check: bool,
+        /// Fail on unreadable files instead of skipping them with a warning
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Check a hierarchy for issues like ignored lines, duplicate keys or undefined references
+    Validate {
+        /// Path to the last linked environment file (leaf node in hierarchy)
+        #[arg(value_hint = ValueHint::FilePath)]
+        source_path: String,
+        /// Also check the built variables against a TOML schema of required keys, types and patterns
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        schema: Option<String>,
+        /// Print nothing, answer with the exit code only
+        #[arg(long)]
+        check: bool,
+        /// Maximum number of files from the leaf up to a root
+        #[arg(long, default_value_t = DEFAULT_MAX_DEPTH)]
+        max_depth: usize,
+        /// Fail if a file declares parents below its first variable
+        #[arg(long)]
+        strict_header: bool,
+    },
+    /// Print the parent relations of all env files of a directory as Graphviz DOT or Mermaid graph
+    Graph {
+        /// Root directory containing environment files
+        #[arg(value_hint = ValueHint::DirPath)]
+        source_dir: String,
+        /// Graph syntax, files with several parents are highlighted
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+        /// Fail on unreadable files instead of skipping them with a warning
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Check all env files of a directory for broken links, cycles, orphans, redundant keys and the issues of validate
+    Lint {
+        /// Root directory containing environment files
+        #[arg(value_hint = ValueHint::DirPath)]
+        source_dir: String,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+        /// Fail on unreadable files instead of skipping them with a warning
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Move (or copy) a variable from an env file into one of its parents or children
+    Promote {
+        /// Name of the variable
+        key: String,
+        /// Env file currently setting the variable
+        #[arg(value_hint = ValueHint::FilePath)]
+        from: String,
+        /// Ancestor or descendant of <FROM> to move the variable to
+        #[arg(value_hint = ValueHint::FilePath)]
+        to: String,
+        /// Keep the variable in <FROM>
+        #[arg(long)]
+        copy: bool,
+    },
+    /// Move `# rsenv:` parent declarations to the top of the files
+    Fmt {
+        /// Env files to format
+        #[arg(required = true, value_hint = ValueHint::FilePath)]
+        files: Vec<String>,
+        /// Change nothing, fail if a file is not formatted
+        #[arg(long)]
+        check: bool,
+    },
+    /// Show which variables of a hierarchy a running process is missing or has different values for
+    CheckProc {
+        /// Process id
+        pid: u32,
+        /// Path to the last linked environment file (leaf node in hierarchy)
+        #[arg(value_hint = ValueHint::FilePath)]
+        source_path: String,
+        /// Print nothing, answer with the exit code only
+        #[arg(long)]
+        check: bool,
+    },
+    /// Write variables of the current environment into an env file
+    Capture {
+        /// Env file to write to, created if missing
+        #[arg(value_hint = ValueHint::FilePath)]
+        source_path: String,
+        /// Names of the variables to capture, `*` matches any characters (e.g. 'AWS_*')
+        #[arg(long = "filter", required = true)]
+        filters: Vec<String>,
+    },
+    /// Show added, removed and changed variables between two hierarchies
+    Diff {
+        /// Leaf of the first hierarchy
+        #[arg(value_hint = ValueHint::FilePath)]
+        old: String,
+        /// Leaf of the second hierarchy
+        #[arg(value_hint = ValueHint::FilePath)]
+        new: String,
+        /// Print nothing, answer with the exit code only
+        #[arg(long)]
+        check: bool,
+    },
+    /// Inline small parents and externalize large values of a leaf for faster evaluation
+    Optimize {
+        /// Path to the last linked environment file (leaf node in hierarchy)
+        #[arg(value_hint = ValueHint::FilePath)]
+        source_path: String,
+        /// Inline parents with at most this many variables
+        #[arg(long, default_value_t = 3)]
+        max_inline_vars: usize,
+        /// Move values longer than this many bytes into separate files
+        #[arg(long, default_value_t = 1024)]
+        max_value_len: usize,
+    },
+    /// Run a command with the built environment, locally or in a container (rsenv exec leaf.env -- cmd)
+    #[command(group(clap::ArgGroup::new("container").args(["docker", "compose_service"])))]
+    Exec {
+        /// Path to the last linked environment file (leaf node in hierarchy)
+        #[arg(value_hint = ValueHint::FilePath)]
+        source_path: String,
+        /// Image for docker run
+        #[arg(long)]
+        docker: Option<String>,
+        /// Service for docker compose run
+        #[arg(long)]
+        compose_service: Option<String>,
+        /// Compose file containing the service
+        #[arg(long, value_hint = ValueHint::FilePath, default_value = "compose.yaml")]
+        compose_file: String,
+        /// Start from an empty environment instead of the current one (like env -i), local only
+        #[arg(long, conflicts_with = "container")]
+        clean: bool,
+        /// Command and arguments to run
+        #[arg(last = true, required = true)]
+        args: Vec<String>,
+    },
+    /// Print a shell hook loading the rsenv section of .envrc when entering a directory (alternative to direnv)
+    Hook {
+        /// Shell to print the hook for, e.g. `eval "$(rsenv hook bash)"`
+        #[arg(value_enum)]
+        shell: HookShell,
+    },
+    /// Approve the rsenv section of .envrc for the shell hook, needed again after every change
+    Allow {
+        /// Directory containing the .envrc
+        #[arg(value_hint = ValueHint::DirPath, default_value = ".")]
+        source_dir: String,
+    },
+    /// Print the rsenv section of .envrc if it was approved with `rsenv allow`, used by the hook
+    #[command(hide = true)]
+    HookSection {
+        /// Directory containing the .envrc
+        #[arg(value_hint = ValueHint::DirPath, default_value = ".")]
+        source_dir: String,
+    },
+    /// Show which optional external programs are available and which features they enable
+    Capabilities,
+    /// Convert direnv or dotenv-vault layouts into rsenv env files
+    Migrate {
+        #[command(subcommand)]
+        source: MigrateSource,
+    },
+    /// Export the built environment for CI providers
+    Export {
+        #[command(subcommand)]
+        target: ExportTarget,
+    },
+}
+
+fn parse_assignment(assignment: &str) -> Result<(String, String), String> {
+    match assignment.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("expected VAR=VALUE, got '{}'", assignment)),
+    }
+}
+
+#[derive(Subcommand, Debug, PartialEq, Clone)]
+pub enum MigrateSource {
+    /// Convert a direnv .envrc into an env file
+    Direnv {
+        /// Path to the .envrc file
+        #[arg(value_hint = ValueHint::FilePath, default_value = ".envrc")]
+        envrc_path: String,
+        /// Env file to create
+        #[arg(short, long, value_hint = ValueHint::FilePath, default_value = "envrc.env")]
+        output: String,
+    },
+    /// Convert .env and .env.<stage> files of a dotenv-vault project into env files
+    DotenvVault {
+        /// Directory containing the .env files
+        #[arg(value_hint = ValueHint::DirPath, default_value = ".")]
+        source_dir: String,
+        /// Directory for the created env files
+        #[arg(short, long, value_hint = ValueHint::DirPath, default_value = "environments")]
+        output_dir: String,
     },
-}
\ No newline at end of file
+}
+
+#[derive(Subcommand, Debug, PartialEq, Clone)]
+pub enum ExportTarget {
+    /// Append variables to $GITHUB_ENV and print masks for secret-looking names
+    GithubEnv {
+        /// Path to the last linked environment file (leaf node in hierarchy)
+        #[arg(value_hint = ValueHint::FilePath)]
+        source_path: String,
+        /// Append to $GITHUB_OUTPUT instead, for step outputs
+        #[arg(long)]
+        output: bool,
+        /// File to append to instead of $GITHUB_ENV or $GITHUB_OUTPUT
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        file: Option<String>,
+    },
+    /// Write variables as GitLab dotenv report artifact
+    GitlabDotenv {
+        /// Path to the last linked environment file (leaf node in hierarchy)
+        #[arg(value_hint = ValueHint::FilePath)]
+        source_path: String,
+        /// File to write, stdout if omitted
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        file: Option<String>,
+    },
+}
+
+#[derive(clap::ValueEnum, Debug, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Output formats of `build`: shell syntaxes in addition to `OutputFormat`.
+#[derive(clap::ValueEnum, Debug, PartialEq, Clone, Copy)]
+pub enum BuildFormat {
+    /// `export KEY=value` lines for POSIX shells
+    Text,
+    /// Values with the files they were taken from
+    Json,
+    /// `KEY=value` lines
+    Dotenv,
+    /// `set -gx` commands for fish
+    Fish,
+    /// `$env:` assignments for PowerShell
+    Powershell,
+    /// Env file for `docker run --env-file`
+    Docker,
+}
+
+impl BuildFormat {
+    /// Syntax of the variables, `None` for json.
+    pub fn export_format(self) -> Option<ExportFormat> {
+        match self {
+            BuildFormat::Text => Some(ExportFormat::Posix),
+            BuildFormat::Json => None,
+            BuildFormat::Dotenv => Some(ExportFormat::Dotenv),
+            BuildFormat::Fish => Some(ExportFormat::Fish),
+            BuildFormat::Powershell => Some(ExportFormat::Powershell),
+            BuildFormat::Docker => Some(ExportFormat::Docker),
+        }
+    }
+}
@@ -1,12 +1,44 @@
-use crate::cli::args::{Cli, Commands};
+use crate::cli::args::{Cli, Commands, ExportTarget, MigrateSource, OutputFormat};
+use crate::cli::exit;
+use crate::cli::output::{PathDisplay, SecretMask};
+use crate::diagnostics::build_env_with_diagnostics_and_options;
 use crate::edit::{
     create_branches, create_vimscript, open_files_in_editor, select_file_with_suffix,
 };
-use crate::envrc::update_dot_envrc;
+use crate::diff::{diff_env, EnvChange};
+use crate::envrc::{repair_dot_envrc, update_dot_envrc_with_options, EnvrcOptions};
+use crate::batch::{build_all, builds_to_json};
 use crate::builder::TreeBuilder;
-use crate::{build_env_vars, get_files, is_dag, link_all, print_files};
+use crate::container::{run_in_container, Container};
+use crate::cache::{cached_envrc_stanza, hierarchy_hash};
+use crate::capture::capture_env;
+use crate::git::build_env_at_rev;
+use crate::capabilities::{capabilities, require_program};
+use crate::ci::{format_github_env, format_gitlab_dotenv, github_masks};
+use crate::migrate::{migrate_direnv, migrate_dotenv_vault};
+use crate::optimize::{optimize, OptimizeOptions};
+use crate::watch::watch_env;
+use crate::format::{format_env_file, needs_formatting};
+use crate::lint::{findings_to_json, lint_directory};
+use crate::graph::{dependency_graph, render_graph, GraphFormat};
+use crate::hook::{allow_section, allowed_section, hook_script};
+use crate::promote::promote_variable;
+use crate::schema::Schema;
+use crate::export::{format_variables, ExportFormat};
+use crate::errors::TreeResult;
+use crate::procenv::{check_process_env, run_with_env};
+use crate::arena::TreeArena;
+use crate::util::file::WalkPolicy;
+use crate::util::path::{ensure_file_exists, ensure_within, PathExt};
+use crate::{
+    build_env_with_options, build_env_with_overrides, build_env_with_sources, format_env_vars, sourced_to_json,
+    get_files_with_options, is_dag_with_policy, link_all_with_policy, prefix_env_vars, BuildOptions, OnExisting,
+    SourcedValue, ValueSource,
+};
 use anyhow::{anyhow, Result};
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::io::Write;
 use crossterm::style::Stylize;
@@ -14,70 +46,545 @@ use tracing::{debug, instrument};
 use tempfile::NamedTempFile;
 
 pub fn execute_command(cli: &Cli) -> Result<()> {
-    match &cli.command {
-        Some(Commands::Build { source_path }) => _build(source_path),
+    let command = match (&cli.command, &cli.directory) {
+        (Some(command), Some(directory)) => Some(resolve_paths(command, Path::new(directory))?),
+        (command, _) => command.clone(),
+    };
+    // commands without explicit .envrc argument write to the one in the -C directory
+    let envrc_path = cli.directory.as_ref()
+        .map(|directory| Path::new(directory).join(".envrc").to_string_lossy().into_owned());
+    let base = match &cli.directory {
+        Some(directory) => PathBuf::from(directory),
+        None => env::current_dir()?,
+    };
+    let paths = PathDisplay::new(&base, cli.absolute);
+    let secrets = SecretMask::new(&cli.secret_pattern, cli.show_secrets);
+    let options = BuildOptions {
+        confine_to: cli.confined.then(|| base.clone()),
+        ..Default::default()
+    };
+    match &command {
+        Some(Commands::Build {
+            source_path,
+            prefix,
+            rewrite_refs,
+            max_depth,
+            values,
+            strict_header,
+            git_rev,
+            overrides,
+            exec,
+            watch,
+            explain,
+            verbose,
+            format,
+        }) => {
+            let options = BuildOptions {
+                max_depth: *max_depth,
+                values: *values,
+                strict_header: *strict_header,
+                ..options
+            };
+            if *watch {
+                return _build_watch(source_path, prefix.as_deref(), *rewrite_refs, &options, overrides, exec.as_deref());
+            }
+            let output = match format.export_format() {
+                None if prefix.is_some() || git_rev.is_some() => {
+                    eprintln!("{}", "--format json cannot be combined with --prefix or --git-rev".red());
+                    process::exit(1);
+                }
+                None => _build_json(source_path, &options, overrides, &paths)?,
+                Some(_) if *explain => _build_explained(source_path, &options, overrides, &paths, &secrets)?,
+                Some(export_format) => _build(
+                    source_path,
+                    prefix.as_deref(),
+                    *rewrite_refs,
+                    &options,
+                    git_rev.as_deref(),
+                    overrides,
+                    export_format,
+                )?,
+            };
+            _emit(&output, exec.as_deref())?;
+            if *verbose && git_rev.is_none() {
+                _report_ignored_lines(source_path, &paths, &options);
+            }
+            Ok(())
+        }
+        Some(Commands::BuildAll { source_dir, format }) => _build_all(source_dir, *format, &paths, &options),
         Some(Commands::Envrc {
             source_path,
             envrc_path,
-        }) => _envrc(source_path, envrc_path.as_deref()),
-        Some(Commands::Files { source_path }) => _files(source_path),
-        Some(Commands::EditLeaf { source_path }) => _edit_leaf(source_path),
-        Some(Commands::Edit { source_dir }) => _edit(source_dir),
-        Some(Commands::SelectLeaf { source_path }) => _select_leaf(source_path),
-        Some(Commands::Select { source_dir }) => _select(source_dir),
-        Some(Commands::Link { nodes }) => _link(nodes),
-        Some(Commands::Branches { source_dir }) => _branches(source_dir),
-        Some(Commands::Tree { source_dir }) => _tree(source_dir),
-        Some(Commands::TreeEdit { source_dir }) => _tree_edit(source_dir),
-        Some(Commands::Leaves { source_dir }) => _leaves(source_dir),
+            force,
+            quiet,
+            cached,
+        }) => _envrc(source_path, envrc_path.as_deref(), *force, *quiet, *cached, &options, &secrets),
+        Some(Commands::Hash { source_path }) => _hash(source_path, &options),
+        Some(Commands::EnvrcRepair { envrc_path }) => _envrc_repair(envrc_path, &options),
+        Some(Commands::Files { source_path, format }) => _files(source_path, *format, &paths, &options),
+        Some(Commands::EditLeaf { source_path }) => _edit_leaf(source_path, &options),
+        Some(Commands::Edit { source_dir }) => _edit(source_dir, &paths, &options, &secrets),
+        Some(Commands::SelectLeaf { source_path }) => _select_leaf(source_path, envrc_path.as_deref(), &options, &secrets),
+        Some(Commands::Select { source_dir }) => _select(source_dir, envrc_path.as_deref(), &paths, &options, &secrets),
+        Some(Commands::Link { nodes, on_existing }) => _link(nodes, *on_existing, &options),
+        Some(Commands::Branches { source_dir, strict }) => _branches(source_dir, *strict, &paths, &options),
+        Some(Commands::Tree { source_dir, strict }) => _tree(source_dir, *strict, &paths, &options),
+        Some(Commands::TreeEdit { source_dir, strict }) => _tree_edit(source_dir, *strict, &paths, &options),
+        Some(Commands::Leaves { source_dir, strict }) => _leaves(source_dir, *strict, &paths, &options),
+        Some(Commands::IsDag { source_dir, check, strict }) => _is_dag(source_dir, *check, *strict, &paths, &options),
+        Some(Commands::Validate { source_path, schema, check, max_depth, strict_header }) => {
+            let options = BuildOptions { max_depth: *max_depth, strict_header: *strict_header, ..options };
+            _validate(source_path, schema.as_deref(), *check, &paths, &options, &secrets)
+        }
+        Some(Commands::Graph { source_dir, format, strict }) => _graph(source_dir, *format, *strict, &paths, &options),
+        Some(Commands::Lint { source_dir, format, strict }) => _lint(source_dir, *format, *strict, &paths, &options),
+        Some(Commands::Promote { key, from, to, copy }) => _promote(key, from, to, *copy, &paths, &options),
+        Some(Commands::Fmt { files, check }) => _fmt(files, *check, &paths, &options),
+        Some(Commands::CheckProc { pid, source_path, check }) => _check_proc(*pid, source_path, *check, &options, &secrets),
+        Some(Commands::Capture { source_path, filters }) => _capture(source_path, filters, &options),
+        Some(Commands::Diff { old, new, check }) => _diff(old, new, *check, &options, &secrets),
+        Some(Commands::Optimize {
+            source_path,
+            max_inline_vars,
+            max_value_len,
+        }) => _optimize(source_path, *max_inline_vars, *max_value_len, &paths, &options),
+        Some(Commands::Migrate { source }) => _migrate(source, &paths, &options),
+        Some(Commands::Hook { shell }) => {
+            print!("{}", hook_script(*shell));
+            Ok(())
+        }
+        Some(Commands::Allow { source_dir }) => _allow(source_dir, &paths),
+        Some(Commands::HookSection { source_dir }) => _hook_section(source_dir),
+        Some(Commands::Capabilities) => _capabilities(&paths),
+        Some(Commands::Export { target }) => _export(target, &options),
+        Some(Commands::Exec {
+            source_path,
+            docker,
+            compose_service,
+            compose_file,
+            clean,
+            args,
+        }) => {
+            let container = match (docker, compose_service) {
+                (Some(image), _) => Some(Container::Docker { image: image.clone() }),
+                (None, Some(service)) => Some(Container::ComposeService {
+                    service: service.clone(),
+                    compose_file: compose_file.clone(),
+                }),
+                (None, None) => None,
+            };
+            _exec(source_path, container.as_ref(), *clean, args, &options)
+        }
         None => Ok(())
     }
 }
 
+/// Resolves all relative path arguments of `command` against `base`, so commands behave as
+/// if started in `base` without changing the process working directory.
+fn resolve_paths(command: &Commands, base: &Path) -> Result<Commands> {
+    if !base.is_dir() {
+        return Err(anyhow!("Directory does not exist: {:?}", base));
+    }
+    let resolve = |path: &String| base.join(path).to_string_lossy().into_owned();
+
+    let mut command = command.clone();
+    match &mut command {
+        Commands::Envrc { source_path, envrc_path, .. } => {
+            *source_path = resolve(source_path);
+            *envrc_path = Some(resolve(envrc_path.get_or_insert_with(|| ".envrc".to_string())));
+        }
+        Commands::EnvrcRepair { envrc_path } => *envrc_path = resolve(envrc_path),
+        Commands::Build { source_path, .. }
+        | Commands::Hash { source_path }
+        | Commands::Exec { source_path, .. }
+        | Commands::Files { source_path, .. }
+        | Commands::EditLeaf { source_path }
+        | Commands::SelectLeaf { source_path }
+        | Commands::CheckProc { source_path, .. }
+        | Commands::Capture { source_path, .. }
+        | Commands::Optimize { source_path, .. } => *source_path = resolve(source_path),
+        Commands::BuildAll { source_dir, .. }
+        | Commands::Edit { source_dir }
+        | Commands::Select { source_dir }
+        | Commands::Branches { source_dir, .. }
+        | Commands::Tree { source_dir, .. }
+        | Commands::TreeEdit { source_dir, .. }
+        | Commands::Leaves { source_dir, .. }
+        | Commands::IsDag { source_dir, .. }
+        | Commands::Graph { source_dir, .. }
+        | Commands::Lint { source_dir, .. }
+        | Commands::Allow { source_dir }
+        | Commands::HookSection { source_dir } => *source_dir = resolve(source_dir),
+        Commands::Validate { source_path, schema, .. } => {
+            *source_path = resolve(source_path);
+            *schema = schema.as_ref().map(resolve);
+        }
+        Commands::Promote { from, to, .. } => {
+            *from = resolve(from);
+            *to = resolve(to);
+        }
+        Commands::Fmt { files, .. } => files.iter_mut().for_each(|file| *file = resolve(file)),
+        Commands::Capabilities | Commands::Hook { .. } => {}
+        Commands::Diff { old, new, .. } => {
+            *old = resolve(old);
+            *new = resolve(new);
+        }
+        Commands::Link { nodes, .. } => {
+            for node in nodes.iter_mut() {
+                *node = resolve(node);
+            }
+        }
+        Commands::Export { target: ExportTarget::GithubEnv { source_path, file, .. } }
+        | Commands::Export { target: ExportTarget::GitlabDotenv { source_path, file } } => {
+            *source_path = resolve(source_path);
+            if let Some(file) = file {
+                *file = resolve(file);
+            }
+        }
+        Commands::Migrate { source: MigrateSource::Direnv { envrc_path, output } } => {
+            *envrc_path = resolve(envrc_path);
+            *output = resolve(output);
+        }
+        Commands::Migrate { source: MigrateSource::DotenvVault { source_dir, output_dir } } => {
+            *source_dir = resolve(source_dir);
+            *output_dir = resolve(output_dir);
+        }
+    }
+    Ok(command)
+}
+
 #[instrument]
-fn _build(source_path: &str) -> Result<()> {
-    debug!("source_path: {:?}", source_path);
-    let vars = build_env_vars(Path::new(source_path)).unwrap_or_else(|e| {
+fn _build(
+    source_path: &str,
+    prefix: Option<&str>,
+    rewrite_refs: bool,
+    options: &BuildOptions,
+    git_rev: Option<&str>,
+    overrides: &[(String, String)],
+    format: ExportFormat,
+) -> Result<String> {
+    debug!("source_path: {:?}, prefix: {:?}, git_rev: {:?}", source_path, prefix, git_rev);
+    let built = match git_rev {
+        // a revision is not the working tree `build_env_with_overrides` reads, so the
+        // overrides are layered here
+        Some(rev) => build_env_at_rev(Path::new(source_path), rev, options)
+            .map(|(variables, _, _)| variables.into_iter().chain(overrides.iter().cloned()).collect()),
+        None => ensure_file_exists(Path::new(source_path))
+            .and_then(|_| build_env_with_overrides(Path::new(source_path), options, &overrides.iter().cloned().collect()))
+            .map(|sourced| sourced.into_iter().map(|(key, sourced)| (key, sourced.value)).collect()),
+    };
+    let variables = built.unwrap_or_else(|e| {
         eprintln!("{}", format!("Cannot build environment: {}", e).red());
         process::exit(1);
     });
-    println!("{}", vars);
+    Ok(render_variables(variables, prefix, rewrite_refs, overrides, format).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot format environment: {}", e).red());
+        process::exit(1);
+    }))
+}
+
+/// Formats built variables in `format` after applying `prefix`, marking the names of
+/// `overrides`, which are already part of `variables`.
+fn render_variables(
+    variables: BTreeMap<String, String>,
+    prefix: Option<&str>,
+    rewrite_refs: bool,
+    overrides: &[(String, String)],
+    format: ExportFormat,
+) -> TreeResult<String> {
+    let variables = match prefix {
+        Some(prefix) => prefix_env_vars(variables, prefix, rewrite_refs),
+        None => variables,
+    };
+    let mut output = String::new();
+    if !overrides.is_empty() {
+        let names: Vec<&str> = overrides.iter().map(|(name, _)| name.as_str()).collect();
+        output.push_str(&format!("# overridden with --set: {}\n", names.join(" ")));
+    }
+    output.push_str(&format_variables(&variables, format)?);
+    Ok(output)
+}
+
+/// Prints a one-line summary of ignored lines per file to stderr.
+fn _report_ignored_lines(source_path: &str, paths: &PathDisplay, options: &BuildOptions) {
+    match build_env_with_diagnostics_and_options(Path::new(source_path), options) {
+        Ok(result) => {
+            let counts = result.ignored_lines();
+            let summary = if counts.is_empty() {
+                "none".to_string()
+            } else {
+                counts.iter()
+                    .map(|(file, count)| format!("{} ({})", paths.show(file), count))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            eprintln!("{}", format!("Ignored lines: {}", summary).yellow());
+        }
+        Err(e) => eprintln!("{}", format!("Cannot collect diagnostics: {}", e).red()),
+    }
+}
+
+/// Prints the built variables as JSON with the file each value was taken from, `null` for
+/// values from `--set`.
+#[instrument]
+fn _build_json(
+    source_path: &str,
+    options: &BuildOptions,
+    overrides: &[(String, String)],
+    paths: &PathDisplay,
+) -> Result<String> {
+    let sourced = _build_sourced(source_path, options, overrides);
+    Ok(serde_json::to_string_pretty(&sourced_to_json(&sourced, |file| paths.show(file)))?)
+}
+
+/// Builds the variables of `source_path` with their sources and `overrides` layered on top,
+/// exits if the hierarchy cannot be built.
+fn _build_sourced(
+    source_path: &str,
+    options: &BuildOptions,
+    overrides: &[(String, String)],
+) -> BTreeMap<String, SourcedValue> {
+    ensure_file_exists(Path::new(source_path))
+        .and_then(|_| build_env_with_overrides(Path::new(source_path), options, &overrides.iter().cloned().collect()))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", format!("Cannot build environment: {}", e).red());
+            process::exit(1);
+        })
+}
+
+/// Formats built variables as `export` lines, each followed by the file it was taken from.
+/// Secret values are masked, the output is meant for reading.
+fn _build_explained(
+    source_path: &str,
+    options: &BuildOptions,
+    overrides: &[(String, String)],
+    paths: &PathDisplay,
+    secrets: &SecretMask,
+) -> Result<String> {
+    Ok(_build_sourced(source_path, options, overrides).iter()
+        .map(|(key, sourced)| {
+            let source = match &sourced.source {
+                ValueSource::File(file) => paths.show(file),
+                ValueSource::Override => "--set".to_string(),
+            };
+            format!("export {}={}  # from {}\n", key, secrets.value(key, &sourced.value), source)
+        })
+        .collect())
+}
+
+/// Prints `output`, or pipes it into `sh -c <exec>` and exits with the command's exit code if
+/// it fails.
+fn _emit(output: &str, exec: Option<&str>) -> Result<()> {
+    let Some(command) = exec else {
+        println!("{}", output);
+        return Ok(());
+    };
+    let status = pipe_to_command(output, command).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot run {}: {}", command, e).red());
+        process::exit(1);
+    });
+    if !status.success() {
+        eprintln!("{}", format!("Command failed with {}: {}", status, command).red());
+        process::exit(status.code().unwrap_or(1));
+    }
     Ok(())
 }
 
+fn pipe_to_command(output: &str, command: &str) -> Result<process::ExitStatus> {
+    debug!("Piping output into: {}", command);
+    let mut child = process::Command::new("sh")
+        .args(["-c", command])
+        .stdin(process::Stdio::piped())
+        .spawn()?;
+    // a command which exits without reading its input is reported by its exit code
+    let _ = child.stdin.take().expect("stdin is piped").write_all(output.as_bytes());
+    Ok(child.wait()?)
+}
+
+/// Prints or pipes the environment of `source_path` again whenever a file of its hierarchy
+/// changes. Failures are reported and watching continues, so they can be fixed in place.
 #[instrument]
-fn _envrc(source_path: &str, envrc_path: Option<&str>) -> Result<()> {
+fn _build_watch(
+    source_path: &str,
+    prefix: Option<&str>,
+    rewrite_refs: bool,
+    options: &BuildOptions,
+    overrides: &[(String, String)],
+    exec: Option<&str>,
+) -> Result<()> {
+    ensure_file_exists(Path::new(source_path)).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot build environment: {}", e).red());
+        process::exit(1);
+    });
+    watch_env(Path::new(source_path), options, |result| {
+        match result {
+            Ok(variables) => {
+                // the watched builds have no sources, so the overrides are layered here
+                let mut variables = variables.clone();
+                variables.extend(overrides.iter().cloned());
+                let rendered = render_variables(variables, prefix, rewrite_refs, overrides, ExportFormat::Posix);
+                let output = match rendered {
+                    Ok(output) => output,
+                    Err(e) => {
+                        eprintln!("{}", format!("Cannot format environment: {}", e).red());
+                        return true;
+                    }
+                };
+                match exec {
+                    None => println!("{}", output),
+                    Some(command) => match pipe_to_command(&output, command) {
+                        Ok(status) if status.success() => {}
+                        Ok(status) => eprintln!("{}", format!("Command failed with {}: {}", status, command).red()),
+                        Err(e) => eprintln!("{}", format!("Cannot run {}: {}", command, e).red()),
+                    },
+                }
+            }
+            Err(e) => eprintln!("{}", format!("Cannot build environment: {}", e).red()),
+        }
+        true
+    })?;
+    Ok(())
+}
+
+#[instrument(skip(options))]
+fn _build_all(source_dir: &str, format: OutputFormat, paths: &PathDisplay, options: &BuildOptions) -> Result<()> {
+    let (builds, issues) = build_all(Path::new(source_dir), options).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot build leaves: {}", e).red());
+        process::exit(1);
+    });
+    for issue in &issues {
+        eprintln!("{}", format!("Warning: skipped {}: {}", paths.show(&issue.path), issue.reason).yellow());
+    }
+    match format {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&builds_to_json(&builds, |leaf| paths.show(leaf)))?);
+        }
+        OutputFormat::Text => {
+            for build in &builds {
+                println!("# {}", paths.show(&build.leaf));
+                match &build.result {
+                    Ok(variables) => println!("{}", format_env_vars(variables)),
+                    Err(e) => eprintln!("{}", format!("Cannot build {}: {}", paths.show(&build.leaf), e).red()),
+                }
+            }
+        }
+    }
+    if builds.iter().any(|b| b.result.is_err()) {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+#[instrument]
+fn _envrc(
+    source_path: &str,
+    envrc_path: Option<&str>,
+    force: bool,
+    quiet: bool,
+    cached: bool,
+    options: &BuildOptions,
+    secrets: &SecretMask,
+) -> Result<()> {
     let envrc_path = envrc_path.unwrap_or(".envrc");
     debug!(
         "source_path: {:?}, envrc_path: {:?}",
         source_path,
         envrc_path
     );
-    let vars = build_env_vars(Path::new(source_path)).unwrap_or_else(|e| {
-        eprintln!("{}", format!("Cannot build environment: {}", e).red());
+    let vars = ensure_file_exists(Path::new(source_path))
+        .and_then(|_| build_env_with_options(Path::new(source_path), options))
+        .map(|(variables, _, _)| format_env_vars(&variables))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", format!("Cannot build environment: {}", e).red());
+            process::exit(1);
+        });
+    if let Some(root) = &options.confine_to {
+        ensure_within(Path::new(envrc_path), root, None).unwrap_or_else(|e| {
+            eprintln!("{}", format!("Cannot update {}: {}", envrc_path, e).red());
+            process::exit(1);
+        });
+    }
+    let data = if cached { cached_envrc_stanza(Path::new(source_path))? } else { vars };
+    let changes = update_dot_envrc_with_options(Path::new(envrc_path), data.as_str(), &EnvrcOptions { force })?;
+    if !quiet {
+        // the cached stanza contains no exports to compare
+        if !cached {
+            print_changes(&changes, secrets);
+        }
+        if require_program("direnv").is_err() {
+            eprintln!("{}", "direnv not found, .envrc will not be loaded automatically".yellow());
+        }
+    }
+    Ok(())
+}
+
+#[instrument(skip(options))]
+fn _hash(source_path: &str, options: &BuildOptions) -> Result<()> {
+    let hash = hierarchy_hash(Path::new(source_path), options).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot hash environment: {}", e).red());
+        process::exit(1);
+    });
+    println!("{}", hash);
+    Ok(())
+}
+
+#[instrument(skip(options))]
+fn _envrc_repair(envrc_path: &str, options: &BuildOptions) -> Result<()> {
+    let path = Path::new(envrc_path);
+    let confined = match &options.confine_to {
+        Some(root) => ensure_within(path, root, None),
+        None => Ok(()),
+    };
+    let removed = confined.and_then(|_| repair_dot_envrc(path)).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot repair {}: {}", envrc_path, e).red());
         process::exit(1);
     });
-    update_dot_envrc(Path::new(envrc_path), vars.as_str())?;
+    match removed {
+        0 => println!("No duplicate rsenv sections found"),
+        n => println!("Merged {} rsenv sections into one", n + 1),
+    }
     Ok(())
 }
 
+fn print_changes(changes: &[EnvChange], secrets: &SecretMask) {
+    for change in changes {
+        let line = secrets.change(change).to_string();
+        match change {
+            EnvChange::Added { .. } => println!("{}", line.green()),
+            EnvChange::Removed { .. } => println!("{}", line.red()),
+            EnvChange::Changed { .. } => println!("{}", line.yellow()),
+        }
+    }
+}
+
 #[instrument]
-fn _files(source_path: &str) -> Result<()> {
+fn _files(source_path: &str, format: OutputFormat, paths: &PathDisplay, options: &BuildOptions) -> Result<()> {
     debug!("source_path: {:?}", source_path);
-    print_files(Path::new(source_path)).unwrap_or_else(|e| {
+    let files = get_files_with_options(Path::new(source_path), options).unwrap_or_else(|e| {
         eprintln!("{}", format!("Cannot print environment: {}", e).red());
         process::exit(1);
     });
+    match format {
+        OutputFormat::Json => {
+            let files: Vec<String> = files.iter().map(|file| paths.show(file)).collect();
+            println!("{}", serde_json::to_string_pretty(&files)?);
+        }
+        OutputFormat::Text => {
+            for file in files {
+                println!("{}", paths.show(&file));
+            }
+        }
+    }
     Ok(())
 }
 
 #[instrument]
-fn _edit_leaf(source_path: &str) -> Result<()> {
+fn _edit_leaf(source_path: &str, options: &BuildOptions) -> Result<()> {
     let path = Path::new(source_path);
     if !path.exists() {
         return Err(anyhow!("File does not exist: {:?}", source_path));
     }
-    let files = get_files(path).unwrap_or_else(|e| {
+    let files = get_files_with_options(path, options).unwrap_or_else(|e| {
         eprintln!("{}", format!("Cannot get files: {}", e).red());
         process::exit(1);
     });
@@ -89,18 +596,18 @@ fn _edit_leaf(source_path: &str) -> Result<()> {
 }
 
 #[instrument]
-fn _edit(source_dir: &str) -> Result<()> {
+fn _edit(source_dir: &str, paths: &PathDisplay, options: &BuildOptions, secrets: &SecretMask) -> Result<()> {
     let path = Path::new(source_dir);
     if !path.exists() {
         eprintln!("Error: Directory does not exist: {:?}", source_dir);
         process::exit(1);
     }
-    let selected_file = select_file_with_suffix(path, ".env").unwrap_or_else(|_| {
+    let selected_file = select_file_with_suffix(path, ".env", options, secrets).unwrap_or_else(|_| {
         eprintln!("{}", "No .env files found".to_string().red());
         process::exit(1);
     });
-    println!("Selected: {}", selected_file.display());
-    let files = get_files(&selected_file).unwrap_or_else(|e| {
+    println!("Selected: {}", paths.show(&selected_file));
+    let files = get_files_with_options(&selected_file, options).unwrap_or_else(|e| {
         eprintln!("{}", format!("Cannot get files: {}", e).red());
         process::exit(1);
     });
@@ -112,62 +619,269 @@ fn _edit(source_dir: &str) -> Result<()> {
 }
 
 #[instrument]
-fn _select_leaf(source_path: &str) -> Result<()> {
+fn _select_leaf(source_path: &str, envrc_path: Option<&str>, options: &BuildOptions, secrets: &SecretMask) -> Result<()> {
     let path = Path::new(source_path);
     if !path.exists() {
         eprintln!("Error: File does not exist: {:?}", source_path);
         process::exit(1);
     }
-    _envrc(source_path, None)
+    _envrc(source_path, envrc_path, false, false, false, options, secrets)
 }
 
 #[instrument]
-fn _select(source_dir: &str) -> Result<()> {
+fn _select(
+    source_dir: &str,
+    envrc_path: Option<&str>,
+    paths: &PathDisplay,
+    options: &BuildOptions,
+    secrets: &SecretMask,
+) -> Result<()> {
     let path = Path::new(source_dir);
     if !path.exists() {
         eprintln!("Error: Directory does not exist: {:?}", source_dir);
         process::exit(1);
     }
-    let selected_file = select_file_with_suffix(path, ".env").unwrap_or_else(|_| {
+    let selected_file = select_file_with_suffix(path, ".env", options, secrets).unwrap_or_else(|_| {
         eprintln!("{}", "No .env files found.".to_string().red());
         process::exit(1);
     });
-    println!("Selected: {}", selected_file.display());
-    _envrc(selected_file.to_str().unwrap(), None)
+    println!("Selected: {}", paths.show(&selected_file));
+    _envrc(selected_file.to_utf8_str()?, envrc_path, false, false, false, options, secrets)
 }
 
-#[instrument]
-fn _link(nodes: &[String]) -> Result<()> {
+#[instrument(skip(options))]
+fn _link(nodes: &[String], on_existing: OnExisting, options: &BuildOptions) -> Result<()> {
     let paths = nodes.iter()
         .map(|s| Path::new(s).to_path_buf())
         .collect::<Vec<_>>();
-    link_all(&paths);
+    link_all_with_policy(&paths, on_existing, options).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot link: {}", e).red());
+        process::exit(1);
+    });
     println!("Linked: {}", nodes.join(" <- "));
     Ok(())
 }
 
+/// Answers with `exit::SUCCESS` if the files below `source_path` form a DAG, `exit::NEGATIVE`
+/// if they do not.
+#[instrument(skip(options))]
+fn _is_dag(source_path: &str, check: bool, strict: bool, paths: &PathDisplay, options: &BuildOptions) -> Result<()> {
+    if !Path::new(source_path).is_dir() {
+        eprintln!("{}", format!("Directory does not exist: {}", source_path).red());
+        process::exit(exit::ERROR);
+    }
+    let policy = if strict { WalkPolicy::Strict } else { WalkPolicy::SkipWithWarning };
+    let confined = match &options.confine_to {
+        Some(root) => ensure_within(Path::new(source_path), root, None),
+        None => Ok(()),
+    };
+    let (is_dag, issues) = confined.and_then(|_| is_dag_with_policy(Path::new(source_path), policy)).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Failed to determine if DAG: {}", e).red());
+        process::exit(exit::ERROR);
+    });
+    for issue in &issues {
+        eprintln!("{}", format!("Warning: skipped {}: {}", paths.show(&issue.path), issue.reason).yellow());
+    }
+    if !check {
+        println!("{}", is_dag);
+    }
+    process::exit(if is_dag { exit::SUCCESS } else { exit::NEGATIVE });
+}
+
+/// Answers with `exit::SUCCESS` if the hierarchy of `source_path` builds without diagnostics,
+/// `exit::NEGATIVE` if diagnostics were found.
+#[instrument(skip(options))]
+fn _validate(
+    source_path: &str,
+    schema: Option<&str>,
+    check: bool,
+    paths: &PathDisplay,
+    options: &BuildOptions,
+    secrets: &SecretMask,
+) -> Result<()> {
+    let source = Path::new(source_path);
+    let validated = ensure_file_exists(source)
+        .and_then(|_| build_env_with_diagnostics_and_options(source, options))
+        .and_then(|result| {
+            let violations = match schema {
+                Some(schema) => Schema::from_file(Path::new(schema))?
+                    .validate(&build_env_with_sources(source, options)?),
+                None => Vec::new(),
+            };
+            Ok((result, violations))
+        });
+    let (result, violations) = validated.unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot validate: {}", e).red());
+        process::exit(exit::ERROR);
+    });
+    if !check {
+        for diagnostic in &result.diagnostics {
+            let rendered = diagnostic.display_with(|file| paths.show(file));
+            match secrets.diagnostic(diagnostic) {
+                Some(text) => println!("{}: {}", rendered, text),
+                None => println!("{}", rendered),
+            }
+        }
+        for violation in &violations {
+            println!("{}", violation.display_with(|file| paths.show(file)));
+        }
+    }
+    let valid = result.diagnostics.is_empty() && violations.is_empty();
+    process::exit(if valid { exit::SUCCESS } else { exit::NEGATIVE });
+}
+
+/// Answers with `exit::SUCCESS` if no file below `source_path` has lint findings,
+/// `exit::NEGATIVE` otherwise.
+#[instrument(skip(options))]
+fn _lint(source_path: &str, format: OutputFormat, strict: bool, paths: &PathDisplay, options: &BuildOptions) -> Result<()> {
+    let policy = if strict { WalkPolicy::Strict } else { WalkPolicy::SkipWithWarning };
+    let (findings, issues) = lint_directory(Path::new(source_path), policy, options).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot lint: {}", e).red());
+        process::exit(exit::ERROR);
+    });
+    for issue in &issues {
+        eprintln!("{}", format!("Warning: skipped {}: {}", paths.show(&issue.path), issue.reason).yellow());
+    }
+    match format {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&findings_to_json(&findings, |file| paths.show(file)))?);
+        }
+        OutputFormat::Text => {
+            for finding in &findings {
+                println!("{}", finding.display_with(|file| paths.show(file)));
+            }
+        }
+    }
+    process::exit(if findings.is_empty() { exit::SUCCESS } else { exit::NEGATIVE });
+}
+
+#[instrument(skip(options))]
+fn _graph(source_path: &str, format: GraphFormat, strict: bool, paths: &PathDisplay, options: &BuildOptions) -> Result<()> {
+    let policy = if strict { WalkPolicy::Strict } else { WalkPolicy::SkipWithWarning };
+    let (parents_of, issues) = dependency_graph(Path::new(source_path), policy, options).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot build graph: {}", e).red());
+        process::exit(1);
+    });
+    for issue in &issues {
+        eprintln!("{}", format!("Warning: skipped {}: {}", paths.show(&issue.path), issue.reason).yellow());
+    }
+    print!("{}", render_graph(&parents_of, format, |file| paths.show(file)));
+    Ok(())
+}
+
+#[instrument(skip(options))]
+fn _promote(key: &str, from: &str, to: &str, copy: bool, paths: &PathDisplay, options: &BuildOptions) -> Result<()> {
+    promote_variable(key, Path::new(from), Path::new(to), copy, options).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot promote {}: {}", key, e).red());
+        process::exit(1);
+    });
+    let action = if copy { "Copied" } else { "Moved" };
+    println!("{} {} from {} to {}", action, key, paths.show(Path::new(from)), paths.show(Path::new(to)));
+    Ok(())
+}
+
+/// Moves parent declarations to the file headers. With `check` nothing is changed and the
+/// answer is `exit::NEGATIVE` if any file needs formatting.
+#[instrument(skip(options))]
+fn _fmt(files: &[String], check: bool, paths: &PathDisplay, options: &BuildOptions) -> Result<()> {
+    let mut unformatted = false;
+    for file in files.iter().map(Path::new) {
+        let result = ensure_file_exists(file).and_then(|_| {
+            if let Some(root) = &options.confine_to {
+                ensure_within(file, root, None)?;
+            }
+            if check {
+                needs_formatting(file)
+            } else {
+                format_env_file(file)
+            }
+        });
+        match result {
+            Ok(true) => {
+                unformatted = true;
+                println!("{}", paths.show(file));
+            }
+            Ok(false) => {}
+            Err(e) => {
+                eprintln!("{}", format!("Cannot format {}: {}", paths.show(file), e).red());
+                process::exit(exit::ERROR);
+            }
+        }
+    }
+    if check && unformatted {
+        process::exit(exit::NEGATIVE);
+    }
+    Ok(())
+}
+
+/// Answers with `exit::SUCCESS` if process `pid` has all variables of `source_path` with the
+/// built values, `exit::NEGATIVE` otherwise.
 #[instrument]
-fn _branches(source_path: &str) -> Result<()> {
-    debug!("source_path: {:?}", source_path);
+fn _check_proc(pid: u32, source_path: &str, check: bool, options: &BuildOptions, secrets: &SecretMask) -> Result<()> {
+    let changes = ensure_file_exists(Path::new(source_path))
+        .and_then(|_| check_process_env(pid, Path::new(source_path), options))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", format!("Cannot check process: {}", e).red());
+            process::exit(exit::ERROR);
+        });
+    if !check {
+        print_changes(&changes, secrets);
+    }
+    process::exit(if changes.is_empty() { exit::SUCCESS } else { exit::NEGATIVE });
+}
+
+/// Answers with `exit::SUCCESS` if both hierarchies build the same environment,
+/// `exit::NEGATIVE` otherwise.
+#[instrument(skip(options))]
+fn _diff(old: &str, new: &str, check: bool, options: &BuildOptions, secrets: &SecretMask) -> Result<()> {
+    let changes = diff_env(Path::new(old), Path::new(new), options).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot compare environments: {}", e).red());
+        process::exit(exit::ERROR);
+    });
+    if !check {
+        print_changes(&changes, secrets);
+    }
+    process::exit(if changes.is_empty() { exit::SUCCESS } else { exit::NEGATIVE });
+}
+
+/// Builds all trees below `source_path`, exiting if the hierarchy is a DAG or cannot be built.
+fn build_trees(source_path: &str, strict: bool, paths: &PathDisplay, options: &BuildOptions) -> Vec<TreeArena> {
     let path = Path::new(source_path);
-    if is_dag(path).expect("Failed to determine if DAG") {
+    let policy = if strict { WalkPolicy::Strict } else { WalkPolicy::SkipWithWarning };
+    let confined = match &options.confine_to {
+        Some(root) => ensure_within(path, root, None),
+        None => Ok(()),
+    };
+    let (is_dag, issues) = confined.and_then(|_| is_dag_with_policy(path, policy)).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Failed to determine if DAG: {}", e).red());
+        process::exit(1);
+    });
+    for issue in &issues {
+        eprintln!("{}", format!("Warning: skipped {}: {}", paths.show(&issue.path), issue.reason).yellow());
+    }
+    if is_dag {
         eprintln!(
             "{}",
             "Dependencies form a DAG, you cannot use tree based commands.".to_string().red()
         );
         process::exit(1);
     }
-    let mut builder = TreeBuilder::new();
-    let trees = builder.build_from_directory(path).unwrap_or_else(|e| {
+    let mut builder = TreeBuilder::new().with_policy(policy).with_confinement(options.confine_to.clone());
+    builder.build_from_directory(path).unwrap_or_else(|e| {
         eprintln!("{}", format!("Cannot build trees: {}", e).red());
         process::exit(1);
-    });
+    })
+}
+
+#[instrument(skip(options))]
+fn _branches(source_path: &str, strict: bool, paths: &PathDisplay, options: &BuildOptions) -> Result<()> {
+    debug!("source_path: {:?}", source_path);
+    let trees = build_trees(source_path, strict, paths, options);
     println!("Found {} trees:\n", trees.len());
     for tree in &trees {
         if let Some(root_idx) = tree.root() {
             if let Some(root_node) = tree.get_node(root_idx) {
-                println!("Tree Root: {}", root_node.data.file_path.display());
-                let _path = [root_node.data.file_path.to_str().unwrap().to_string()];
+                println!("Tree Root: {}", paths.show(&root_node.data.file_path));
                 // TODO: Implement print_leaf_paths for arena-based tree
                 println!();
             }
@@ -176,48 +890,28 @@ fn _branches(source_path: &str) -> Result<()> {
     Ok(())
 }
 
-#[instrument]
-fn _tree(source_path: &str) -> Result<()> {
+#[instrument(skip(options))]
+fn _tree(source_path: &str, strict: bool, paths: &PathDisplay, options: &BuildOptions) -> Result<()> {
     debug!("source_path: {:?}", source_path);
-    let path = Path::new(source_path);
-    if is_dag(path).expect("Failed to determine if DAG") {
-        eprintln!(
-            "{}",
-            "Dependencies form a DAG, you cannot use tree based commands.".to_string().red()
-        );
-        process::exit(1);
-    }
-    let mut builder = TreeBuilder::new();
-    let trees = builder.build_from_directory(path).unwrap_or_else(|e| {
-        eprintln!("{}", format!("Cannot build trees: {}", e).red());
-        process::exit(1);
-    });
+    let trees = build_trees(source_path, strict, paths, options);
     println!("Found {} trees:\n", trees.len());
     for tree in &trees {
         if let Some(root_idx) = tree.root() {
             if let Some(root_node) = tree.get_node(root_idx) {
-                println!("{}", root_node.data.file_path.display());
+                println!("{}", paths.show(&root_node.data.file_path));
             }
         }
     }
     Ok(())
 }
 
-#[instrument]
-fn _tree_edit(source_path: &str) -> Result<()> {
+#[instrument(skip(options))]
+fn _tree_edit(source_path: &str, strict: bool, paths: &PathDisplay, options: &BuildOptions) -> Result<()> {
     // vim -O3 test.env int.env prod.env -c "wincmd h" -c "sp test.env" -c "wincmd l" -c "sp int.env" -c "wincmd l" -c "sp prod.env"
     debug!("source_path: {:?}", source_path);
-    let path = Path::new(source_path);
-    if is_dag(path).expect("Failed to determine if DAG") {
-        eprintln!(
-            "{}",
-            "Dependencies form a DAG, you cannot use tree based commands.".to_string().red()
-        );
-        process::exit(1);
-    }
-    let mut builder = TreeBuilder::new();
-    let trees = builder.build_from_directory(path).unwrap_or_else(|e| {
-        eprintln!("{}", format!("Cannot build trees: {}", e).red());
+    let trees = build_trees(source_path, strict, paths, options);
+    let vim = require_program("vim").unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot edit trees: {}", e).red());
         process::exit(1);
     });
     println!("Editing {} trees...", trees.len());
@@ -234,7 +928,7 @@ fn _tree_edit(source_path: &str) -> Result<()> {
     let mut tmpfile = NamedTempFile::new()?;
     tmpfile.write_all(vimscript.as_bytes())?;
 
-    let status = process::Command::new("vim")
+    let status = process::Command::new(vim)
         .arg("-S")
         .arg(tmpfile.path())
         .status()
@@ -244,28 +938,199 @@ fn _tree_edit(source_path: &str) -> Result<()> {
     Ok(())
 }
 
-#[instrument]
-fn _leaves(source_path: &str) -> Result<()> {
+#[instrument(skip(options))]
+fn _leaves(source_path: &str, strict: bool, paths: &PathDisplay, options: &BuildOptions) -> Result<()> {
+    debug!("source_path: {:?}", source_path);
+    let trees = build_trees(source_path, strict, paths, options);
+    debug!("Found {} trees:\n", trees.len());
+    for tree in &trees {
+        let leaf_nodes = tree.leaf_nodes();
+        for leaf in &leaf_nodes {
+            println!("{}", paths.show(Path::new(leaf)));
+        }
+    }
+    Ok(())
+}
+
+#[instrument(skip(options))]
+fn _optimize(
+    source_path: &str,
+    max_inline_vars: usize,
+    max_value_len: usize,
+    paths: &PathDisplay,
+    options: &BuildOptions,
+) -> Result<()> {
     debug!("source_path: {:?}", source_path);
+    let options = OptimizeOptions {
+        max_inline_vars,
+        max_value_len,
+        confine_to: options.confine_to.clone(),
+    };
+    let report = optimize(Path::new(source_path), &options).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot optimize environment: {}", e).red());
+        process::exit(1);
+    });
+    for parent in &report.inlined {
+        println!("Inlined: {}", paths.show(parent));
+    }
+    for (key, file) in &report.externalized {
+        println!("Externalized: {} -> {}", key, paths.show(file));
+    }
+    if report.inlined.is_empty() && report.externalized.is_empty() {
+        println!("Nothing to optimize.");
+    }
+    Ok(())
+}
+
+#[instrument(skip(options))]
+fn _migrate(source: &MigrateSource, paths: &PathDisplay, options: &BuildOptions) -> Result<()> {
+    let report = match source {
+        MigrateSource::Direnv { envrc_path, output } => {
+            migrate_direnv(Path::new(envrc_path), Path::new(output), options)
+        }
+        MigrateSource::DotenvVault { source_dir, output_dir } => {
+            migrate_dotenv_vault(Path::new(source_dir), Path::new(output_dir), options)
+        }
+    }
+    .unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot migrate: {}", e).red());
+        process::exit(1);
+    });
+    for file in &report.created {
+        println!("Created: {}", paths.show(file));
+    }
+    if !report.manual.is_empty() {
+        println!("\nNeeds manual attention:");
+        for note in &report.manual {
+            println!("{}", format!("  {}", note).yellow());
+        }
+    }
+    Ok(())
+}
+
+#[instrument(skip(options))]
+fn _capture(source_path: &str, filters: &[String], options: &BuildOptions) -> Result<()> {
     let path = Path::new(source_path);
-    if is_dag(path).expect("Failed to determine if DAG") {
-        eprintln!(
-            "{}",
-            "Dependencies form a DAG, you cannot use tree based commands.".to_string().red()
-        );
+    if let Some(root) = &options.confine_to {
+        let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        ensure_within(dir, root, None).unwrap_or_else(|e| {
+            eprintln!("{}", format!("Cannot update {}: {}", source_path, e).red());
+            process::exit(1);
+        });
+    }
+    let report = capture_env(path, env::vars(), filters).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot capture environment: {}", e).red());
         process::exit(1);
+    });
+    if report.added.is_empty() && report.updated.is_empty() {
+        println!("{}", "No variables matched".yellow());
     }
-    let mut builder = TreeBuilder::new();
-    let trees = builder.build_from_directory(path).unwrap_or_else(|e| {
-        eprintln!("{}", format!("Cannot build trees: {}", e).red());
+    for name in &report.added {
+        println!("Added: {}", name);
+    }
+    for name in &report.updated {
+        println!("Updated: {}", name);
+    }
+    Ok(())
+}
+
+#[instrument(skip(options))]
+fn _export(target: &ExportTarget, options: &BuildOptions) -> Result<()> {
+    let source_path = match target {
+        ExportTarget::GithubEnv { source_path, .. } | ExportTarget::GitlabDotenv { source_path, .. } => source_path,
+    };
+    let variables = ensure_file_exists(Path::new(source_path))
+        .and_then(|_| build_env_with_options(Path::new(source_path), options))
+        .map(|(variables, _, _)| variables)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", format!("Cannot build environment: {}", e).red());
+            process::exit(1);
+        });
+
+    match target {
+        ExportTarget::GithubEnv { output, file, .. } => {
+            let variable = if *output { "GITHUB_OUTPUT" } else { "GITHUB_ENV" };
+            let file = file.clone().or_else(|| env::var(variable).ok()).unwrap_or_else(|| {
+                eprintln!("{}", format!("{} is not set, use --file outside of GitHub Actions", variable).red());
+                process::exit(1);
+            });
+            for mask in github_masks(&variables) {
+                println!("{}", mask);
+            }
+            let mut target_file = std::fs::OpenOptions::new().create(true).append(true).open(&file)?;
+            target_file.write_all(format_github_env(&variables).as_bytes())?;
+        }
+        ExportTarget::GitlabDotenv { file, .. } => {
+            let contents = format_gitlab_dotenv(&variables).unwrap_or_else(|e| {
+                eprintln!("{}", format!("Cannot export: {}", e).red());
+                process::exit(1);
+            });
+            match file {
+                Some(file) => std::fs::write(file, contents)?,
+                None => print!("{}", contents),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[instrument]
+fn _capabilities(paths: &PathDisplay) -> Result<()> {
+    for capability in capabilities() {
+        match &capability.path {
+            Some(path) => println!("{} {:<8} {}", "✓".green(), capability.program, paths.show(path)),
+            None => println!("{} {:<8} {}", "✗".red(), capability.program, "not found".yellow()),
+        }
+        let state = if capability.is_available() { "enabled" } else { "disabled" };
+        println!("    {}: {}", state, capability.features);
+    }
+    Ok(())
+}
+
+#[instrument]
+fn _allow(source_dir: &str, paths: &PathDisplay) -> Result<()> {
+    let envrc = allow_section(Path::new(source_dir)).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot allow .envrc: {}", e).red());
         process::exit(1);
     });
-    debug!("Found {} trees:\n", trees.len());
-    for tree in &trees {
-        let leaf_nodes = tree.leaf_nodes();
-        for leaf in &leaf_nodes {
-            println!("{}", leaf);
+    println!("Allowed: {}", paths.show(&envrc));
+    Ok(())
+}
+
+#[instrument]
+fn _hook_section(source_dir: &str) -> Result<()> {
+    match allowed_section(Path::new(source_dir)) {
+        Ok(section) => print!("{}", section.unwrap_or_default()),
+        Err(e) => {
+            eprintln!("{}", format!("rsenv: {}", e).red());
+            process::exit(1);
         }
     }
     Ok(())
 }
+
+#[instrument(skip(options))]
+fn _exec(
+    source_path: &str,
+    container: Option<&Container>,
+    clean: bool,
+    args: &[String],
+    options: &BuildOptions,
+) -> Result<()> {
+    let variables = ensure_file_exists(Path::new(source_path))
+        .and_then(|_| build_env_with_options(Path::new(source_path), options))
+        .map(|(variables, _, _)| variables)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", format!("Cannot build environment: {}", e).red());
+            process::exit(1);
+        });
+    let status = match container {
+        Some(container) => run_in_container(container, &variables, args),
+        None => run_with_env(&variables, args, clean),
+    }
+    .unwrap_or_else(|e| {
+        eprintln!("{}", format!("Cannot run {}: {}", args.join(" "), e).red());
+        process::exit(1);
+    });
+    process::exit(status.code().unwrap_or(1));
+}
@@ -0,0 +1,15 @@
+//! Exit codes of query commands, so scripts can branch on the answer without parsing output.
+
+/// Positive answer, e.g. the hierarchy is a DAG or the file validated cleanly
+pub const SUCCESS: i32 = 0;
+/// Negative answer, e.g. the hierarchy is not a DAG or validation found issues
+pub const NEGATIVE: i32 = 1;
+/// The question could not be answered, e.g. a file could not be read
+pub const ERROR: i32 = 2;
+
+/// Shown below the command list in `--help`.
+pub const HELP: &str = "\
+Exit codes of query commands (is-dag --check, validate, lint, check-proc, diff):
+  0  positive answer (is a DAG, no issues found, process is up to date, no differences)
+  1  negative answer (not a DAG, issues found, process differs, environments differ)
+  2  error, the question could not be answered";
@@ -1,2 +1,4 @@
 pub mod commands;
-pub mod args;
\ No newline at end of file
+pub mod args;
+pub mod output;
+pub mod exit;
@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+use tempfile::NamedTempFile;
+use tracing::{debug, instrument};
+
+use crate::capabilities::require_program;
+use crate::errors::{TreeError, TreeResult};
+use crate::export::{format_variables, ExportFormat};
+use crate::util::path::PathExt;
+
+/// Where `run_in_container` starts the command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Container {
+    /// `docker run --rm <image>`
+    Docker { image: String },
+    /// `docker compose run --rm <service>` for a service of `compose_file`
+    ComposeService { service: String, compose_file: String },
+}
+
+/// Compose override which loads `env_file` into `service`.
+pub fn compose_override(service: &str, env_file: &Path) -> TreeResult<String> {
+    Ok(format!(
+        "services:\n  {}:\n    env_file:\n      - \"{}\"\n",
+        service,
+        env_file.to_utf8_str()?.replace('\\', "\\\\").replace('"', "\\\""),
+    ))
+}
+
+/// Runs `args` in a container with `variables` as its environment.
+///
+/// The variables are passed in a temporary env file which only the current user can read,
+/// so values never show up on the command line. The file is removed when the container exits.
+/// Values docker env files cannot hold, i.e. multiline values, fail before docker is started.
+#[instrument(level = "debug", skip(variables))]
+pub fn run_in_container(
+    container: &Container,
+    variables: &BTreeMap<String, String>,
+    args: &[String],
+) -> TreeResult<ExitStatus> {
+    let env_file = write_temp_file(&format_variables(variables, ExportFormat::Docker)?)?;
+    let docker = require_program("docker")?;
+
+    let mut command = Command::new(docker);
+    // kept alive until the container exits
+    let override_file;
+    match container {
+        Container::Docker { image } => {
+            command.args(["run", "--rm", "-i", "--env-file"]).arg(env_file.path()).arg(image);
+        }
+        Container::ComposeService { service, compose_file } => {
+            override_file = write_temp_file(&compose_override(service, env_file.path())?)?;
+            command
+                .args(["compose", "-f", compose_file, "-f"])
+                .arg(override_file.path())
+                .args(["run", "--rm", service]);
+        }
+    }
+    command.args(args);
+    debug!("Running {:?}", command);
+    command.status().map_err(TreeError::FileReadError)
+}
+
+fn write_temp_file(contents: &str) -> TreeResult<NamedTempFile> {
+    let mut file = NamedTempFile::new().map_err(TreeError::FileReadError)?;
+    file.write_all(contents.as_bytes()).map_err(TreeError::FileReadError)?;
+    file.flush().map_err(TreeError::FileReadError)?;
+    Ok(file)
+}
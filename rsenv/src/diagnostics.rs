@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use tracing::instrument;
+
+use crate::errors::TreeResult;
+use crate::format::late_parent_declaration;
+use crate::secrets::{find_secret, ALLOW_SECRET_MARKER};
+use crate::sops::read_env_lines;
+use crate::{build_env_with_options, parse_parent_spec, split_parent_specs, strip_export, BuildOptions};
+
+/// Non-fatal findings while parsing env files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// A line which is neither blank, a comment nor an assignment, e.g. a shell statement
+    IgnoredLine,
+    /// An `export` line without `=` or without variable name
+    MalformedExport,
+    /// A variable exported more than once within the same file
+    DuplicateKey(String),
+    /// A variable with an empty value (`FOO=` or `FOO=""`), see `ValueMode::DropEmpty`
+    EmptyValue(String),
+    /// A value with leading or trailing whitespace, see `ValueMode::Trim`
+    UntrimmedValue(String),
+    /// Several parents without explicit priorities, precedence depends on their order (rightmost wins)
+    ImplicitParentOrder,
+    /// A `# rsenv:` parent declaration below the first variable, where it is easily missed
+    LateParentDeclaration,
+    /// A value looks like a credential, e.g. an AWS access key. Allowed by a preceding
+    /// `# rsenv-allow-secret` comment, see `secrets::ALLOW_SECRET_MARKER`
+    PossibleSecret { key: String, kind: &'static str },
+    /// A value references a variable which is not defined in the hierarchy
+    UnexpandedVariable { key: String, reference: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    /// 1-based line number
+    pub line: usize,
+    pub kind: DiagnosticKind,
+    pub text: String,
+}
+
+impl DiagnosticKind {
+    /// Stable identifier for machine-readable output.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiagnosticKind::IgnoredLine => "ignored-line",
+            DiagnosticKind::MalformedExport => "malformed-export",
+            DiagnosticKind::DuplicateKey(_) => "duplicate-key",
+            DiagnosticKind::EmptyValue(_) => "empty-value",
+            DiagnosticKind::UntrimmedValue(_) => "untrimmed-value",
+            DiagnosticKind::ImplicitParentOrder => "implicit-parent-order",
+            DiagnosticKind::LateParentDeclaration => "late-parent-declaration",
+            DiagnosticKind::PossibleSecret { .. } => "possible-secret",
+            DiagnosticKind::UnexpandedVariable { .. } => "unexpanded-variable",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            DiagnosticKind::IgnoredLine => "line ignored".to_string(),
+            DiagnosticKind::MalformedExport => "malformed export".to_string(),
+            DiagnosticKind::DuplicateKey(key) => format!("duplicate key {}", key),
+            DiagnosticKind::LateParentDeclaration => "parent declaration below variables, run rsenv fmt".to_string(),
+            DiagnosticKind::ImplicitParentOrder => "parent precedence relies on order, consider priorities like base.env[10]".to_string(),
+            DiagnosticKind::EmptyValue(key) => format!("empty value for {}", key),
+            DiagnosticKind::UntrimmedValue(key) => format!("whitespace around value of {}", key),
+            DiagnosticKind::PossibleSecret { key, kind } => format!("{} looks like a secret ({})", key, kind),
+            DiagnosticKind::UnexpandedVariable { key, reference } => {
+                format!("{} references undefined variable {}", key, reference)
+            }
+        }
+    }
+}
+
+impl Diagnostic {
+    /// Renders the diagnostic like `Display`, showing the path with `show_file`.
+    pub fn display_with(&self, show_file: impl Fn(&Path) -> String) -> String {
+        format!("{}:{}: {}", show_file(&self.file), self.line, self.kind.message())
+    }
+}
+
+/// Renders `file:line: message` without the line's text, which may hold a secret.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.display_with(|p| p.display().to_string()))
+    }
+}
+
+/// Result of `build_env_with_diagnostics`.
+#[derive(Debug)]
+pub struct BuildResult {
+    pub variables: BTreeMap<String, String>,
+    pub files: Vec<PathBuf>,
+    pub is_dag: bool,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl BuildResult {
+    /// Number of lines per file which contributed nothing because they are neither blank, a
+    /// comment nor a valid assignment (`IgnoredLine`, `MalformedExport`). Files without such
+    /// lines are omitted. A file consisting mostly of ignored lines is likely a shell script.
+    pub fn ignored_lines(&self) -> BTreeMap<PathBuf, usize> {
+        let mut counts = BTreeMap::new();
+        for diagnostic in &self.diagnostics {
+            if matches!(diagnostic.kind, DiagnosticKind::IgnoredLine | DiagnosticKind::MalformedExport) {
+                *counts.entry(diagnostic.file.clone()).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+}
+
+/// Builds the environment like `build_env` and additionally collects diagnostics for all files
+/// of the hierarchy, so callers do not need to parse the files themselves.
+#[instrument(level = "debug")]
+pub fn build_env_with_diagnostics(file_path: &Path) -> TreeResult<BuildResult> {
+    build_env_with_diagnostics_and_options(file_path, &BuildOptions::default())
+}
+
+/// Same as `build_env_with_diagnostics`, with explicit `BuildOptions`.
+#[instrument(level = "debug")]
+pub fn build_env_with_diagnostics_and_options(file_path: &Path, options: &BuildOptions) -> TreeResult<BuildResult> {
+    let (variables, files, is_dag) = build_env_with_options(file_path, options)?;
+
+    let mut diagnostics = Vec::new();
+    for file in &files {
+        diagnostics.extend(file_diagnostics(file, &variables)?);
+    }
+
+    Ok(BuildResult { variables, files, is_dag, diagnostics })
+}
+
+/// Collects diagnostics of a single file. References are checked against `defined`.
+#[instrument(level = "debug", skip(defined))]
+pub fn file_diagnostics(file_path: &Path, defined: &BTreeMap<String, String>) -> TreeResult<Vec<Diagnostic>> {
+    Ok(check_file(file_path, &read_env_lines(file_path)?, Some(defined)))
+}
+
+/// Collects diagnostics of the `lines` of `file_path`, decrypted if it is SOPS-encrypted.
+/// References are checked against `defined`, not at all if it is `None`, e.g. because the
+/// hierarchy of the file cannot be built.
+pub fn check_file(file_path: &Path, lines: &[String], defined: Option<&BTreeMap<String, String>>) -> Vec<Diagnostic> {
+    let reference_regex = Regex::new(r"\$\{?([A-Za-z_][A-Za-z0-9_]*)").unwrap();
+    let mut diagnostics = Vec::new();
+    let mut seen_keys = HashSet::new();
+
+    if let Some(index) = late_parent_declaration(lines) {
+        diagnostics.push(Diagnostic {
+            file: file_path.to_path_buf(),
+            line: index + 1,
+            kind: DiagnosticKind::LateParentDeclaration,
+            text: lines[index].clone(),
+        });
+    }
+    let mut secret_allowed = false;
+    for (index, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        let allow_secret = std::mem::replace(&mut secret_allowed, trimmed == ALLOW_SECRET_MARKER);
+        let kind = if let Some(parents) = line.strip_prefix("# rsenv:") {
+            let specs: Vec<_> = split_parent_specs(parents).into_iter().map(parse_parent_spec).collect();
+            let implicit = specs.len() > 1 && specs.iter().all(|s| matches!(s, Ok((_, None))));
+            implicit.then_some(DiagnosticKind::ImplicitParentOrder)
+        } else if trimmed.is_empty() || trimmed.starts_with('#') {
+            None
+        } else if let Some(export) = strip_export(line) {
+            match export.split_once('=') {
+                Some((key, value)) if !key.trim().is_empty() => {
+                    let key = key.trim().to_string();
+                    let mut kinds = Vec::new();
+                    if !seen_keys.insert(key.clone()) {
+                        kinds.push(DiagnosticKind::DuplicateKey(key.clone()));
+                    }
+                    if matches!(value.trim(), "" | "\"\"" | "''") {
+                        kinds.push(DiagnosticKind::EmptyValue(key.clone()));
+                    } else if value.trim() != value {
+                        kinds.push(DiagnosticKind::UntrimmedValue(key.clone()));
+                    }
+                    if let Some(kind) = find_secret(value).filter(|_| !allow_secret) {
+                        kinds.push(DiagnosticKind::PossibleSecret { key: key.clone(), kind });
+                    }
+                    let references = reference_regex.captures_iter(value).map(|caps| caps[1].to_string());
+                    for reference in references.filter(|r| defined.is_some_and(|defined| !defined.contains_key(r))) {
+                        kinds.push(DiagnosticKind::UnexpandedVariable { key: key.clone(), reference });
+                    }
+                    for kind in kinds {
+                        diagnostics.push(Diagnostic {
+                            file: file_path.to_path_buf(),
+                            line: index + 1,
+                            kind,
+                            text: line.clone(),
+                        });
+                    }
+                    None
+                }
+                _ => Some(DiagnosticKind::MalformedExport),
+            }
+        } else {
+            Some(DiagnosticKind::IgnoredLine)
+        };
+
+        if let Some(kind) = kind {
+            diagnostics.push(Diagnostic {
+                file: file_path.to_path_buf(),
+                line: index + 1,
+                kind,
+                text: line.clone(),
+            });
+        }
+    }
+    diagnostics
+}
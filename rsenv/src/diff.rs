@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use tracing::instrument;
+
+use crate::{build_env_with_options, BuildOptions};
+use crate::errors::TreeResult;
+use crate::util::path::ensure_file_exists;
+
+/// A difference between two sets of environment variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvChange {
+    Added { key: String, value: String },
+    Removed { key: String, value: String },
+    Changed { key: String, old: String, new: String },
+}
+
+impl EnvChange {
+    pub fn key(&self) -> &str {
+        match self {
+            EnvChange::Added { key, .. } | EnvChange::Removed { key, .. } | EnvChange::Changed { key, .. } => key,
+        }
+    }
+}
+
+impl fmt::Display for EnvChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvChange::Added { key, value } => write!(f, "+ {}={}", key, value),
+            EnvChange::Removed { key, value } => write!(f, "- {}={}", key, value),
+            EnvChange::Changed { key, old, new } => write!(f, "~ {}: {} -> {}", key, old, new),
+        }
+    }
+}
+
+/// Compares two sets of variables, ordered by key.
+pub fn diff_vars(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> Vec<EnvChange> {
+    let mut changes = Vec::new();
+    for (key, old_value) in old {
+        match new.get(key) {
+            None => changes.push(EnvChange::Removed { key: key.clone(), value: old_value.clone() }),
+            Some(new_value) if new_value != old_value => changes.push(EnvChange::Changed {
+                key: key.clone(),
+                old: old_value.clone(),
+                new: new_value.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    for (key, value) in new {
+        if !old.contains_key(key) {
+            changes.push(EnvChange::Added { key: key.clone(), value: value.clone() });
+        }
+    }
+    changes.sort_by(|a, b| a.key().cmp(b.key()));
+    changes
+}
+
+/// Builds both leaves and compares their environments, changes lead from `old` to `new`.
+#[instrument(level = "debug")]
+pub fn diff_env(old: &Path, new: &Path, options: &BuildOptions) -> TreeResult<Vec<EnvChange>> {
+    ensure_file_exists(old)?;
+    ensure_file_exists(new)?;
+    let (old_vars, _, _) = build_env_with_options(old, options)?;
+    let (new_vars, _, _) = build_env_with_options(new, options)?;
+    Ok(diff_vars(&old_vars, &new_vars))
+}
+
+/// Collects `export KEY=VALUE` lines into a map.
+pub fn parse_exports<'a>(lines: impl Iterator<Item = &'a str>) -> BTreeMap<String, String> {
+    lines
+        .filter_map(|line| line.strip_prefix("export "))
+        .filter_map(|export| export.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_vars() {
+        let old = parse_exports("export A=1\nexport B=2\nexport C=3".lines());
+        let new = parse_exports("export B=2\nexport C=4\nexport D=5".lines());
+        let changes = diff_vars(&old, &new);
+        assert_eq!(changes, vec![
+            EnvChange::Removed { key: "A".into(), value: "1".into() },
+            EnvChange::Changed { key: "C".into(), old: "3".into(), new: "4".into() },
+            EnvChange::Added { key: "D".into(), value: "5".into() },
+        ]);
+        assert_eq!(changes[1].to_string(), "~ C: 3 -> 4");
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::env;
@@ -11,9 +12,53 @@ use tracing::{debug, instrument};
 
 use crate::errors::{TreeError, TreeResult};
 use crate::arena::TreeArena;
+use crate::capabilities::require_program;
+use crate::cli::output::SecretMask;
+use crate::util::path::{ensure_file_exists, PathExt};
+use crate::{build_env_with_options, format_env_vars, BuildOptions};
+
+/// A file offered by `select_file_with_suffix`, previewing the environment it builds.
+struct EnvFileItem {
+    path: PathBuf,
+    text: String,
+    options: Arc<BuildOptions>,
+    secrets: Arc<SecretMask>,
+}
 
-#[instrument(level = "debug")]
-pub fn select_file_with_suffix(dir: &Path, suffix: &str) -> TreeResult<PathBuf> {
+impl SkimItem for EnvFileItem {
+    fn text(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.text)
+    }
+
+    fn preview(&self, _context: PreviewContext) -> ItemPreview {
+        ItemPreview::Text(preview_environment(&self.path, &self.options, &self.secrets))
+    }
+}
+
+/// Text shown next to a file while selecting: the variables it builds with `options` and
+/// secret values hidden by `secrets`, or why it cannot be built.
+pub fn preview_environment(path: &Path, options: &BuildOptions, secrets: &SecretMask) -> String {
+    let built = ensure_file_exists(path).and_then(|_| build_env_with_options(path, options));
+    match built {
+        Ok((variables, _, _)) => {
+            let masked: BTreeMap<String, String> = variables.iter()
+                .map(|(key, value)| (key.clone(), secrets.value(key, value).to_string()))
+                .collect();
+            format_env_vars(&masked)
+        }
+        Err(e) => format!("Cannot build environment: {}", e),
+    }
+}
+
+/// Lets the user pick one of the files below `dir` ending with `suffix`, previewing the
+/// environment each file builds with `options` and secret values hidden by `secrets`.
+#[instrument(level = "debug", skip(secrets))]
+pub fn select_file_with_suffix(
+    dir: &Path,
+    suffix: &str,
+    options: &BuildOptions,
+    secrets: &SecretMask,
+) -> TreeResult<PathBuf> {
     debug!("Searching for files with suffix {} in {:?}", suffix, dir);
 
     // List all files with the given suffix
@@ -25,6 +70,11 @@ pub fn select_file_with_suffix(dir: &Path, suffix: &str) -> TreeResult<PathBuf>
         .map(|e| e.path().to_path_buf())
         .collect();
 
+    // skim items are strings, so non UTF-8 paths could not be selected faithfully
+    for file in &files {
+        file.to_utf8_str()?;
+    }
+
     if files.is_empty() {
         return Err(TreeError::InternalError(format!(
             "No files found with suffix {} in {:?}",
@@ -41,8 +91,15 @@ pub fn select_file_with_suffix(dir: &Path, suffix: &str) -> TreeResult<PathBuf>
     // which we can achieve by transforming our Vec<String> into a stream of Arc<dyn SkimItem> objects.
     // For each file path String, we convert it to an Arc<String> and then to Arc<dyn SkimItem>,
     // just like before. We then send each of these items through the tx (sender) part of the channel.
+    let options = Arc::new(options.clone());
+    let secrets = Arc::new(secrets.clone());
     for file in files.iter() {
-        let item = Arc::new(file.to_string_lossy().into_owned()) as Arc<dyn SkimItem>;
+        let item = Arc::new(EnvFileItem {
+            path: file.clone(),
+            text: file.to_string_lossy().into_owned(),
+            options: Arc::clone(&options),
+            secrets: Arc::clone(&secrets),
+        }) as Arc<dyn SkimItem>;
         tx.send(item).map_err(|e| TreeError::InternalError(
             format!("Failed to send item through channel: {}", e)
         ))?;
@@ -51,9 +108,11 @@ pub fn select_file_with_suffix(dir: &Path, suffix: &str) -> TreeResult<PathBuf>
     // This step is important because Skim::run_with() needs to know when there are no more items to expect.
     drop(tx); // Close the channel
 
-    let options = SkimOptionsBuilder::default()
+    let skim_options = SkimOptionsBuilder::default()
         .height(Some("50%"))
         .multi(false)
+        // empty command: the preview comes from `EnvFileItem::preview`
+        .preview(Some(""))
         .build()
         .map_err(|e| TreeError::InternalError(
             format!("Failed to build skim options: {}", e)
@@ -61,7 +120,7 @@ pub fn select_file_with_suffix(dir: &Path, suffix: &str) -> TreeResult<PathBuf>
 
     // Running Skim with the Receiver: Instead of creating and passing a stream of items directly,
     // we just pass the rx (receiver) part of the channel to Skim::run_with().
-    let selected_items = Skim::run_with(&options, Some(rx))
+    let selected_items = Skim::run_with(&skim_options, Some(rx))
         .map(|out| out.selected_items)
         .unwrap_or_default();
 
@@ -79,27 +138,44 @@ pub fn select_file_with_suffix(dir: &Path, suffix: &str) -> TreeResult<PathBuf>
         .ok_or_else(|| TreeError::InternalError("No file selected".to_string()))
 }
 
+/// Opens `files` in `$EDITOR` (default vim), in the given order, e.g. a leaf and its parents.
+///
+/// Vim and Neovim show all files side by side in vertical splits. Other editors are run once
+/// per file, one after the other, and opening stops at the first editor failure. `$EDITOR`
+/// may contain arguments, e.g. `code --wait`.
 #[instrument(level = "debug")]
 pub fn open_files_in_editor(files: Vec<PathBuf>) -> TreeResult<()> {
     debug!("Opening files in editor: {:?}", files);
 
     let editor = env::var("EDITOR").unwrap_or_else(|_| "vim".to_string());
-    if !editor.contains("vim") {
-        return Err(TreeError::InternalError("Only vim is supported for now".to_string()));
+    let mut words = editor.split_whitespace();
+    let program = words.next()
+        .ok_or_else(|| TreeError::InternalError("EDITOR is empty".to_string()))?;
+    let editor_args: Vec<&str> = words.collect();
+    let program = require_program(program)?;
+    let run_editor = |options: &[&str], files: &[PathBuf]| {
+        Command::new(&program)
+            .args(&editor_args)
+            .args(options)
+            .args(files)
+            .status()
+            .map_err(|e| TreeError::InternalError(format!("Failed to run editor: {}", e)))
+    };
+
+    let is_vim = program.file_name().is_some_and(|name| name.to_string_lossy().contains("vim"));
+    if is_vim {
+        run_editor(&["-O"], &files)?;
+        return Ok(());
     }
 
-    let file_paths: Vec<String> = files.iter()
-        .map(|path| path.to_string_lossy().into_owned())
-        .collect();
-
-    // Spawn a new process to run the editor.
-    // For Vim and NeoVim, the `-p` option opens files in separate tabs.
-    Command::new(&editor)
-        .arg("-O")
-        .args(&file_paths)
-        .status()
-        .map_err(|e| TreeError::InternalError(format!("Failed to run editor: {}", e)))?;
-
+    for file in files {
+        let status = run_editor(&[], std::slice::from_ref(&file))?;
+        if !status.success() {
+            return Err(TreeError::InternalError(format!(
+                "Editor failed with {} on {}", status, file.display()
+            )));
+        }
+    }
     Ok(())
 }
 
@@ -1,33 +1,49 @@
 use std::path::Path;
 use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, Read, Write};
+use std::io::{Read, Write};
 use regex::Regex;
 use tracing::{debug, instrument};
+use crate::diff::{diff_vars, parse_exports, EnvChange};
 use crate::errors::{TreeError, TreeResult};
+use crate::util::file::read_lines;
+use crate::util::hash::fnv1a_hex;
 use crate::util::path::ensure_file_exists;
 
 pub const START_SECTION_DELIMITER: &str = "#------------------------------- rsenv start --------------------------------";
 pub const END_SECTION_DELIMITER: &str = "#-------------------------------- rsenv end ---------------------------------";
 
+pub const HASH_PREFIX: &str = "# rsenv-hash: ";
+
+/// Options for `update_dot_envrc_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct EnvrcOptions {
+    /// Overwrite the managed section even if it was edited manually
+    pub force: bool,
+}
+
 #[instrument(level = "debug")]
 pub fn update_dot_envrc(target_file_path: &Path, data: &str) -> TreeResult<()> {
+    update_dot_envrc_with_options(target_file_path, data, &EnvrcOptions::default())?;
+    Ok(())
+}
+
+/// Writes `data` into the managed rsenv section of `target_file_path`.
+///
+/// The section stores a hash of its content. If the content no longer matches the hash,
+/// it has been edited by hand and is only overwritten with `options.force`.
+///
+/// Returns the exported variables which changed compared to the previous section.
+#[instrument(level = "debug")]
+pub fn update_dot_envrc_with_options(target_file_path: &Path, data: &str, options: &EnvrcOptions) -> TreeResult<Vec<EnvChange>> {
     ensure_file_exists(target_file_path)?;
 
-    let section = format!(
-        "\n{start_section_delimiter}\n\
-         {data}\
-         {end_section_delimiter}\n",
-        start_section_delimiter = START_SECTION_DELIMITER,
-        data = data,
-        end_section_delimiter = END_SECTION_DELIMITER,
-    );
+    let section = format_section(data);
 
-    let file = File::open(target_file_path)
-        .map_err(TreeError::FileReadError)?;
-    let reader = BufReader::new(file);
-    let lines: Vec<String> = reader.lines()
-        .collect::<Result<_, _>>()
-        .map_err(TreeError::FileReadError)?;
+    let lines = read_lines(target_file_path)?;
+    let count = find_sections(&lines).len();
+    if count > 1 {
+        return Err(TreeError::DuplicateManagedSection { path: target_file_path.to_path_buf(), count });
+    }
 
     let start_index = lines.iter().position(|l| {
         l.starts_with(START_SECTION_DELIMITER)
@@ -36,6 +52,18 @@ pub fn update_dot_envrc(target_file_path: &Path, data: &str) -> TreeResult<()> {
         l.starts_with(END_SECTION_DELIMITER)
     });
 
+    if let (Some(start), Some(end), false) = (start_index, end_index, options.force) {
+        if start < end && is_section_modified(&lines[start + 1..end]) {
+            return Err(TreeError::ManagedSectionModified(target_file_path.to_path_buf()));
+        }
+    }
+
+    let old_vars = match (start_index, end_index) {
+        (Some(start), Some(end)) if start < end => parse_exports(lines[start + 1..end].iter().map(String::as_str)),
+        _ => Default::default(),
+    };
+    let changes = diff_vars(&old_vars, &parse_exports(data.lines()));
+
     let mut new_file_content = String::new();
 
     match (start_index, end_index) {
@@ -57,7 +85,86 @@ pub fn update_dot_envrc(target_file_path: &Path, data: &str) -> TreeResult<()> {
         .map_err(TreeError::FileReadError)?;
 
     file.write_all(new_file_content.as_bytes())
-        .map_err(TreeError::FileReadError)
+        .map_err(TreeError::FileReadError)?;
+    Ok(changes)
+}
+
+/// Collapses multiple managed sections, e.g. appended by other tools, into a single section.
+///
+/// The last section is the newest one, its variables are kept and written as a new section at
+/// the end of the file. Returns the number of removed sections.
+#[instrument(level = "debug")]
+pub fn repair_dot_envrc(target_file_path: &Path) -> TreeResult<usize> {
+    ensure_file_exists(target_file_path)?;
+    let lines = read_lines(target_file_path)?;
+    let sections = find_sections(&lines);
+    let Some(&(newest_start, newest_end)) = sections.last() else {
+        return Ok(0);
+    };
+    if sections.len() == 1 {
+        return Ok(0);
+    }
+
+    let data: String = lines[newest_start + 1..newest_end]
+        .iter()
+        .filter(|l| !l.starts_with(HASH_PREFIX))
+        .map(|l| format!("{}\n", l))
+        .collect();
+
+    let mut remaining: Vec<&str> = Vec::new();
+    let mut next = 0;
+    for (start, end) in &sections {
+        remaining.extend(lines[next..*start].iter().map(String::as_str));
+        next = end + 1;
+    }
+    remaining.extend(lines[next..].iter().map(String::as_str));
+
+    let new_file_content = remaining.join("\n") + &format_section(&data);
+    std::fs::write(target_file_path, new_file_content).map_err(TreeError::FileReadError)?;
+    Ok(sections.len() - 1)
+}
+
+fn format_section(data: &str) -> String {
+    format!(
+        "\n{start_section_delimiter}\n\
+         {hash_prefix}{hash}\n\
+         {data}\
+         {end_section_delimiter}\n",
+        start_section_delimiter = START_SECTION_DELIMITER,
+        hash_prefix = HASH_PREFIX,
+        hash = section_hash(data.lines()),
+        data = data,
+        end_section_delimiter = END_SECTION_DELIMITER,
+    )
+}
+
+/// Returns start and end line index of every complete managed section.
+pub(crate) fn find_sections(lines: &[String]) -> Vec<(usize, usize)> {
+    let mut sections = Vec::new();
+    let mut start = None;
+    for (index, line) in lines.iter().enumerate() {
+        if line.starts_with(START_SECTION_DELIMITER) {
+            start = start.or(Some(index));
+        } else if line.starts_with(END_SECTION_DELIMITER) {
+            if let Some(start) = start.take() {
+                sections.push((start, index));
+            }
+        }
+    }
+    sections
+}
+
+/// Checks the stored hash of a managed section against its content.
+/// Sections without hash (written by older versions) cannot be verified and count as unmodified.
+fn is_section_modified(section_lines: &[String]) -> bool {
+    match section_lines.first().and_then(|l| l.strip_prefix(HASH_PREFIX)) {
+        Some(stored) => stored.trim() != section_hash(section_lines[1..].iter().map(String::as_str)),
+        None => false,
+    }
+}
+
+fn section_hash<'a>(lines: impl Iterator<Item = &'a str>) -> String {
+    fnv1a_hex(lines.collect::<Vec<_>>().join("\n").as_bytes())
 }
 
 #[instrument(level = "debug")]
@@ -73,7 +180,7 @@ pub fn delete_section(file_path: &Path) -> TreeResult<()> {
     // In this case, we want to match across multiple lines, hence the s modifier is used.
     // (?s)#--------------------- rsenv start ----------------------.*#---------------------- rsenv end -----------------------\n
     let pattern = format!(
-        r"(?s){start_section_delimiter}.*{end_section_delimiter}\n",
+        r"(?s){start_section_delimiter}.*?{end_section_delimiter}\n",
         start_section_delimiter = START_SECTION_DELIMITER,
         end_section_delimiter = END_SECTION_DELIMITER,
     );
@@ -84,7 +191,7 @@ pub fn delete_section(file_path: &Path) -> TreeResult<()> {
     // Assert that only one section
     let result = re.find_iter(&contents).collect::<Vec<_>>();
     if result.len() > 1 {
-        return Err(TreeError::MultipleParents(file_path.to_path_buf()));
+        return Err(TreeError::DuplicateManagedSection { path: file_path.to_path_buf(), count: result.len() });
     }
 
     // Replace the matched section with an empty string
@@ -18,8 +18,17 @@ pub enum TreeError {
         reason: String,
     },
 
-    #[error("Cycle detected in environment hierarchy starting at: {0}")]
-    CycleDetected(PathBuf),
+    #[error("Cyclic dependency in environment hierarchy: {}", .path.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(" -> "))]
+    CyclicDependency {
+        /// Files from the first to the repeated one, e.g. `a.env -> b.env -> a.env`
+        path: Vec<PathBuf>,
+    },
+
+    #[error("Hierarchy exceeds the maximum depth of {limit}: {}", .path.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(" -> "))]
+    HierarchyTooDeep {
+        limit: usize,
+        path: Vec<PathBuf>,
+    },
 
     #[error("Path resolution failed: {path}, reason: {reason}")]
     PathResolution {
@@ -30,6 +39,68 @@ pub enum TreeError {
     #[error("Multiple parent declarations found in: {0}")]
     MultipleParents(PathBuf),
 
+    #[error("{child} already links to {existing}, refusing to link {proposed}")]
+    ParentExists {
+        child: PathBuf,
+        existing: String,
+        proposed: String,
+    },
+
+    #[error("Path is not valid UTF-8: {0:?}")]
+    InvalidPathEncoding(PathBuf),
+
+    #[error("Managed rsenv section in {0} was edited manually, use --force to overwrite it")]
+    ManagedSectionModified(PathBuf),
+
+    #[error("Found {count} managed rsenv sections in {path}, run `rsenv envrc-repair` to merge them")]
+    DuplicateManagedSection {
+        path: PathBuf,
+        count: usize,
+    },
+
+    #[error("The rsenv section of {0} is not allowed, run `rsenv allow` to approve it")]
+    EnvrcNotAllowed(PathBuf),
+
+    #[error("Refusing to access {path}: outside of {root}{}", .referenced_by.as_ref().map(|p| format!(" (referenced by {})", p.display())).unwrap_or_default())]
+    OutsideProject {
+        path: PathBuf,
+        root: PathBuf,
+        referenced_by: Option<PathBuf>,
+    },
+
+    #[error("Required program not found in PATH: {0}")]
+    ProgramNotFound(String),
+
+    #[error("Cannot export {key}: {reason}")]
+    UnsupportedValue {
+        key: String,
+        reason: String,
+    },
+
+    #[error("Cannot read the environment of process {pid}: {reason}")]
+    ProcessEnvUnavailable {
+        pid: u32,
+        reason: String,
+    },
+
+    #[error("{key} is not set in {path}")]
+    VariableNotFound {
+        key: String,
+        path: PathBuf,
+    },
+
+    #[error("{to} is neither an ancestor nor a descendant of {from}")]
+    UnrelatedFiles {
+        from: PathBuf,
+        to: PathBuf,
+    },
+
+    #[error("Cannot expand {reference}: {reason}")]
+    VariableExpansion {
+        reference: String,
+        reason: String,
+    },
+
     #[error("Internal tree operation failed: {0}")]
     InternalError(String),
 }
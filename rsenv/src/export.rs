@@ -0,0 +1,57 @@
+use std::collections::BTreeMap;
+
+use crate::errors::{TreeError, TreeResult};
+use crate::format_env_vars;
+use crate::util::value::unquote;
+
+/// Syntax in which built variables are written, see `format_variables`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// `export KEY=value` for POSIX shells, values verbatim
+    #[default]
+    Posix,
+    /// `KEY=value`, values verbatim
+    Dotenv,
+    /// `set -gx KEY 'value'` for fish
+    Fish,
+    /// `$env:KEY = 'value'` for PowerShell
+    Powershell,
+    /// `KEY=value` for `docker run --env-file`, which takes values literally
+    Docker,
+}
+
+/// Formats variables in the syntax of `format`.
+///
+/// POSIX and dotenv output keeps values as written in the env files. The other formats take
+/// values literally, so they are unquoted like the shell would; references like `$HOME` are
+/// not expanded. Docker env files cannot hold multiline values, they fail with
+/// `TreeError::UnsupportedValue`.
+pub fn format_variables(variables: &BTreeMap<String, String>, format: ExportFormat) -> TreeResult<String> {
+    if format == ExportFormat::Posix {
+        return Ok(format_env_vars(variables));
+    }
+    let mut contents = String::new();
+    for (key, value) in variables {
+        let line = match format {
+            ExportFormat::Posix | ExportFormat::Dotenv => format!("{}={}", key, value),
+            ExportFormat::Fish => {
+                let escaped = unquote(value).replace('\\', "\\\\").replace('\'', "\\'");
+                format!("set -gx {} '{}'", key, escaped)
+            }
+            ExportFormat::Powershell => format!("$env:{} = '{}'", key, unquote(value).replace('\'', "''")),
+            ExportFormat::Docker => {
+                let value = unquote(value);
+                if value.contains('\n') {
+                    return Err(TreeError::UnsupportedValue {
+                        key: key.clone(),
+                        reason: "docker env files do not support multiline values".to_string(),
+                    });
+                }
+                format!("{}={}", key, value)
+            }
+        };
+        contents.push_str(&line);
+        contents.push('\n');
+    }
+    Ok(contents)
+}
@@ -0,0 +1,56 @@
+use std::path::Path;
+
+use tracing::instrument;
+
+use crate::errors::{TreeError, TreeResult};
+use crate::sops::is_sops_encrypted;
+use crate::strip_export;
+use crate::util::file::{read_text_file, write_text_file};
+
+/// Index of the first `# rsenv:` line which follows a variable assignment, i.e. which is not
+/// part of the file header where parent declarations belong.
+pub fn late_parent_declaration(lines: &[String]) -> Option<usize> {
+    let first_assignment = lines.iter().position(|line| strip_export(line).is_some())?;
+    lines.iter()
+        .enumerate()
+        .skip(first_assignment)
+        .find(|(_, line)| line.starts_with("# rsenv:"))
+        .map(|(index, _)| index)
+}
+
+/// Whether `format_env_file` would change `path`.
+#[instrument(level = "debug")]
+pub fn needs_formatting(path: &Path) -> TreeResult<bool> {
+    let (lines, _) = read_text_file(path)?;
+    ensure_not_encrypted(path, &lines)?;
+    Ok(late_parent_declaration(&lines).is_some())
+}
+
+/// Moves all `# rsenv:` lines of `path` to the top of the file, keeping their order and the
+/// order of all other lines. Returns whether the file was changed.
+///
+/// SOPS-encrypted files are refused: reordering their lines would break the MAC, they have
+/// to be edited with `sops`.
+#[instrument(level = "debug")]
+pub fn format_env_file(path: &Path) -> TreeResult<bool> {
+    let (lines, format) = read_text_file(path)?;
+    ensure_not_encrypted(path, &lines)?;
+    if late_parent_declaration(&lines).is_none() {
+        return Ok(false);
+    }
+    let (mut formatted, rest): (Vec<String>, Vec<String>) =
+        lines.into_iter().partition(|line| line.starts_with("# rsenv:"));
+    formatted.extend(rest);
+    write_text_file(path, &formatted, format)?;
+    Ok(true)
+}
+
+fn ensure_not_encrypted(path: &Path, lines: &[String]) -> TreeResult<()> {
+    if is_sops_encrypted(path, lines) {
+        return Err(TreeError::InvalidFormat {
+            path: path.to_path_buf(),
+            reason: "SOPS-encrypted files cannot be formatted, edit them with sops".to_string(),
+        });
+    }
+    Ok(())
+}
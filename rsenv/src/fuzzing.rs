@@ -0,0 +1,61 @@
+//! Entry points for the fuzz targets in `fuzz/` (feature `fuzzing`).
+//!
+//! Each function takes arbitrary bytes and panics only if an invariant is violated, so the
+//! fuzzer reports content corruption in addition to crashes of the parser itself.
+use std::fs;
+
+use crate::util::file::{normalize_lines, read_lines};
+use crate::{link, parse_env_lines, parse_parent_spec, split_parent_specs, strip_export, unlink};
+
+/// Parses `data` as env file content. Invalid UTF-8 is skipped, it is rejected when reading
+/// files anyway.
+pub fn parse_env(data: &[u8]) {
+    let Ok(content) = std::str::from_utf8(data) else { return };
+    let (lines, _) = normalize_lines(content);
+    for line in &lines {
+        let _ = strip_export(line);
+        if let Some(specs) = line.strip_prefix("# rsenv:") {
+            for spec in split_parent_specs(specs) {
+                let _ = parse_parent_spec(spec);
+            }
+        }
+    }
+    if let Ok(parsed) = parse_env_lines(&lines) {
+        assert!(parsed.variables.keys().all(|key| !key.is_empty()), "empty variable name in {:?}", content);
+    }
+}
+
+/// Links a parent to a child with content `data` and unlinks it again. Apart from the
+/// `# rsenv:` line, the content of the child must not change.
+pub fn link_roundtrip(data: &[u8]) {
+    let Ok(content) = std::str::from_utf8(data) else { return };
+    let tempdir = tempfile::tempdir().expect("tempdir");
+    let parent = tempdir.path().join("parent.env");
+    let child = tempdir.path().join("child.env");
+    fs::write(&parent, "export PARENT=parent\n").expect("write parent");
+    fs::write(&child, content).expect("write child");
+
+    let (lines, _) = normalize_lines(content);
+    let declarations = lines.iter().filter(|line| line.starts_with("# rsenv:")).count();
+    let without_declarations = |lines: Vec<String>| -> Vec<String> {
+        lines.into_iter().filter(|line| !line.starts_with("# rsenv:")).collect()
+    };
+    let expected = without_declarations(lines);
+
+    let linked = link(&parent, &child);
+    if declarations > 1 {
+        assert!(linked.is_err(), "linked a file with {} declarations", declarations);
+        return;
+    }
+    linked.expect("link");
+    let linked_lines = read_lines(&child).expect("read linked child");
+    let parents = parse_env_lines(&linked_lines).expect("parse linked child").parents;
+    assert_eq!(parents, vec!["parent.env".to_string()]);
+    assert_eq!(without_declarations(linked_lines), expected, "link changed the content");
+
+    unlink(&child).expect("unlink");
+    let unlinked_lines = read_lines(&child).expect("read unlinked child");
+    let parents = parse_env_lines(&unlinked_lines).expect("parse unlinked child").parents;
+    assert!(parents.is_empty());
+    assert_eq!(without_declarations(unlinked_lines), expected, "unlink changed the content");
+}
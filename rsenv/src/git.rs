@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+use tracing::{debug, instrument};
+
+use crate::capabilities::require_program;
+use crate::errors::{TreeError, TreeResult};
+use crate::{build_env_with_reader, BuildOptions};
+use crate::reader::EnvReader;
+use crate::sops::decrypt_env_content;
+use crate::util::path::PathExt;
+
+/// Builds the environment of `file_path` as it was at git revision `rev`.
+///
+/// The leaf and all its parents are read with `git show <rev>:<path>` instead of from the
+/// working tree, so files which were changed or deleted since are taken from the revision.
+/// Apart from that the hierarchy is built like by `build_env_with_options`, honoring all of
+/// `options`. The returned files are the paths the files had in the working tree at that
+/// revision, in the order of `order_files`.
+#[instrument(level = "debug")]
+pub fn build_env_at_rev(
+    file_path: &Path,
+    rev: &str,
+    options: &BuildOptions,
+) -> TreeResult<(BTreeMap<String, String>, Vec<PathBuf>, bool)> {
+    require_program("git")?;
+    let dir = file_path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let file_name = file_path.file_name()
+        .ok_or_else(|| TreeError::InvalidParent(file_path.to_path_buf()))?;
+    let file_path = dir.to_canonical()?.join(file_name);
+    let root = PathBuf::from(git(dir, &["rev-parse", "--show-toplevel"])?.trim_end());
+    debug!("repository root: {:?}", root);
+
+    let mut reader = GitRevision { root, rev: rev.to_string() };
+    build_env_with_reader(&file_path, options, &mut reader, |_| {})
+}
+
+/// Reads the files as they were at revision `rev` of the repository at `root`.
+#[derive(Debug)]
+struct GitRevision {
+    root: PathBuf,
+    rev: String,
+}
+
+impl EnvReader for GitRevision {
+    fn resolve(&mut self, path: &Path) -> TreeResult<PathBuf> {
+        Ok(normalize_path(path))
+    }
+
+    fn read_lines(&mut self, file: &Path) -> TreeResult<Vec<String>> {
+        let relative = file.strip_prefix(&self.root).map_err(|_| TreeError::PathResolution {
+            path: file.to_path_buf(),
+            reason: format!("not inside the git repository {}", self.root.display()),
+        })?;
+        let object = format!("{}:{}", self.rev, relative.to_utf8_str()?.replace('\\', "/"));
+        decrypt_env_content(file, &git(&self.root, &["show", &object])?)
+    }
+}
+
+/// Runs git in `dir` and returns its stdout.
+fn git(dir: &Path, args: &[&str]) -> TreeResult<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(TreeError::FileReadError)?;
+    if !output.status.success() {
+        return Err(TreeError::PathResolution {
+            path: dir.to_path_buf(),
+            reason: format!("git {}: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()),
+        });
+    }
+    String::from_utf8(output.stdout).map_err(|e| TreeError::InvalidFormat {
+        path: dir.to_path_buf(),
+        reason: e.to_string(),
+    })
+}
+
+/// Resolves `.` and `..` components without touching the file system, because the files of
+/// a past revision need not exist in the working tree.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+use tracing::instrument;
+
+use crate::errors::{TreeError, TreeResult};
+use crate::{parse_parent_spec, split_parent_specs, BuildOptions};
+use crate::util::file::{read_dir_files, WalkIssue, WalkPolicy};
+use crate::util::path::{ensure_within, PathExt};
+
+/// Syntax in which `render_graph` writes the parent relations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz DOT, e.g. for `dot -Tsvg`
+    #[default]
+    Dot,
+    /// Mermaid flowchart, e.g. for Markdown documentation
+    Mermaid,
+}
+
+/// Files mapped to their parents, in declaration order.
+pub type ParentMap = BTreeMap<PathBuf, Vec<PathBuf>>;
+
+/// Parent relations of all files below `dir` which declare parents. Parents are keys as well,
+/// so roots map to no parents. Unlike `TreeBuilder::build_from_directory` files with several
+/// parents are supported.
+///
+/// Unreadable files are handled according to `policy` and reported as issues, a reference
+/// to a missing parent is an error, as is one outside `BuildOptions::confine_to` of `options`.
+#[instrument(level = "debug")]
+pub fn dependency_graph(
+    dir: &Path,
+    policy: WalkPolicy,
+    options: &BuildOptions,
+) -> TreeResult<(ParentMap, Vec<WalkIssue>)> {
+    if !dir.is_dir() {
+        return Err(TreeError::InvalidFormat { path: dir.to_path_buf(), reason: "Not a directory".to_string() });
+    }
+    if let Some(root) = &options.confine_to {
+        ensure_within(dir, root, None)?;
+    }
+    let (dir_files, issues) = read_dir_files(dir, policy)?;
+    let mut parents_of = ParentMap::new();
+    for (path, lines) in dir_files {
+        let file = path.to_canonical()?;
+        let file_dir = file.parent().ok_or_else(|| TreeError::InvalidParent(file.clone()))?;
+        let mut parents = Vec::new();
+        for specs in lines.iter().filter_map(|line| line.strip_prefix("# rsenv:")) {
+            for spec in split_parent_specs(specs) {
+                let (parent, _) = parse_parent_spec(spec)
+                    .map_err(|reason| TreeError::InvalidFormat { path: file.clone(), reason })?;
+                let parent = file_dir.join(parent).to_canonical()?;
+                if let Some(root) = &options.confine_to {
+                    ensure_within(&parent, root, Some(&file))?;
+                }
+                parents.push(parent);
+            }
+        }
+        if parents.is_empty() {
+            continue;
+        }
+        for parent in &parents {
+            parents_of.entry(parent.clone()).or_default();
+        }
+        parents_of.insert(file, parents);
+    }
+    Ok((parents_of, issues))
+}
+
+/// Parent relations of env files as an arena of nodes, e.g. for embedding rsenv as a library.
+///
+/// Files are stored sorted, every method lists files in this order unless stated otherwise.
+/// Paths are looked up canonicalized, like the paths of a directory scan.
+#[derive(Debug, Clone, Default)]
+pub struct EnvGraph {
+    files: Vec<PathBuf>,
+    index: HashMap<PathBuf, usize>,
+    parents: Vec<Vec<usize>>,
+    children: Vec<Vec<usize>>,
+}
+
+impl EnvGraph {
+    /// Scans `dir` like `dependency_graph`, files without links are not part of the graph.
+    pub fn from_directory(
+        dir: &Path,
+        policy: WalkPolicy,
+        options: &BuildOptions,
+    ) -> TreeResult<(EnvGraph, Vec<WalkIssue>)> {
+        let (parents_of, issues) = dependency_graph(dir, policy, options)?;
+        Ok((EnvGraph::from_parents(&parents_of), issues))
+    }
+
+    pub fn from_parents(parents_of: &ParentMap) -> EnvGraph {
+        let files: Vec<PathBuf> = parents_of.keys().chain(parents_of.values().flatten())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .cloned()
+            .collect();
+        let index: HashMap<PathBuf, usize> = files.iter().enumerate().map(|(i, file)| (file.clone(), i)).collect();
+        let mut parents = vec![Vec::new(); files.len()];
+        let mut children = vec![Vec::new(); files.len()];
+        for (file, file_parents) in parents_of {
+            let child = index[file];
+            for parent in file_parents {
+                parents[child].push(index[parent]);
+                children[index[parent]].push(child);
+            }
+        }
+        for list in &mut children {
+            list.sort_unstable();
+            list.dedup();
+        }
+        EnvGraph { files, index, parents, children }
+    }
+
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    pub fn contains(&self, file: &Path) -> bool {
+        self.id(file).is_some()
+    }
+
+    /// Files without parents.
+    pub fn roots(&self) -> Vec<&Path> {
+        self.select(|id| self.parents[id].is_empty())
+    }
+
+    /// Files without children.
+    pub fn leaves(&self) -> Vec<&Path> {
+        self.select(|id| self.children[id].is_empty())
+    }
+
+    /// Direct parents of `file` in declaration order, empty if it is not part of the graph.
+    pub fn parents(&self, file: &Path) -> Vec<&Path> {
+        self.id(file).map(|id| self.paths(&self.parents[id])).unwrap_or_default()
+    }
+
+    /// Direct children of `file`.
+    pub fn children(&self, file: &Path) -> Vec<&Path> {
+        self.id(file).map(|id| self.paths(&self.children[id])).unwrap_or_default()
+    }
+
+    /// All files `file` inherits from, nearest first, each once.
+    pub fn ancestors(&self, file: &Path) -> Vec<&Path> {
+        self.reachable(file, &self.parents)
+    }
+
+    /// All files inheriting from `file`, nearest first, each once.
+    pub fn descendants(&self, file: &Path) -> Vec<&Path> {
+        self.reachable(file, &self.children)
+    }
+
+    /// All files with every parent before its children. Fails with
+    /// `TreeError::CyclicDependency` if the files reference each other in a circle.
+    pub fn topological_order(&self) -> TreeResult<Vec<&Path>> {
+        let mut missing_parents: Vec<usize> = self.parents.iter().map(|parents| parents.len()).collect();
+        let mut ready: BTreeSet<usize> = (0..self.files.len()).filter(|&id| missing_parents[id] == 0).collect();
+        let mut order = Vec::with_capacity(self.files.len());
+        while let Some(id) = ready.pop_first() {
+            order.push(id);
+            for &child in &self.children[id] {
+                // a parent may be declared more than once
+                missing_parents[child] -= self.parents[child].iter().filter(|&&p| p == id).count();
+                if missing_parents[child] == 0 {
+                    ready.insert(child);
+                }
+            }
+        }
+        if order.len() < self.files.len() {
+            let start = (0..self.files.len()).find(|&id| missing_parents[id] > 0).unwrap_or_default();
+            return Err(TreeError::CyclicDependency { path: self.find_cycle(start, &missing_parents) });
+        }
+        Ok(self.paths(&order))
+    }
+
+    /// Follows unresolved parents from `start` until a file repeats, all files of the cycle
+    /// have unresolved parents within it.
+    fn find_cycle(&self, start: usize, missing_parents: &[usize]) -> Vec<PathBuf> {
+        let mut path = vec![start];
+        loop {
+            let current = path[path.len() - 1];
+            let next = self.parents[current].iter().copied()
+                .find(|&parent| missing_parents[parent] > 0)
+                .unwrap_or(start);
+            if let Some(position) = path.iter().position(|&id| id == next) {
+                let mut cycle: Vec<PathBuf> = path[position..].iter().map(|&id| self.files[id].clone()).collect();
+                cycle.push(self.files[next].clone());
+                return cycle;
+            }
+            path.push(next);
+        }
+    }
+
+    fn id(&self, file: &Path) -> Option<usize> {
+        self.index.get(file).or_else(|| self.index.get(&file.to_canonical().ok()?)).copied()
+    }
+
+    fn paths(&self, ids: &[usize]) -> Vec<&Path> {
+        ids.iter().map(|&id| self.files[id].as_path()).collect()
+    }
+
+    fn select(&self, predicate: impl Fn(usize) -> bool) -> Vec<&Path> {
+        (0..self.files.len()).filter(|&id| predicate(id)).map(|id| self.files[id].as_path()).collect()
+    }
+
+    fn reachable(&self, file: &Path, edges: &[Vec<usize>]) -> Vec<&Path> {
+        let Some(start) = self.id(file) else { return Vec::new() };
+        let mut seen = vec![false; self.files.len()];
+        seen[start] = true;
+        let mut queue = VecDeque::from([start]);
+        let mut found = Vec::new();
+        while let Some(id) = queue.pop_front() {
+            for &next in &edges[id] {
+                if !std::mem::replace(&mut seen[next], true) {
+                    found.push(next);
+                    queue.push_back(next);
+                }
+            }
+        }
+        self.paths(&found)
+    }
+}
+
+/// Renders `parents_of` as a graph with edges from parent to child. Files with several parents
+/// are highlighted. Paths are rendered with `show_file`.
+pub fn render_graph(
+    parents_of: &ParentMap,
+    format: GraphFormat,
+    show_file: impl Fn(&Path) -> String,
+) -> String {
+    let nodes: BTreeSet<&PathBuf> = parents_of.keys().chain(parents_of.values().flatten()).collect();
+    let has_several_parents = |file: &PathBuf| parents_of.get(file).is_some_and(|parents| parents.len() > 1);
+    let mut out = String::new();
+    match format {
+        GraphFormat::Dot => {
+            let quote = |file: &Path| format!("\"{}\"", show_file(file).replace('\\', "\\\\").replace('"', "\\\""));
+            out.push_str("digraph rsenv {\n    node [shape=box];\n");
+            for node in &nodes {
+                if has_several_parents(node) {
+                    out.push_str(&format!("    {} [style=filled, fillcolor=lightyellow];\n", quote(node)));
+                } else {
+                    out.push_str(&format!("    {};\n", quote(node)));
+                }
+            }
+            for (file, parents) in parents_of {
+                for parent in parents {
+                    out.push_str(&format!("    {} -> {};\n", quote(parent), quote(file)));
+                }
+            }
+            out.push_str("}\n");
+        }
+        GraphFormat::Mermaid => {
+            // mermaid ids cannot hold paths, nodes are numbered and labeled with the path instead
+            let ids: BTreeMap<&PathBuf, String> = nodes.iter().enumerate().map(|(i, node)| (*node, format!("n{}", i))).collect();
+            out.push_str("graph TD\n");
+            for (node, id) in &ids {
+                let label = show_file(node).replace('"', "#quot;");
+                let class = if has_several_parents(node) { ":::dag" } else { "" };
+                out.push_str(&format!("    {}[\"{}\"]{}\n", id, label, class));
+            }
+            for (file, parents) in parents_of {
+                for parent in parents {
+                    out.push_str(&format!("    {} --> {}\n", ids[parent], ids[file]));
+                }
+            }
+            out.push_str("    classDef dag fill:#ffffe0\n");
+        }
+    }
+    out
+}
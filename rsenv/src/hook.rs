@@ -0,0 +1,144 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::instrument;
+
+use crate::envrc::find_sections;
+use crate::errors::{TreeError, TreeResult};
+use crate::util::file::read_lines;
+use crate::util::hash::fnv1a_hex;
+use crate::util::path::PathExt;
+
+/// Shells `hook_script` supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum HookShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Prints the managed rsenv section of `./.envrc` if it was allowed, see `allowed_section`.
+const SECTION: &str = "rsenv hook-section";
+
+const POSIX_HOOK: &str = r#"# Loads the rsenv section of .envrc once it was approved with `rsenv allow`. A plain section
+# replays the variables of the last `rsenv envrc`, only `rsenv envrc --cached` rebuilds them.
+_rsenv_hook() {
+    [ "$PWD" = "${_RSENV_HOOK_DIR:-}" ] && return
+    _RSENV_HOOK_DIR="$PWD"
+    local section
+    section="$(@SECTION@)"
+    [ -n "$section" ] || return
+    if eval "$section"; then
+        echo "rsenv: loaded $PWD/.envrc" >&2
+    else
+        echo "rsenv: cannot load $PWD/.envrc" >&2
+    fi
+}
+"#;
+
+const BASH_REGISTRATION: &str = r#"if [[ ";${PROMPT_COMMAND[*]:-};" != *";_rsenv_hook;"* ]]; then
+    PROMPT_COMMAND="_rsenv_hook${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+fi
+"#;
+
+const ZSH_REGISTRATION: &str = r#"autoload -Uz add-zsh-hook
+add-zsh-hook chpwd _rsenv_hook
+_rsenv_hook
+"#;
+
+// fish cannot evaluate the POSIX section, it is run by sh and the changed variables are taken
+// over, except the ones sh maintains itself
+const FISH_HOOK: &str = r#"# Loads the rsenv section of .envrc once it was approved with `rsenv allow`. A plain section
+# replays the variables of the last `rsenv envrc`, only `rsenv envrc --cached` rebuilds them.
+function _rsenv_hook --on-variable PWD
+    set -l section (@SECTION@ | string collect)
+    test -n "$section"; or return
+    set -l variables (sh -c 'eval "$1" >&2 && env -0' sh "$section" | string split0)
+    or begin
+        echo "rsenv: cannot load $PWD/.envrc" >&2
+        return 1
+    end
+    for variable in $variables
+        set -l parts (string split -m 1 = -- $variable)
+        set -l name $parts[1]
+        contains -- $name PWD OLDPWD SHLVL _; and continue
+        if not set -q $name; or test "$$name" != "$parts[2]"
+            set -gx $name $parts[2]
+        end
+    end
+    echo "rsenv: loaded $PWD/.envrc" >&2
+end
+_rsenv_hook
+"#;
+
+/// Shell code loading the managed rsenv section of `.envrc` whenever the shell enters a
+/// directory, an alternative to direnv. Meant to be evaluated in the shell's startup file,
+/// e.g. `eval "$(rsenv hook bash)"` or `rsenv hook fish | source`.
+///
+/// Only sections approved with `allow_section` are loaded, see `allowed_section`.
+///
+/// Sections written by `rsenv envrc --cached` rebuild the environment when one of its files
+/// changed. Plain sections hold the variables of the last `rsenv envrc` and only replay them,
+/// changes of the env files are not picked up until `rsenv envrc` runs again.
+pub fn hook_script(shell: HookShell) -> String {
+    let script = match shell {
+        HookShell::Bash => format!("{}{}", POSIX_HOOK, BASH_REGISTRATION),
+        HookShell::Zsh => format!("{}{}", POSIX_HOOK, ZSH_REGISTRATION),
+        HookShell::Fish => FISH_HOOK.to_string(),
+    };
+    script.replace("@SECTION@", SECTION)
+}
+
+/// Managed rsenv section of the `.envrc` in `dir` including its delimiters, as the hook
+/// evaluates it. `None` if there is no `.envrc` or it has no section.
+#[instrument(level = "debug")]
+pub fn envrc_section(dir: &Path) -> TreeResult<Option<String>> {
+    let envrc = dir.join(".envrc");
+    if !envrc.is_file() {
+        return Ok(None);
+    }
+    let lines = read_lines(&envrc)?;
+    Ok(find_sections(&lines).first().map(|&(start, end)| lines[start..=end].join("\n") + "\n"))
+}
+
+/// Approves the current rsenv section of the `.envrc` in `dir` for the shell hook, by
+/// storing its hash in `${XDG_DATA_HOME:-$HOME/.local/share}/rsenv/allow`. Any later change
+/// of the section needs a new approval. Returns the approved `.envrc`.
+#[instrument(level = "debug")]
+pub fn allow_section(dir: &Path) -> TreeResult<PathBuf> {
+    let envrc = dir.join(".envrc");
+    let section = envrc_section(dir)?.ok_or_else(|| TreeError::InvalidFormat {
+        path: envrc.clone(),
+        reason: "no rsenv section".to_string(),
+    })?;
+    let approval = approval_file(dir)?;
+    if let Some(parent) = approval.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&approval, fnv1a_hex(section.as_bytes()))?;
+    Ok(envrc)
+}
+
+/// The rsenv section of the `.envrc` in `dir` if it is unchanged since `allow_section`, `None`
+/// if there is none. Fails with `EnvrcNotAllowed` if it was not approved or changed since.
+#[instrument(level = "debug")]
+pub fn allowed_section(dir: &Path) -> TreeResult<Option<String>> {
+    let Some(section) = envrc_section(dir)? else { return Ok(None) };
+    let approved = fs::read_to_string(approval_file(dir)?).unwrap_or_default();
+    if approved.trim() != fnv1a_hex(section.as_bytes()) {
+        return Err(TreeError::EnvrcNotAllowed(dir.join(".envrc")));
+    }
+    Ok(Some(section))
+}
+
+/// Approval of the section of `dir`, named by the hash of its canonical path.
+fn approval_file(dir: &Path) -> TreeResult<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .ok_or_else(|| TreeError::InternalError("Neither XDG_DATA_HOME nor HOME is set".to_string()))?;
+    let dir = dir.to_canonical()?;
+    Ok(data_home.join("rsenv/allow").join(fnv1a_hex(dir.to_utf8_str()?.as_bytes())))
+}
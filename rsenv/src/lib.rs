@@ -1,14 +1,13 @@
 use std::path::{Path, PathBuf};
-use std::collections::BTreeMap;
-use std::fs::{File, symlink_metadata};
-use std::io::{BufRead, BufReader};
-use std::env;
+use std::collections::{BTreeMap, HashMap};
 
 use regex::Regex;
-use tracing::{debug, instrument};
-use walkdir::WalkDir;
+use tracing::{debug, instrument, warn};
 use crate::errors::{TreeError, TreeResult};
-use crate::util::path::{ensure_file_exists, PathExt};
+use crate::util::file::{read_dir_files, read_text_file, write_text_file, WalkIssue, WalkPolicy};
+use crate::util::path::{ensure_file_exists, ensure_within, PathExt};
+use crate::format::late_parent_declaration;
+use crate::reader::{EnvReader, WorkTree};
 
 pub mod envrc;
 pub mod edit;
@@ -18,6 +17,33 @@ pub mod util;
 pub mod errors;
 pub mod builder;
 pub mod arena;
+pub mod optimize;
+pub mod diff;
+pub mod diagnostics;
+pub mod migrate;
+pub mod capabilities;
+pub mod git;
+pub mod cache;
+pub mod container;
+pub mod batch;
+pub mod procenv;
+pub mod ci;
+pub mod capture;
+pub mod secrets;
+pub mod sops;
+pub mod watch;
+pub mod format;
+pub mod lint;
+pub mod graph;
+pub mod hook;
+pub mod promote;
+pub mod reader;
+pub mod schema;
+pub mod export;
+#[cfg(feature = "async")]
+pub mod async_api;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
 
 #[instrument(level = "trace")]
 pub fn get_files(file_path: &Path) -> TreeResult<Vec<PathBuf>> {
@@ -26,6 +52,14 @@ pub fn get_files(file_path: &Path) -> TreeResult<Vec<PathBuf>> {
     Ok(files)
 }
 
+/// Same as `get_files`, with explicit `BuildOptions`.
+#[instrument(level = "trace")]
+pub fn get_files_with_options(file_path: &Path, options: &BuildOptions) -> TreeResult<Vec<PathBuf>> {
+    ensure_file_exists(file_path)?;
+    let (_, files, _) = build_env_with_options(file_path, options)?;
+    Ok(files)
+}
+
 #[instrument(level = "trace")]
 pub fn print_files(file_path: &Path) -> TreeResult<()> {
     let files = get_files(file_path)?;
@@ -39,45 +73,137 @@ pub fn print_files(file_path: &Path) -> TreeResult<()> {
 pub fn build_env_vars(file_path: &Path) -> TreeResult<String> {
     ensure_file_exists(file_path)?;
 
-    let mut env_vars = String::new();
     let (variables, _, _) = build_env(file_path)?;
+    Ok(format_env_vars(&variables))
+}
+
+/// Builds the environment like `build_env_vars`, but prefixes every variable name with `prefix`.
+///
+/// With `rewrite_refs`, references like `$VAR` or `${VAR}` to variables of the hierarchy are
+/// renamed as well, so values interpolated by the shell keep pointing at the prefixed names.
+#[instrument(level = "trace")]
+pub fn build_env_vars_with_prefix(file_path: &Path, prefix: &str, rewrite_refs: bool) -> TreeResult<String> {
+    ensure_file_exists(file_path)?;
 
+    let (variables, _, _) = build_env(file_path)?;
+    Ok(format_env_vars(&prefix_env_vars(variables, prefix, rewrite_refs)))
+}
+
+/// Prefixes all variable names, optionally rewriting references between them.
+pub fn prefix_env_vars(variables: BTreeMap<String, String>, prefix: &str, rewrite_refs: bool) -> BTreeMap<String, String> {
+    let re = Regex::new(r"\$(\{)?([A-Za-z_][A-Za-z0-9_]*)").unwrap();
+    let keys: Vec<String> = variables.keys().cloned().collect();
+
+    variables.into_iter()
+        .map(|(k, v)| {
+            let v = if rewrite_refs {
+                re.replace_all(&v, |caps: &regex::Captures| {
+                    let name = &caps[2];
+                    if keys.iter().any(|k| k == name) {
+                        format!("${}{}{}", caps.get(1).map_or("", |m| m.as_str()), prefix, name)
+                    } else {
+                        caps[0].to_string()
+                    }
+                }).into_owned()
+            } else {
+                v
+            };
+            (format!("{}{}", prefix, k), v)
+        })
+        .collect()
+}
+
+pub fn format_env_vars(variables: &BTreeMap<String, String>) -> String {
+    let mut env_vars = String::new();
     for (k, v) in variables {
         env_vars.push_str(&format!("export {}={}\n", k, v));
     }
-
-    Ok(env_vars)
+    env_vars
 }
 
 #[instrument(level = "trace")]
 pub fn is_dag(dir_path: &Path) -> TreeResult<bool> {
+    let (is_dag, issues) = is_dag_with_policy(dir_path, WalkPolicy::default())?;
+    for issue in issues {
+        warn!("Skipped unreadable file {}: {}", issue.path.display(), issue.reason);
+    }
+    Ok(is_dag)
+}
+
+/// Checks whether any file below `dir_path` declares more than one parent.
+///
+/// Unreadable files are handled according to `policy` and reported as issues.
+#[instrument(level = "trace")]
+pub fn is_dag_with_policy(dir_path: &Path, policy: WalkPolicy) -> TreeResult<(bool, Vec<WalkIssue>)> {
     let re = Regex::new(r"# rsenv: (.+)")
         .map_err(|e| TreeError::InternalError(e.to_string()))?;
 
-    // Walk through each file in the directory
-    for entry in WalkDir::new(dir_path) {
-        let entry = entry.map_err(|e| TreeError::PathResolution {
-            path: dir_path.to_path_buf(),
-            reason: e.to_string(),
-        })?;
-
-        if entry.file_type().is_file() {
-            let file = File::open(entry.path())
-                .map_err(TreeError::FileReadError)?;
-            let reader = BufReader::new(file);
-
-            for line in reader.lines() {
-                let line = line.map_err(TreeError::FileReadError)?;
-                if let Some(caps) = re.captures(&line) {
-                    let parent_references: Vec<&str> = caps[1].split_whitespace().collect();
-                    if parent_references.len() > 1 {
-                        return Ok(true);
-                    }
+    let (files, issues) = read_dir_files(dir_path, policy)?;
+    for (_, lines) in files {
+        for line in lines {
+            if let Some(caps) = re.captures(&line) {
+                let parent_references = split_parent_specs(&caps[1]);
+                if parent_references.len() > 1 {
+                    return Ok((true, issues));
                 }
             }
         }
     }
-    Ok(false)
+    Ok((false, issues))
+}
+
+/// Default for `BuildOptions::max_depth`.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// Options controlling how `build_env_with_options` traverses the hierarchy.
+#[derive(Debug, Clone)]
+pub struct BuildOptions {
+    /// Maximum number of files in a chain from the leaf up to a root (the leaf counts as 1)
+    pub max_depth: usize,
+    /// Normalization of values, applied to every file right after parsing
+    pub values: ValueMode,
+    /// Refuse to read files outside this directory, symlinks are resolved before the check
+    pub confine_to: Option<PathBuf>,
+    /// Reject files declaring parents below their first variable, see `format::format_env_file`
+    pub strict_header: bool,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_DEPTH,
+            values: ValueMode::default(),
+            confine_to: None,
+            strict_header: false,
+        }
+    }
+}
+
+/// How values are normalized when a file is parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ValueMode {
+    /// Keep values exactly as written, including surrounding whitespace and empty values
+    #[default]
+    Verbatim,
+    /// Remove whitespace around values
+    Trim,
+    /// Remove whitespace around values and drop variables with empty values (`FOO=`, `FOO=""`).
+    /// A dropped variable does not override the value of a parent.
+    DropEmpty,
+}
+
+impl ValueMode {
+    pub fn apply(self, variables: BTreeMap<String, String>) -> BTreeMap<String, String> {
+        match self {
+            ValueMode::Verbatim => variables,
+            ValueMode::Trim => variables.into_iter().map(|(k, v)| (k, v.trim().to_string())).collect(),
+            ValueMode::DropEmpty => variables
+                .into_iter()
+                .map(|(k, v)| (k, v.trim().to_string()))
+                .filter(|(_, v)| !matches!(v.as_str(), "" | "\"\"" | "''"))
+                .collect(),
+        }
+    }
 }
 
 /// Recursively builds map of environment variables from the specified file and its parents.
@@ -88,55 +214,281 @@ pub fn is_dag(dir_path: &Path) -> TreeResult<bool> {
 ///
 /// child wins against parent
 /// rightmost sibling wins
+///
+/// The returned files are ordered as described in `order_files`, independent of precedence.
+///
+/// A file shared by several children is read once. A file which is its own ancestor fails
+/// with `TreeError::CyclicDependency`.
+///
+/// No process-wide state like the current working directory is touched, so builds can run
+/// concurrently from several threads.
 #[instrument(level = "debug")]
 pub fn build_env(file_path: &Path) -> TreeResult<(BTreeMap<String, String>, Vec<PathBuf>, bool)> {
-    warn_if_symlink(file_path)?;
-    let file_path = file_path.to_canonical()?;
-    ensure_file_exists(&file_path)?;
+    build_env_with_options(file_path, &BuildOptions::default())
+}
+
+/// Same as `build_env`, with explicit `BuildOptions`.
+#[instrument(level = "debug")]
+pub fn build_env_with_options(
+    file_path: &Path,
+    options: &BuildOptions,
+) -> TreeResult<(BTreeMap<String, String>, Vec<PathBuf>, bool)> {
+    build_env_with_progress(file_path, options, |_| {})
+}
+
+/// Same as `build_env_with_sources`, with `overrides` layered on top of the hierarchy.
+/// Overrides win against every file and are recorded with `ValueSource::Override`, nothing is
+/// written to disk.
+#[instrument(level = "debug")]
+pub fn build_env_with_overrides(
+    file_path: &Path,
+    options: &BuildOptions,
+    overrides: &BTreeMap<String, String>,
+) -> TreeResult<BTreeMap<String, SourcedValue>> {
+    let mut sourced = build_env_with_sources(file_path, options)?;
+    sourced.extend(overrides.iter().map(|(k, v)| {
+        (k.clone(), SourcedValue { value: v.clone(), source: ValueSource::Override })
+    }));
+    Ok(sourced)
+}
+
+/// Where the value of a built variable was taken from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueSource {
+    /// A file of the hierarchy
+    File(PathBuf),
+    /// An override of `build_env_with_overrides`, not backed by any file
+    Override,
+}
+
+impl ValueSource {
+    /// The file, `None` for an override.
+    pub fn file(&self) -> Option<&Path> {
+        match self {
+            ValueSource::File(file) => Some(file),
+            ValueSource::Override => None,
+        }
+    }
+}
+
+/// Value of a built variable together with where it was taken from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourcedValue {
+    pub value: String,
+    pub source: ValueSource,
+}
+
+/// Same as `build_env_with_options`, but records for every variable the file which won.
+#[instrument(level = "debug")]
+pub fn build_env_with_sources(file_path: &Path, options: &BuildOptions) -> TreeResult<BTreeMap<String, SourcedValue>> {
+    let (sourced, _, _) = build_sourced_with_reader(file_path, options, &mut WorkTree::new(), |_| {})?;
+    Ok(sourced.into_iter()
+        .map(|(k, (value, file))| (k, SourcedValue { value, source: ValueSource::File(file) }))
+        .collect())
+}
+
+/// Decision of the `on_variable` hook of `build_env_with_hook`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VariableAction {
+    /// Take the variable as built
+    Keep,
+    /// Take the variable with a different name and/or value
+    Replace { name: String, value: String },
+    /// Leave the variable out
+    Drop,
+}
+
+/// Same as `build_env_with_sources`, but passes every built variable with the file it was
+/// taken from to `on_variable`, which may keep, transform or veto it. Variables are passed in
+/// name order; if replacements collide, the later one wins.
+#[instrument(level = "debug", skip(on_variable))]
+pub fn build_env_with_hook(
+    file_path: &Path,
+    options: &BuildOptions,
+    mut on_variable: impl FnMut(&str, &str, &Path) -> VariableAction,
+) -> TreeResult<BTreeMap<String, String>> {
+    let (sourced, _, _) = build_sourced_with_reader(file_path, options, &mut WorkTree::new(), |_| {})?;
+    let mut variables = BTreeMap::new();
+    for (name, (value, file)) in sourced {
+        match on_variable(&name, &value, &file) {
+            VariableAction::Keep => variables.insert(name, value),
+            VariableAction::Replace { name, value } => variables.insert(name, value),
+            VariableAction::Drop => None,
+        };
+    }
+    Ok(variables)
+}
+
+/// Builds the environment of `file_path` as JSON object mapping every variable to its
+/// `value` and the `file` it was taken from.
+#[instrument(level = "debug")]
+pub fn build_env_json(file_path: &Path) -> TreeResult<serde_json::Value> {
+    ensure_file_exists(file_path)?;
+    let sourced = build_env_with_sources(file_path, &BuildOptions::default())?;
+    Ok(sourced_to_json(&sourced, |file| file.display().to_string()))
+}
+
+/// JSON object of `sourced` variables, with files formatted by `show_file`, `null` for overrides.
+pub fn sourced_to_json(
+    sourced: &BTreeMap<String, SourcedValue>,
+    show_file: impl Fn(&Path) -> String,
+) -> serde_json::Value {
+    let mut document = serde_json::Map::new();
+    for (key, sourced_value) in sourced {
+        document.insert(key.clone(), serde_json::json!({
+            "value": sourced_value.value,
+            "file": sourced_value.source.file().map(&show_file),
+        }));
+    }
+    serde_json::Value::Object(document)
+}
+
+/// Same as `build_env_with_options`, but calls `on_file` for every file right before it is read.
+#[instrument(level = "debug", skip(on_file))]
+pub fn build_env_with_progress(
+    file_path: &Path,
+    options: &BuildOptions,
+    on_file: impl FnMut(&Path),
+) -> TreeResult<(BTreeMap<String, String>, Vec<PathBuf>, bool)> {
+    build_env_with_reader(file_path, options, &mut WorkTree::new(), on_file)
+}
+
+/// Same as `build_env_with_progress`, but reads the files through `reader`, e.g. from a past
+/// git revision instead of the working tree.
+#[instrument(level = "debug", skip(reader, on_file))]
+pub fn build_env_with_reader(
+    file_path: &Path,
+    options: &BuildOptions,
+    reader: &mut impl EnvReader,
+    on_file: impl FnMut(&Path),
+) -> TreeResult<(BTreeMap<String, String>, Vec<PathBuf>, bool)> {
+    let (sourced, files, is_dag) = build_sourced_with_reader(file_path, options, reader, on_file)?;
+    let variables = sourced.into_iter().map(|(k, (value, _))| (k, value)).collect();
+    Ok((variables, files, is_dag))
+}
+
+/// Built values with the file each was taken from.
+type FileValues = BTreeMap<String, (String, PathBuf)>;
+
+/// Traversal behind `build_env_with_reader`, recording the file every value was taken from.
+fn build_sourced_with_reader(
+    file_path: &Path,
+    options: &BuildOptions,
+    reader: &mut impl EnvReader,
+    mut on_file: impl FnMut(&Path),
+) -> TreeResult<(FileValues, Vec<PathBuf>, bool)> {
+    let file_path = reader.resolve(file_path)?;
     debug!("Current file_path: {:?}", file_path);
 
-    let mut variables: BTreeMap<String, String> = BTreeMap::new();
+    let mut variables = FileValues::new();
     let mut files_read: Vec<PathBuf> = Vec::new();
     let mut is_dag = false;
 
-    let mut to_read_files: Vec<PathBuf> = vec![file_path];
-
-    while let Some(current_file) = to_read_files.pop() {
-        ensure_file_exists(&current_file)?;
+    // child which first referenced a file, to report the traversal path
+    let mut referenced_by: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let root = options.confine_to.as_deref().map(Path::to_canonical).transpose()?;
+    let mut parents_of: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    // every file comes with its chain of children up to the leaf, to tell cycles from diamonds
+    let mut to_read_files: Vec<(PathBuf, Vec<PathBuf>)> = vec![(file_path.clone(), Vec::new())];
+
+    while let Some((current_file, children)) = to_read_files.pop() {
+        let depth = children.len() + 1;
+        current_file.to_utf8_str()?;
+        if let Some(root) = &root {
+            // resolved files are canonical, so comparing components is enough
+            if !current_file.starts_with(root) {
+                return Err(TreeError::OutsideProject {
+                    path: current_file.clone(),
+                    root: root.clone(),
+                    referenced_by: referenced_by.get(&current_file).cloned(),
+                });
+            }
+        }
         if files_read.contains(&current_file) {
             continue;
         }
+        if depth > options.max_depth {
+            let mut path = vec![current_file.clone()];
+            while let Some(child) = referenced_by.get(path.last().unwrap()) {
+                path.push(child.clone());
+            }
+            path.reverse();
+            return Err(TreeError::HierarchyTooDeep { limit: options.max_depth, path });
+        }
 
         files_read.push(current_file.clone());
 
-        let (vars, parents) = extract_env(&current_file)?;
+        on_file(&current_file);
+        let lines = reader.read_lines(&current_file)?;
+        if options.strict_header {
+            if let Some(index) = late_parent_declaration(&lines) {
+                return Err(TreeError::InvalidFormat {
+                    path: current_file,
+                    reason: format!("parent declaration in line {} is below the first variable", index + 1),
+                });
+            }
+        }
+        let (vars, parents) = parse_env_file(&current_file, &lines, reader, root.as_deref())?;
+        let vars = options.values.apply(vars);
         is_dag = is_dag || parents.len() > 1;
 
         debug!("vars: {:?}, parents: {:?}, is_dag: {:?}", vars, parents, is_dag);
 
         for (k, v) in vars {
-            variables.entry(k).or_insert(v);  // first entry wins
+            // first entry wins
+            variables.entry(k).or_insert_with(|| (v, current_file.clone()));
         }
 
-        for parent in parents {
-            to_read_files.push(parent);
+        let mut chain = children;
+        chain.push(current_file.clone());
+        for parent in &parents {
+            if let Some(start) = chain.iter().position(|file| file == parent) {
+                let mut path = chain[start..].to_vec();
+                path.push(parent.clone());
+                return Err(TreeError::CyclicDependency { path });
+            }
+            referenced_by.entry(parent.clone()).or_insert_with(|| current_file.clone());
+            to_read_files.push((parent.clone(), chain.clone()));
         }
+        parents_of.insert(current_file, parents);
     }
 
-    Ok((variables, files_read, is_dag))
+    Ok((variables, order_files(&file_path, &parents_of), is_dag))
+}
+
+/// Orders the files of a hierarchy: the leaf first, then depth-first the parents of each file
+/// in declaration order (ascending priority if priorities are given). A file reachable via
+/// several children is listed once, at its first occurrence.
+///
+/// This is a stable listing order, not the precedence order: the rightmost parent still wins.
+pub(crate) fn order_files(leaf: &Path, parents_of: &HashMap<PathBuf, Vec<PathBuf>>) -> Vec<PathBuf> {
+    let mut ordered: Vec<PathBuf> = Vec::new();
+    let mut stack = vec![leaf.to_path_buf()];
+    while let Some(file) = stack.pop() {
+        if ordered.contains(&file) {
+            continue;
+        }
+        if let Some(parents) = parents_of.get(&file) {
+            stack.extend(parents.iter().rev().cloned());
+        }
+        ordered.push(file);
+    }
+    ordered
 }
 
 /// Extracts environment variables and the parent path from a specified file.
 ///
 /// This function reads the given `file_path` to:
 ///
-/// 1. Identify and extract environment variables specified using the `export` keyword.
+/// 1. Identify and extract environment variables specified using the `export` keyword or as
+///    plain dotenv assignment (`KEY=value`).
 /// 2. Identify any parent environment file via the special `# rsenv:` comment.
 ///    parent's path can be relative to the child's path.
 ///
-/// The current working directory is temporarily changed to the directory of the `file_path`
-/// during the extraction process to construct correct parent paths. It is restored
-/// afterward.
+/// SOPS-encrypted files are decrypted transparently, see `sops::read_env_lines`.
+///
+/// Relative parent paths are resolved against the directory of `file_path`. The current
+/// working directory is not touched, so files can be extracted concurrently.
 ///
 /// # Arguments
 ///
@@ -162,72 +514,268 @@ pub fn build_env(file_path: &Path) -> TreeResult<(BTreeMap<String, String>, Vec<
 /// * The parent path specified in `# rsenv:` is invalid or not specified properly.
 #[instrument(level = "debug")]
 pub fn extract_env(file_path: &Path) -> TreeResult<(BTreeMap<String, String>, Vec<PathBuf>)> {
-    warn_if_symlink(file_path)?;
-    let file_path = file_path.to_canonical()?;
-    debug!("Current file_path: {:?}", file_path);
+    extract_env_with(file_path, &mut WorkTree::new())
+}
 
-    // Save the original current directory, to restore it later
-    let original_dir = env::current_dir()
-        .map_err(|e| TreeError::InternalError(format!("Failed to get current dir: {}", e)))?;
+/// Same as `extract_env`, reading and resolving files through `reader`, which is shared across
+/// the files of one operation.
+pub(crate) fn extract_env_with(
+    file_path: &Path,
+    reader: &mut impl EnvReader,
+) -> TreeResult<(BTreeMap<String, String>, Vec<PathBuf>)> {
+    let file_path = reader.resolve(file_path)?;
+    debug!("Current file_path: {:?}", file_path);
+    let lines = reader.read_lines(&file_path)?;
+    parse_env_file(&file_path, &lines, reader, None)
+}
 
-    // Change the current directory in order to construct correct parent path
+/// Parses the `lines` of the resolved `file_path` into its variables and resolved parents.
+///
+/// Value files of `# rsenv-file:` directives are resolved and read through `reader`, so they
+/// are taken from the same source as the env files. With `root`, value files must be inside it.
+fn parse_env_file(
+    file_path: &Path,
+    lines: &[String],
+    reader: &mut impl EnvReader,
+    root: Option<&Path>,
+) -> TreeResult<(BTreeMap<String, String>, Vec<PathBuf>)> {
     let parent_dir = file_path.parent()
-        .ok_or_else(|| TreeError::InvalidParent(file_path.clone()))?;
-    env::set_current_dir(parent_dir)
-        .map_err(|e| TreeError::InternalError(format!("Failed to change dir: {}", e)))?;
-
-    debug!("Current directory: {:?}", env::current_dir().unwrap_or_default());
-
-    let file = File::open(&file_path)
-        .map_err(TreeError::FileReadError)?;
-    let reader = BufReader::new(file);
-
-    let mut variables: BTreeMap<String, String> = BTreeMap::new();
+        .ok_or_else(|| TreeError::InvalidParent(file_path.to_path_buf()))?;
+
+    let parsed = parse_env_lines(lines)
+        .map_err(|reason| TreeError::InvalidFormat { path: file_path.to_path_buf(), reason })?;
+    let mut variables = parsed.variables;
+    for (key, value_file) in parsed.value_files {
+        let value_path = reader.resolve(&parent_dir.join(&value_file))?;
+        if let Some(root) = root {
+            if !value_path.starts_with(root) {
+                return Err(TreeError::OutsideProject {
+                    path: value_path,
+                    root: root.to_path_buf(),
+                    referenced_by: Some(file_path.to_path_buf()),
+                });
+            }
+        }
+        variables.insert(key, reader.read_lines(&value_path)?.join("\n"));
+    }
     let mut parent_paths: Vec<PathBuf> = Vec::new();
+    for parent in parsed.parents {
+        let parent_path = reader.resolve(&parent_dir.join(&parent))
+            .map_err(|_| TreeError::InvalidParent(PathBuf::from(&parent)))?;
+        parent_paths.push(parent_path);
+    }
+    debug!("parent_paths: {:?}", parent_paths);
 
-    for line in reader.lines() {
-        let line = line.map_err(TreeError::FileReadError)?;
+    Ok((variables, parent_paths))
+}
+
+/// Prefix of the directive taking the value of a variable from a file, e.g.
+/// `# rsenv-file: CERT=certs/ca.pem`. The file holds the value as it would be written after
+/// `=`, a trailing line break is ignored.
+pub const VALUE_FILE_PREFIX: &str = "# rsenv-file:";
+
+/// What `parse_env_lines` finds in the lines of an env file.
+#[derive(Debug, Default)]
+pub(crate) struct EnvLines {
+    /// Variables assigned in the file
+    pub variables: BTreeMap<String, String>,
+    /// Variables taking their value from a file, see `VALUE_FILE_PREFIX`, with the path as
+    /// written, i.e. relative to the file's directory
+    pub value_files: BTreeMap<String, String>,
+    /// Parent paths as written, in ascending precedence
+    pub parents: Vec<String>,
+}
 
+/// Parses the lines of an env file into its variables, value files and the parent paths as
+/// written in the `# rsenv:` comment, i.e. relative to the file's directory.
+///
+/// Parents are returned in ascending precedence, the last one wins. Without explicit
+/// priorities this is the written order (rightmost wins). With priorities, e.g.
+/// `# rsenv: base.env[10] region.env[20]`, the highest priority wins; either all or none of
+/// the parents must have one. A variable both assigned and taken from a file gets the later
+/// of both. Fails with the reason if a declaration is invalid.
+pub(crate) fn parse_env_lines(lines: &[String]) -> Result<EnvLines, String> {
+    let mut parsed = EnvLines::default();
+    let mut parents: Vec<(String, Option<u32>)> = Vec::new();
+
+    for line in lines {
         // Check for the rsenv comment
         if line.starts_with("# rsenv:") {
-            let parents: Vec<&str> = line.trim_start_matches("# rsenv:").split_whitespace().collect();
-            for parent in parents {
-                if !parent.is_empty() {
-                    let parent_path = PathBuf::from(parent).to_canonical()
-                        .map_err(|_| TreeError::InvalidParent(PathBuf::from(parent)))?;
-                    parent_paths.push(parent_path);
+            for spec in split_parent_specs(line.trim_start_matches("# rsenv:")) {
+                parents.push(parse_parent_spec(spec)?);
+            }
+        }
+
+        else if let Some(directive) = line.strip_prefix(VALUE_FILE_PREFIX) {
+            let (key, path) = directive.split_once('=')
+                .map(|(key, path)| (key.trim(), path.trim()))
+                .filter(|(key, path)| !key.is_empty() && !path.is_empty())
+                .ok_or_else(|| format!("invalid value file directive: {}", line))?;
+            parsed.variables.remove(key);
+            parsed.value_files.insert(key.to_string(), path.to_string());
+        }
+
+        // Check for an assignment, with or without export prefix
+        else if let Some(assignment) = strip_export(line) {
+            // only the first `=` separates, values like `abc==` or `a?b=c` are kept whole
+            if let Some((name, value)) = assignment.split_once('=') {
+                if let Some(var_name) = name.split_whitespace().next() {
+                    parsed.value_files.remove(var_name);
+                    parsed.variables.insert(var_name.to_string(), value.to_string());
                 }
             }
-            debug!("parent_paths: {:?}", parent_paths);
         }
+    }
 
-        // Check for the export prefix
-        else if line.starts_with("export ") {
-            let parts: Vec<&str> = line.split('=').collect();
-            if parts.len() > 1 {
-                let var_name: Vec<&str> = parts[0].split_whitespace().collect();
-                if var_name.len() > 1 {
-                    variables.insert(var_name[1].to_string(), parts[1].to_string());
+    let prioritized = parents.iter().filter(|(_, priority)| priority.is_some()).count();
+    if prioritized > 0 && prioritized < parents.len() {
+        return Err("either all or no parents must have a priority".to_string());
+    }
+    // stable, so equal priorities keep the written order
+    parents.sort_by_key(|(_, priority)| *priority);
+    parsed.parents = parents.into_iter().map(|(path, _)| path).collect();
+    Ok(parsed)
+}
+
+/// Returns the assignment of an `export KEY=value` line, or the whole line if it is a plain
+/// dotenv assignment (`KEY=value`, the name being a valid shell identifier).
+pub(crate) fn strip_export(line: &str) -> Option<&str> {
+    if let Some(assignment) = line.strip_prefix("export ") {
+        return Some(assignment);
+    }
+    let (name, _) = line.split_once('=')?;
+    let is_identifier = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    is_identifier.then_some(line)
+}
+
+/// Splits the parent references of a `# rsenv:` declaration at whitespace, except inside
+/// `${...}`, so `${DIR:?set DIR first}/base.env` stays one reference.
+pub(crate) fn split_parent_specs(specs: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut depth = 0;
+    let mut start = None;
+    for (index, c) in specs.char_indices() {
+        match c {
+            c if c.is_whitespace() && depth == 0 => {
+                if let Some(begin) = start.take() {
+                    result.push(&specs[begin..index]);
                 }
+                continue;
             }
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            _ => {}
         }
+        start.get_or_insert(index);
+    }
+    if let Some(begin) = start {
+        result.push(&specs[begin..]);
     }
+    result
+}
 
-    // After executing your code, restore the original current directory
-    env::set_current_dir(original_dir)
-        .map_err(|e| TreeError::InternalError(format!("Failed to restore dir: {}", e)))?;
+/// Splits a parent reference like `base.env[10]` into path and optional priority. Variables in
+/// the path are expanded, see `expand_env_vars`.
+pub(crate) fn parse_parent_spec(spec: &str) -> Result<(String, Option<u32>), String> {
+    let (path, priority) = match spec.strip_suffix(']').and_then(|s| s.rsplit_once('[')) {
+        Some((path, priority)) => priority.parse()
+            .map(|priority| (path, Some(priority)))
+            .map_err(|_| format!("invalid parent priority: {}", spec))?,
+        None => (spec, None),
+    };
+    let path = expand_env_vars(path).map_err(|e| e.to_string())?;
+    Ok((path, priority))
+}
 
-    Ok((variables, parent_paths))
+/// Expands environment variables like a POSIX shell: `$VAR` and `${VAR}` (empty if unset),
+/// `${VAR:-default}` (`default` if unset or empty) and `${VAR:?message}` (fails with
+/// `TreeError::VariableExpansion` if unset or empty). A `$` not followed by a name is kept.
+///
+/// Used for `# rsenv:` parent paths, e.g. `# rsenv: ${CONFIG_DIR:-../shared}/base.env`.
+pub fn expand_env_vars(input: &str) -> TreeResult<String> {
+    let mut expanded = String::new();
+    let mut rest = input;
+    while let Some(position) = rest.find('$') {
+        expanded.push_str(&rest[..position]);
+        let reference = &rest[position..];
+        if let Some(braced) = reference.strip_prefix("${") {
+            let end = closing_brace(braced).ok_or_else(|| TreeError::VariableExpansion {
+                reference: reference.to_string(),
+                reason: "missing closing brace".to_string(),
+            })?;
+            expanded.push_str(&expand_braced(&reference[..end + 3], &braced[..end])?);
+            rest = &braced[end + 1..];
+        } else {
+            let name_len = variable_name_len(&reference[1..]);
+            if name_len == 0 {
+                expanded.push('$');
+            } else {
+                expanded.push_str(&std::env::var(&reference[1..=name_len]).unwrap_or_default());
+            }
+            rest = &reference[1 + name_len..];
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
 }
 
-#[instrument(level = "trace")]
-fn warn_if_symlink(file_path: &Path) -> TreeResult<()> {
-    let metadata = symlink_metadata(file_path)
-        .map_err(TreeError::FileReadError)?;
-    if metadata.file_type().is_symlink() {
-        eprintln!("Warning: The file {} is a symbolic link.", file_path.display());
+/// Expands the `expression` inside `${...}`, `reference` is the whole text for errors.
+fn expand_braced(reference: &str, expression: &str) -> TreeResult<String> {
+    let error = |reason: &str| TreeError::VariableExpansion {
+        reference: reference.to_string(),
+        reason: reason.to_string(),
+    };
+    let (name, operation) = expression.split_at(variable_name_len(expression));
+    if name.is_empty() {
+        return Err(error("invalid variable name"));
+    }
+    let value = std::env::var(name).ok();
+    let set = value.clone().filter(|value| !value.is_empty());
+    if operation.is_empty() {
+        Ok(value.unwrap_or_default())
+    } else if let Some(default) = operation.strip_prefix(":-") {
+        set.map_or_else(|| expand_env_vars(default), Ok)
+    } else if let Some(message) = operation.strip_prefix(":?") {
+        let message = if message.is_empty() { "parameter null or not set" } else { message };
+        set.ok_or_else(|| error(message))
+    } else {
+        Err(error("unsupported expansion, use ${VAR}, ${VAR:-default} or ${VAR:?message}"))
     }
-    Ok(())
+}
+
+/// Index of the `}` closing an already opened brace, nested `${...}` are skipped.
+fn closing_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(index),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn variable_name_len(text: &str) -> usize {
+    if !text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        return 0;
+    }
+    text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(text.len())
+}
+
+/// What `link_with_policy` does when the child already has parents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OnExisting {
+    /// Replace the existing parents
+    #[default]
+    Replace,
+    /// Add the parent as rightmost sibling, so it wins against the existing parents. If they
+    /// have priorities, the parent gets one above the highest
+    Append,
+    /// Fail with `TreeError::ParentExists`
+    Abort,
 }
 
 /// Links a parent file to a child file by adding a special comment to the child file.
@@ -236,13 +784,18 @@ fn warn_if_symlink(file_path: &Path) -> TreeResult<()> {
 /// If the child file has multiple parents, the function will return an error.
 #[instrument(level = "debug")]
 pub fn link(parent: &Path, child: &Path) -> TreeResult<()> {
+    link_with_policy(parent, child, OnExisting::Replace)
+}
+
+/// Same as `link`, with `on_existing` deciding about parents the child already has.
+/// Linking a parent which is already linked leaves the child unchanged.
+#[instrument(level = "debug")]
+pub fn link_with_policy(parent: &Path, child: &Path, on_existing: OnExisting) -> TreeResult<()> {
     let parent = parent.to_canonical()?;
     let child = child.to_canonical()?;
     debug!("parent: {:?} <- child: {:?}", parent, child);
 
-    let mut child_contents = std::fs::read_to_string(&child)
-        .map_err(TreeError::FileReadError)?;
-    let mut lines: Vec<_> = child_contents.lines().map(|s| s.to_string()).collect();
+    let (mut lines, format) = read_text_file(&child)?;
 
     // Calculate the relative path from child to parent
     let relative_path = pathdiff::diff_paths(&parent, child.parent().unwrap())
@@ -268,9 +821,39 @@ pub fn link(parent: &Path, child: &Path) -> TreeResult<()> {
             lines.insert(0, format!("# rsenv: {}", relative_path.display()));
         }
         1 => {
-            // One "# rsenv:" line found, so we replace it
-            if let Some(index) = rsenv_index {
-                lines[index] = format!("# rsenv: {}", relative_path.display());
+            let index = rsenv_index.unwrap();
+            let existing: Vec<String> = split_parent_specs(lines[index].trim_start_matches("# rsenv:"))
+                .into_iter()
+                .map(String::from)
+                .collect();
+            // the declaration is only parsed if it is kept
+            let parsed = match on_existing {
+                OnExisting::Replace => Vec::new(),
+                _ => parse_parent_specs(&child, &existing)?,
+            };
+            let child_dir = child.parent().ok_or_else(|| TreeError::InvalidParent(child.clone()))?;
+            let already_linked = parsed.iter()
+                .any(|(path, _)| child_dir.join(path).canonicalize().is_ok_and(|path| path == parent));
+            match on_existing {
+                _ if already_linked => return Ok(()),
+                OnExisting::Abort if !existing.is_empty() => {
+                    return Err(TreeError::ParentExists {
+                        child,
+                        existing: existing.join(" "),
+                        proposed: relative_path.display().to_string(),
+                    });
+                }
+                OnExisting::Append if !existing.is_empty() => {
+                    // prioritized parents need a priority above all others to win
+                    let priority = parsed.iter()
+                        .filter_map(|(_, priority)| *priority)
+                        .max()
+                        .map(|highest| format!("[{}]", highest.saturating_add(10)))
+                        .unwrap_or_default();
+                    lines[index] = format!("# rsenv: {} {}{}", existing.join(" "), relative_path.display(), priority);
+                }
+                // One "# rsenv:" line found, so we replace it
+                _ => lines[index] = format!("# rsenv: {}", relative_path.display()),
             }
         }
         _ => {
@@ -279,22 +862,27 @@ pub fn link(parent: &Path, child: &Path) -> TreeResult<()> {
         }
     }
 
-    // Write the modified content back to the child file
-    child_contents = lines.join("\n");
-    std::fs::write(&child, child_contents)
-        .map_err(TreeError::FileReadError)?;
+    // Write the modified content back to the child file, keeping its BOM and line endings
+    write_text_file(&child, &lines, format)?;
 
     Ok(())
 }
 
+/// Parent `specs` of `child` as path with variables expanded and priority, see
+/// `parse_parent_spec`, so `$CONFIG_DIR/base.env[10]` can be compared by the file it names.
+fn parse_parent_specs(child: &Path, specs: &[String]) -> TreeResult<Vec<(String, Option<u32>)>> {
+    specs.iter()
+        .map(|spec| parse_parent_spec(spec))
+        .collect::<Result<_, _>>()
+        .map_err(|reason| TreeError::InvalidFormat { path: child.to_path_buf(), reason })
+}
+
 #[instrument(level = "debug")]
 pub fn unlink(child: &Path) -> TreeResult<()> {
     let child = child.to_canonical()?;
     debug!("child: {:?}", child);
 
-    let mut child_contents = std::fs::read_to_string(&child)
-        .map_err(TreeError::FileReadError)?;
-    let mut lines: Vec<_> = child_contents.lines().map(|s| s.to_string()).collect();
+    let (mut lines, format) = read_text_file(&child)?;
 
     // Find and count the lines that start with "# rsenv:"
     let mut rsenv_lines = 0;
@@ -318,14 +906,51 @@ pub fn unlink(child: &Path) -> TreeResult<()> {
             return Err(TreeError::MultipleParents(child));
         }
     }
-    // Write the modified content back to the child file
-    child_contents = lines.join("\n");
-    std::fs::write(&child, child_contents)
-        .map_err(TreeError::FileReadError)?;
+    // Write the modified content back to the child file, keeping its BOM and line endings
+    write_text_file(&child, &lines, format)?;
 
     Ok(())
 }
 
+/// Same as `link_all`, with `on_existing` deciding about parents the nodes already have.
+///
+/// The first node becomes a root only with `OnExisting::Replace`, otherwise its parents are
+/// kept. With `OnExisting::Abort` all nodes are checked before any file is changed, so a
+/// conflict leaves the chain untouched. So are nodes outside `BuildOptions::confine_to` of
+/// `options`.
+#[instrument(level = "debug")]
+pub fn link_all_with_policy(nodes: &[PathBuf], on_existing: OnExisting, options: &BuildOptions) -> TreeResult<()> {
+    debug!("nodes: {:?}", nodes);
+    if let Some(root) = &options.confine_to {
+        for node in nodes {
+            ensure_within(node, root, None)?;
+        }
+    }
+    if on_existing == OnExisting::Abort {
+        for pair in nodes.windows(2) {
+            let (_, parents) = extract_env(&pair[1])?;
+            let proposed = pair[0].to_canonical()?;
+            if !parents.is_empty() && !parents.contains(&proposed) {
+                return Err(TreeError::ParentExists {
+                    child: pair[1].clone(),
+                    existing: parents.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(" "),
+                    proposed: proposed.display().to_string(),
+                });
+            }
+        }
+    }
+    let mut parent = None;
+    for node in nodes {
+        match parent {
+            Some(parent_path) => link_with_policy(parent_path, node, on_existing)?,
+            None if on_existing == OnExisting::Replace => unlink(node)?,
+            None => {}
+        }
+        parent = Some(node);
+    }
+    Ok(())
+}
+
 /// links a list of env files together and build the hierarchical environment variables tree
 #[instrument(level = "debug")]
 pub fn link_all(nodes: &[PathBuf]) {
@@ -0,0 +1,285 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+use tracing::instrument;
+
+use crate::errors::{TreeError, TreeResult};
+use crate::diagnostics::{check_file, DiagnosticKind};
+use crate::sops::{is_sops_encrypted, read_env_lines};
+use crate::util::file::{read_dir_files, WalkIssue, WalkPolicy};
+use crate::util::path::{ensure_within, PathExt};
+use crate::{build_env_with_options, parse_env_lines, parse_parent_spec, split_parent_specs, strip_export, BuildOptions};
+
+/// Problems of a directory of env files, found by `lint_directory`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LintKind {
+    /// A `# rsenv:` reference to a file which does not exist, as written
+    BrokenParent(String),
+    /// A `# rsenv:` reference to a file outside `BuildOptions::confine_to`, as written
+    OutsideProject(String),
+    /// A problem within a single file, the same `rsenv build` reports
+    Diagnostic(DiagnosticKind),
+    /// A variable set to the value it inherits from the parents anyway
+    RedundantOverride(String),
+    /// A file which neither declares parents nor is a parent of another file
+    OrphanFile,
+    /// Files referencing each other in a circle, starting and ending with the reported file
+    Cycle(Vec<PathBuf>),
+}
+
+impl LintKind {
+    /// Stable identifier for machine-readable output.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LintKind::BrokenParent(_) => "broken-parent",
+            LintKind::OutsideProject(_) => "outside-project",
+            LintKind::Diagnostic(kind) => kind.name(),
+            LintKind::RedundantOverride(_) => "redundant-override",
+            LintKind::OrphanFile => "orphan-file",
+            LintKind::Cycle(_) => "cycle",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintFinding {
+    pub file: PathBuf,
+    /// 1-based line number, if the finding concerns a single line
+    pub line: Option<usize>,
+    pub kind: LintKind,
+}
+
+impl LintFinding {
+    fn message(&self, show_file: impl Fn(&Path) -> String) -> String {
+        match &self.kind {
+            LintKind::BrokenParent(parent) => format!("parent {} does not exist", parent),
+            LintKind::OutsideProject(parent) => format!("parent {} is outside the project", parent),
+            LintKind::Diagnostic(kind) => kind.message(),
+            LintKind::RedundantOverride(key) => format!("{} overrides the inherited value with the same value", key),
+            LintKind::OrphanFile => "not linked to any other file".to_string(),
+            LintKind::Cycle(files) => {
+                let files: Vec<String> = files.iter().map(|f| show_file(f)).collect();
+                format!("cyclic reference {}", files.join(" -> "))
+            }
+        }
+    }
+
+    /// Renders the finding like `Display`, showing paths with `show_file`.
+    pub fn display_with(&self, show_file: impl Fn(&Path) -> String) -> String {
+        let location = match self.line {
+            Some(line) => format!("{}:{}", show_file(&self.file), line),
+            None => show_file(&self.file),
+        };
+        format!("{}: {}", location, self.message(show_file))
+    }
+}
+
+impl fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.display_with(|p| p.display().to_string()))
+    }
+}
+
+/// Checks all `.env` files below `dir` for broken parent references, redundant overrides,
+/// orphan files and cycles, and each file for the problems of `diagnostics::check_file`.
+/// Findings are sorted by file and line.
+///
+/// Orphans are only reported if some files of the directory are linked, a directory of
+/// standalone files is not a hierarchy. Redundant overrides are not checked for files whose
+/// parents cannot be built, e.g. because of a broken reference or a cycle.
+///
+/// Files are built with `options`. With `BuildOptions::confine_to` parents outside the project
+/// are reported and `dir` must be inside it.
+///
+/// Unreadable files are handled according to `policy` and reported as issues.
+#[instrument(level = "debug")]
+pub fn lint_directory(
+    dir: &Path,
+    policy: WalkPolicy,
+    options: &BuildOptions,
+) -> TreeResult<(Vec<LintFinding>, Vec<WalkIssue>)> {
+    if !dir.is_dir() {
+        return Err(TreeError::InvalidFormat { path: dir.to_path_buf(), reason: "Not a directory".to_string() });
+    }
+    let root = options.confine_to.as_deref().map(Path::to_canonical).transpose()?;
+    if let Some(root) = &root {
+        ensure_within(dir, root, None)?;
+    }
+    let (dir_files, issues) = read_dir_files(dir, policy)?;
+    let mut findings = Vec::new();
+    let mut parents_of: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    let mut declares_parents: HashSet<PathBuf> = HashSet::new();
+    let mut definitions_of: BTreeMap<PathBuf, Definitions> = BTreeMap::new();
+
+    for (path, lines) in dir_files {
+        if path.extension().is_none_or(|extension| extension != "env") {
+            continue;
+        }
+        let file = path.to_canonical()?;
+        let lines = if is_sops_encrypted(&file, &lines) { read_env_lines(&file)? } else { lines };
+        let file_dir = file.parent().ok_or_else(|| TreeError::InvalidParent(file.clone()))?;
+        let mut parents = Vec::new();
+        for (index, line) in lines.iter().enumerate() {
+            if let Some(specs) = line.strip_prefix("# rsenv:") {
+                declares_parents.insert(file.clone());
+                for spec in split_parent_specs(specs) {
+                    let (parent, _) = parse_parent_spec(spec)
+                        .map_err(|reason| TreeError::InvalidFormat { path: file.clone(), reason })?;
+                    let kind = match file_dir.join(&parent).to_canonical() {
+                        Ok(path) if root.as_ref().is_some_and(|root| !path.starts_with(root)) => {
+                            LintKind::OutsideProject(parent)
+                        }
+                        Ok(path) if path.is_file() => {
+                            parents.push(path);
+                            continue;
+                        }
+                        _ => LintKind::BrokenParent(parent),
+                    };
+                    findings.push(LintFinding { file: file.clone(), line: Some(index + 1), kind });
+                }
+            }
+        }
+        // references are only checked if the hierarchy of the file can be built
+        let defined = build_env_with_options(&file, options).ok().map(|(variables, _, _)| variables);
+        findings.extend(check_file(&file, &lines, defined.as_ref()).into_iter().map(|diagnostic| LintFinding {
+            file: diagnostic.file,
+            line: Some(diagnostic.line),
+            kind: LintKind::Diagnostic(diagnostic.kind),
+        }));
+        if let Some(definitions) = Definitions::parse(file_dir, lines, options) {
+            definitions_of.insert(file.clone(), definitions);
+        }
+        parents_of.insert(file, parents);
+    }
+
+    let referenced: HashSet<&PathBuf> = parents_of.values().flatten().collect();
+    if !referenced.is_empty() {
+        for file in parents_of.keys() {
+            if !declares_parents.contains(file) && !referenced.contains(file) {
+                findings.push(LintFinding { file: file.clone(), line: None, kind: LintKind::OrphanFile });
+            }
+        }
+    }
+
+    let cycles = find_cycles(&parents_of);
+    let in_cycle: HashSet<&PathBuf> = cycles.iter().flatten().collect();
+    for (file, definitions) in &definitions_of {
+        if !definitions.parents.is_empty() && !in_cycle.contains(file) {
+            findings.extend(redundant_overrides(file, definitions, options));
+        }
+    }
+    for cycle in cycles {
+        findings.push(LintFinding { file: cycle[0].clone(), line: None, kind: LintKind::Cycle(cycle) });
+    }
+
+    findings.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+    Ok((findings, issues))
+}
+
+/// What a single file defines itself, parsed once for `redundant_overrides`.
+struct Definitions {
+    lines: Vec<String>,
+    /// Normalized with `BuildOptions::values`, like `build_env` does
+    variables: BTreeMap<String, String>,
+    /// Resolved parents in ascending precedence
+    parents: Vec<PathBuf>,
+}
+
+impl Definitions {
+    /// `None` if the parent declaration is invalid or a parent does not exist.
+    fn parse(file_dir: &Path, lines: Vec<String>, options: &BuildOptions) -> Option<Definitions> {
+        let parsed = parse_env_lines(&lines).ok()?;
+        let parents = parsed.parents.iter()
+            .map(|parent| file_dir.join(parent).to_canonical().ok())
+            .collect::<Option<Vec<_>>>()?;
+        Some(Definitions { variables: options.values.apply(parsed.variables), parents, lines })
+    }
+}
+
+/// Variables of `file` having the value its parents provide. Empty if one of its parents
+/// cannot be built.
+fn redundant_overrides(file: &Path, definitions: &Definitions, options: &BuildOptions) -> Vec<LintFinding> {
+    // parents come in ascending precedence, later ones override earlier ones like in `build_env`
+    let mut inherited: BTreeMap<String, String> = BTreeMap::new();
+    for parent in &definitions.parents {
+        match build_env_with_options(parent, options) {
+            Ok((parent_variables, _, _)) => inherited.extend(parent_variables),
+            Err(_) => return Vec::new(),
+        }
+    }
+
+    definitions.variables.iter()
+        .filter(|(key, value)| inherited.get(*key) == Some(*value))
+        .map(|(key, _)| {
+            // the last assignment is the effective one
+            let line = definitions.lines.iter()
+                .rposition(|l| {
+                    strip_export(l)
+                        .and_then(|a| a.split_once('='))
+                        .is_some_and(|(k, _)| k.trim() == key)
+                })
+                .map(|index| index + 1);
+            LintFinding { file: file.to_path_buf(), line, kind: LintKind::RedundantOverride(key.clone()) }
+        })
+        .collect()
+}
+
+/// Finds every cycle of the parent relation once, each starting at its smallest file.
+fn find_cycles(parents_of: &BTreeMap<PathBuf, Vec<PathBuf>>) -> Vec<Vec<PathBuf>> {
+    let mut cycles = BTreeSet::new();
+    let mut done: HashSet<&PathBuf> = HashSet::new();
+    for start in parents_of.keys() {
+        let mut path: Vec<&PathBuf> = Vec::new();
+        visit(start, parents_of, &mut path, &mut done, &mut cycles);
+    }
+    cycles.into_iter().collect()
+}
+
+fn visit<'a>(
+    file: &'a PathBuf,
+    parents_of: &'a BTreeMap<PathBuf, Vec<PathBuf>>,
+    path: &mut Vec<&'a PathBuf>,
+    done: &mut HashSet<&'a PathBuf>,
+    cycles: &mut BTreeSet<Vec<PathBuf>>,
+) {
+    if done.contains(file) {
+        return;
+    }
+    if let Some(position) = path.iter().position(|f| *f == file) {
+        let mut cycle: Vec<PathBuf> = path[position..].iter().map(|f| f.to_path_buf()).collect();
+        let smallest = (0..cycle.len()).min_by_key(|&i| &cycle[i]).unwrap_or(0);
+        cycle.rotate_left(smallest);
+        cycle.push(cycle[0].clone());
+        cycles.insert(cycle);
+        return;
+    }
+    path.push(file);
+    for parent in parents_of.get(file).into_iter().flatten() {
+        visit(parent, parents_of, path, done, cycles);
+    }
+    path.pop();
+    done.insert(file);
+}
+
+/// Machine-readable form of `findings`: a list of objects with `file`, `line` (null if the
+/// finding concerns the whole file), `kind` and `message`. Cycles additionally list their
+/// `files`. Paths are rendered with `show_file`.
+pub fn findings_to_json(findings: &[LintFinding], show_file: impl Fn(&Path) -> String) -> Value {
+    let entries = findings.iter()
+        .map(|finding| {
+            let mut entry = json!({
+                "file": show_file(&finding.file),
+                "line": finding.line,
+                "kind": finding.kind.name(),
+                "message": finding.message(&show_file),
+            });
+            if let LintKind::Cycle(files) = &finding.kind {
+                entry["files"] = json!(files.iter().map(|f| show_file(f)).collect::<Vec<_>>());
+            }
+            entry
+        })
+        .collect();
+    Value::Array(entries)
+}
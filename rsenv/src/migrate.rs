@@ -0,0 +1,196 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, instrument};
+
+use crate::envrc::{END_SECTION_DELIMITER, START_SECTION_DELIMITER};
+use crate::errors::{TreeError, TreeResult};
+use crate::util::file::read_lines;
+use crate::util::path::{ensure_file_exists, ensure_within, get_relative_path, PathExt};
+use crate::BuildOptions;
+
+/// Outcome of a migration: the files written and the constructs which need manual attention.
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    pub created: Vec<PathBuf>,
+    pub manual: Vec<String>,
+}
+
+/// Converts a direnv `.envrc` into an rsenv env file at `output`.
+///
+/// `export` statements are copied, `dotenv <file>` statements become parents (the dotenv file is
+/// converted next to `output`). Everything else (layouts, `use`, `PATH_add`, shell logic) is
+/// reported for manual migration. The managed rsenv section is skipped.
+///
+/// With `BuildOptions::confine_to` of `options`, files outside the project are neither read
+/// nor written.
+#[instrument(level = "debug")]
+pub fn migrate_direnv(envrc_path: &Path, output: &Path, options: &BuildOptions) -> TreeResult<MigrationReport> {
+    ensure_file_exists(envrc_path)?;
+    if let Some(root) = &options.confine_to {
+        ensure_within(envrc_path, root, None)?;
+        ensure_within(output, root, None)?;
+    }
+    let envrc_dir = envrc_path.to_canonical()?
+        .parent()
+        .ok_or_else(|| TreeError::InvalidParent(envrc_path.to_path_buf()))?
+        .to_path_buf();
+    let output_dir = output_dir(output)?;
+
+    let mut report = MigrationReport::default();
+    let mut parents = Vec::new();
+    let mut exports = Vec::new();
+    let mut in_managed_section = false;
+
+    for (index, line) in read_lines(envrc_path)?.into_iter().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with(START_SECTION_DELIMITER) {
+            in_managed_section = true;
+            continue;
+        }
+        if trimmed.starts_with(END_SECTION_DELIMITER) {
+            in_managed_section = false;
+            continue;
+        }
+        if in_managed_section || trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        if trimmed.starts_with("export ") && trimmed.contains('=') {
+            exports.push(trimmed.to_string());
+        } else if let Some(dotenv) = trimmed.strip_prefix("dotenv").filter(|r| r.is_empty() || r.starts_with(' ')) {
+            let source = envrc_dir.join(match dotenv.trim() { "" => ".env", file => file });
+            if source.is_file() {
+                if let Some(root) = &options.confine_to {
+                    ensure_within(&source, root, Some(envrc_path))?;
+                }
+                let target = output_dir.join(converted_name(&source));
+                report.manual.extend(convert_dotenv_file(&source, &target)?);
+                parents.push(get_relative_path(&output_dir, &target)?);
+                report.created.push(target);
+            } else {
+                report.manual.push(format!("line {}: dotenv file not found: {}", index + 1, trimmed));
+            }
+        } else {
+            report.manual.push(format!("line {}: {}", index + 1, trimmed));
+        }
+    }
+
+    let mut contents = vec![format!(
+        "# rsenv: {}",
+        parents.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(" ")
+    ).trim_end().to_string()];
+    contents.push(format!("# migrated from {}", envrc_path.display()));
+    contents.extend(exports);
+    write_env_file(output, &contents)?;
+    report.created.push(output.to_path_buf());
+    Ok(report)
+}
+
+/// Converts a dotenv-vault project layout into rsenv env files in `output_dir`.
+///
+/// `.env` becomes `development.env`, `.env.<stage>` becomes `<stage>.env`. The encrypted
+/// `.env.vault` and the key files `.env.keys`/`.env.me` cannot be converted and are reported.
+///
+/// With `BuildOptions::confine_to` of `options`, files outside the project are neither read
+/// nor written, including files symlinked into `source_dir`.
+#[instrument(level = "debug")]
+pub fn migrate_dotenv_vault(source_dir: &Path, output_dir: &Path, options: &BuildOptions) -> TreeResult<MigrationReport> {
+    if !source_dir.is_dir() {
+        return Err(TreeError::FileNotFound(source_dir.to_path_buf()));
+    }
+    if let Some(root) = &options.confine_to {
+        ensure_within(source_dir, root, None)?;
+        ensure_within(output_dir, root, None)?;
+    }
+    fs::create_dir_all(output_dir).map_err(TreeError::FileReadError)?;
+
+    let mut report = MigrationReport::default();
+    let mut entries: Vec<PathBuf> = fs::read_dir(source_dir)
+        .map_err(TreeError::FileReadError)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file())
+        .collect();
+    entries.sort();
+
+    for source in entries {
+        let name = source.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        match name.as_str() {
+            ".env.vault" => {
+                report.manual.push(format!(
+                    "{}: encrypted environments, decrypt them with dotenv-vault and migrate the plain files", name
+                ));
+                continue;
+            }
+            ".env.keys" | ".env.me" => {
+                report.manual.push(format!("{}: credentials for dotenv-vault, not migrated", name));
+                continue;
+            }
+            _ if name == ".env" || name.starts_with(".env.") => {}
+            _ => continue,
+        }
+        if let Some(root) = &options.confine_to {
+            ensure_within(&source, root, None)?;
+        }
+        let target = output_dir.join(converted_name(&source));
+        debug!("Converting {:?} -> {:?}", source, target);
+        report.manual.extend(convert_dotenv_file(&source, &target)?);
+        report.created.push(target);
+    }
+    Ok(report)
+}
+
+/// Maps `.env` to `development.env`, `.env.<stage>` to `<stage>.env` and `<name>` to `<name>.env`.
+fn converted_name(source: &Path) -> String {
+    let name = source.file_name().unwrap_or_default().to_string_lossy();
+    match name.as_ref() {
+        ".env" => "development.env".to_string(),
+        _ => match name.strip_prefix(".env.") {
+            Some(stage) => format!("{}.env", stage),
+            None => format!("{}.env", name.trim_end_matches(".env").trim_start_matches('.')),
+        },
+    }
+}
+
+/// Writes a plain dotenv file as rsenv env file, prefixing assignments with `export`.
+/// Returns notes for lines which could not be converted.
+fn convert_dotenv_file(source: &Path, target: &Path) -> TreeResult<Vec<String>> {
+    let mut manual = Vec::new();
+    let mut contents = vec!["# rsenv:".to_string(), format!("# migrated from {}", source.display())];
+
+    for (index, line) in read_lines(source)?.into_iter().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            contents.push(line);
+        } else if trimmed.starts_with("export ") {
+            contents.push(trimmed.to_string());
+        } else if let Some((key, value)) = trimmed.split_once('=') {
+            let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'');
+            if quote.is_some_and(|q| value.len() == 1 || !value.ends_with(q)) {
+                manual.push(format!("{}:{}: multi-line value not migrated: {}", source.display(), index + 1, key));
+            } else {
+                contents.push(format!("export {}={}", key.trim(), value));
+            }
+        } else {
+            manual.push(format!("{}:{}: {}", source.display(), index + 1, trimmed));
+        }
+    }
+    write_env_file(target, &contents)?;
+    Ok(manual)
+}
+
+fn output_dir(output: &Path) -> TreeResult<PathBuf> {
+    let dir = output.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    fs::create_dir_all(dir).map_err(TreeError::FileReadError)?;
+    dir.to_canonical()
+}
+
+fn write_env_file(path: &Path, lines: &[String]) -> TreeResult<()> {
+    if path.exists() {
+        return Err(TreeError::InvalidFormat {
+            path: path.to_path_buf(),
+            reason: "Target file already exists".to_string(),
+        });
+    }
+    fs::write(path, lines.join("\n") + "\n").map_err(TreeError::FileReadError)
+}
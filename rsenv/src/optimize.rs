@@ -0,0 +1,129 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, instrument};
+
+use crate::errors::{TreeError, TreeResult};
+use crate::{extract_env, strip_export, VALUE_FILE_PREFIX};
+use crate::util::file::{read_text_file, write_text_file};
+use crate::util::path::{ensure_file_exists, ensure_within, get_relative_path, PathExt};
+
+/// Thresholds controlling how `optimize` rewrites a leaf file.
+#[derive(Debug, Clone)]
+pub struct OptimizeOptions {
+    /// Parents with at most this many variables are inlined into the leaf
+    pub max_inline_vars: usize,
+    /// Values longer than this many bytes are moved into separate files
+    pub max_value_len: usize,
+    /// Refuse to read files outside this directory, see `BuildOptions::confine_to`
+    pub confine_to: Option<PathBuf>,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        Self {
+            max_inline_vars: 3,
+            max_value_len: 1024,
+            confine_to: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct OptimizeReport {
+    pub inlined: Vec<PathBuf>,
+    pub externalized: Vec<(String, PathBuf)>,
+}
+
+/// Rewrites a leaf env file for faster evaluation.
+///
+/// Walks up the leaf's single-parent chain and inlines every parent with at most
+/// `max_inline_vars` variables into the leaf, marking the inlined block with a provenance
+/// comment. Inlining stops at the first parent which is too large or has multiple parents.
+/// Afterwards, values longer than `max_value_len` are written to `<leaf>.values/<KEY>` and
+/// replaced by a `# rsenv-file:` directive, see `VALUE_FILE_PREFIX`. The leaf builds the same
+/// variables as before.
+///
+/// Only the leaf file is modified, parents stay untouched for other children.
+#[instrument(level = "debug")]
+pub fn optimize(leaf: &Path, options: &OptimizeOptions) -> TreeResult<OptimizeReport> {
+    ensure_file_exists(leaf)?;
+    let leaf = leaf.to_canonical()?;
+    if let Some(root) = &options.confine_to {
+        ensure_within(&leaf, root, None)?;
+    }
+    let leaf_dir = leaf.parent()
+        .ok_or_else(|| TreeError::InvalidParent(leaf.clone()))?
+        .to_path_buf();
+    let mut report = OptimizeReport::default();
+
+    let (mut lines, format) = read_text_file(&leaf)?;
+    let (leaf_vars, mut parents) = extract_env(&leaf)?;
+    let mut defined: Vec<String> = leaf_vars.into_keys().collect();
+    let mut inlined_block: Vec<String> = Vec::new();
+
+    let mut child = leaf.clone();
+    while parents.len() == 1 {
+        let parent = parents[0].clone();
+        if let Some(root) = &options.confine_to {
+            ensure_within(&parent, root, Some(&child))?;
+        }
+        let (parent_vars, grand_parents) = extract_env(&parent)?;
+        if parent_vars.len() > options.max_inline_vars {
+            break;
+        }
+        debug!("Inlining {:?} into {:?}", parent, leaf);
+        let relative = get_relative_path(&leaf_dir, &parent)?;
+        let mut block = vec![format!("# inlined from: {}", relative.display())];
+        for (k, v) in parent_vars {
+            if !defined.contains(&k) {
+                block.push(format!("export {}={}", k, v));
+                defined.push(k);
+            }
+        }
+        // Closer ancestors win, so they are placed after the more distant ones
+        block.append(&mut inlined_block);
+        inlined_block = block;
+        report.inlined.push(parent.clone());
+        child = parent;
+        parents = grand_parents;
+    }
+
+    if !report.inlined.is_empty() {
+        let relative_parents = parents.iter()
+            .map(|p| get_relative_path(&leaf_dir, p).map(|r| r.display().to_string()))
+            .collect::<TreeResult<Vec<_>>>()?;
+        let rsenv_line = format!("# rsenv: {}", relative_parents.join(" ")).trim_end().to_string();
+        match lines.iter().position(|l| l.starts_with("# rsenv:")) {
+            Some(index) => {
+                lines[index] = rsenv_line;
+                for (offset, line) in inlined_block.into_iter().enumerate() {
+                    lines.insert(index + 1 + offset, line);
+                }
+            }
+            None => return Err(TreeError::InternalError(format!(
+                "Missing parent declaration in {}", leaf.display()
+            ))),
+        }
+    }
+
+    let values_dir = leaf.with_extension("values");
+    for line in lines.iter_mut() {
+        let Some((key, value)) = strip_export(line).and_then(|a| a.split_once('=')) else { continue };
+        if value.len() <= options.max_value_len {
+            continue;
+        }
+        let Some(key) = key.split_whitespace().next().map(str::to_string) else { continue };
+        fs::create_dir_all(&values_dir).map_err(TreeError::FileReadError)?;
+        let value_file = values_dir.join(&key);
+        fs::write(&value_file, value).map_err(TreeError::FileReadError)?;
+        let relative = get_relative_path(&leaf_dir, &value_file)?;
+        *line = format!("{} {}={}", VALUE_FILE_PREFIX, key, relative.display());
+        report.externalized.push((key, value_file));
+    }
+
+    if !report.inlined.is_empty() || !report.externalized.is_empty() {
+        write_text_file(&leaf, &lines, format)?;
+    }
+    Ok(report)
+}
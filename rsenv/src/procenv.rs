@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+use tracing::{debug, instrument};
+
+use crate::capabilities::require_program;
+use crate::diff::{diff_vars, EnvChange};
+use crate::errors::{TreeError, TreeResult};
+use crate::util::value::unquote;
+use crate::{build_env_with_options, BuildOptions};
+
+/// Reads the environment process `pid` was started with.
+///
+/// On Linux this is `/proc/<pid>/environ`. Elsewhere `ps eww` is used, which only shows
+/// the environment appended to the command line, so values containing spaces are cut off.
+#[instrument(level = "debug")]
+pub fn read_process_env(pid: u32) -> TreeResult<BTreeMap<String, String>> {
+    let unavailable = |reason: String| TreeError::ProcessEnvUnavailable { pid, reason };
+    let environ = Path::new("/proc").join(pid.to_string()).join("environ");
+    if environ.exists() {
+        let data = std::fs::read(&environ).map_err(|e| unavailable(e.to_string()))?;
+        return Ok(parse_environ(&data));
+    }
+    if cfg!(target_os = "linux") {
+        return Err(unavailable("no such process".to_string()));
+    }
+
+    let output = Command::new("ps")
+        .args(["eww", "-o", "command=", "-p", &pid.to_string()])
+        .output()
+        .map_err(|e| unavailable(e.to_string()))?;
+    if !output.status.success() {
+        return Err(unavailable("no such process".to_string()));
+    }
+    Ok(parse_ps_environ(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Compares the built environment of `leaf` with the environment of process `pid`.
+///
+/// Returns `EnvChange::Removed` for variables the process is missing and `EnvChange::Changed`
+/// (old: expected, new: actual) for different values. Variables only the process has are ignored.
+#[instrument(level = "debug")]
+pub fn check_process_env(pid: u32, leaf: &Path, options: &BuildOptions) -> TreeResult<Vec<EnvChange>> {
+    let (variables, _, _) = build_env_with_options(leaf, options)?;
+    let expected: BTreeMap<String, String> = variables.into_iter()
+        .map(|(k, v)| (k, unquote(&v).to_string()))
+        .collect();
+    let actual = read_process_env(pid)?;
+    debug!("expected {} variables, process has {}", expected.len(), actual.len());
+    Ok(diff_vars(&expected, &actual)
+        .into_iter()
+        .filter(|change| !matches!(change, EnvChange::Added { .. }))
+        .collect())
+}
+
+/// Runs `args` as a process with `variables` added to the current environment, or as its only
+/// environment if `clean` is set. Values are unquoted as the shell would when sourcing them.
+#[instrument(level = "debug", skip(variables))]
+pub fn run_with_env(variables: &BTreeMap<String, String>, args: &[String], clean: bool) -> TreeResult<ExitStatus> {
+    let (program, program_args) = args.split_first()
+        .ok_or_else(|| TreeError::InternalError("No command given".to_string()))?;
+    let program = require_program(program)?;
+    let mut command = Command::new(program);
+    if clean {
+        command.env_clear();
+    }
+    command
+        .args(program_args)
+        .envs(variables.iter().map(|(k, v)| (k, unquote(v))));
+    debug!("Running {:?}", command);
+    command.status().map_err(TreeError::FileReadError)
+}
+
+/// Parses NUL separated `KEY=VALUE` entries as found in `/proc/<pid>/environ`.
+fn parse_environ(data: &[u8]) -> BTreeMap<String, String> {
+    data.split(|b| *b == 0)
+        .filter_map(|entry| String::from_utf8_lossy(entry).split_once('=')
+            .map(|(k, v)| (k.to_string(), v.to_string())))
+        .filter(|(k, _)| !k.is_empty())
+        .collect()
+}
+
+/// Picks the `KEY=VALUE` words from `ps eww` output.
+fn parse_ps_environ(output: &str) -> BTreeMap<String, String> {
+    output.split_whitespace()
+        .filter_map(|word| word.split_once('='))
+        .filter(|(k, _)| k.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_environ() {
+        let vars = parse_environ(b"A=1\0B=x=y\0\0=ignored\0");
+        assert_eq!(vars.len(), 2);
+        assert_eq!(vars["B"], "x=y");
+    }
+
+    #[test]
+    fn test_parse_ps_environ() {
+        let vars = parse_ps_environ("/usr/bin/server --port=80 HOME=/home/user LANG=C\n");
+        assert_eq!(vars.keys().collect::<Vec<_>>(), vec!["HOME", "LANG"]);
+    }
+}
@@ -0,0 +1,64 @@
+use std::path::Path;
+
+use tracing::instrument;
+
+use crate::errors::{TreeError, TreeResult};
+use crate::{get_files_with_options, BuildOptions};
+use crate::sops::is_sops_encrypted;
+use crate::strip_export;
+use crate::util::file::{read_text_file, write_text_file};
+use crate::util::path::PathExt;
+
+/// Whether `line` assigns `key`, with or without `export`.
+fn assigns(line: &str, key: &str) -> bool {
+    strip_export(line)
+        .and_then(|assignment| assignment.split_once('='))
+        .is_some_and(|(name, _)| name.trim() == key)
+}
+
+/// Moves the assignment of `key` from the env file `from` into `to`, which must be one of its
+/// ancestors or descendants; with `copy` it stays in `from` as well.
+///
+/// The effective (last) assignment line of `from` is taken verbatim. It replaces the last
+/// assignment of `key` in `to` or is appended to it. Moving removes every assignment of `key`
+/// from `from`, so an earlier one does not take effect. All other lines, comments included,
+/// are kept, as are BOM and line endings. SOPS-encrypted files are refused.
+///
+/// The relation is determined by building both files with `options`.
+#[instrument(level = "debug")]
+pub fn promote_variable(key: &str, from: &Path, to: &Path, copy: bool, options: &BuildOptions) -> TreeResult<()> {
+    let from = from.to_canonical()?;
+    let to = to.to_canonical()?;
+    let related = from != to
+        && (get_files_with_options(&from, options)?.contains(&to) || get_files_with_options(&to, options)?.contains(&from));
+    if !related {
+        return Err(TreeError::UnrelatedFiles { from, to });
+    }
+
+    let (mut from_lines, from_format) = read_text_file(&from)?;
+    let (mut to_lines, to_format) = read_text_file(&to)?;
+    for (path, lines) in [(&from, &from_lines), (&to, &to_lines)] {
+        if is_sops_encrypted(path, lines) {
+            return Err(TreeError::InvalidFormat {
+                path: path.clone(),
+                reason: "cannot rewrite a SOPS-encrypted file".to_string(),
+            });
+        }
+    }
+    let assignment = from_lines.iter()
+        .rfind(|line| assigns(line, key))
+        .cloned()
+        .ok_or_else(|| TreeError::VariableNotFound { key: key.to_string(), path: from.clone() })?;
+
+    match to_lines.iter().rposition(|line| assigns(line, key)) {
+        Some(index) => to_lines[index] = assignment,
+        None => to_lines.push(assignment),
+    }
+    // the target first, so a failure cannot lose the variable
+    write_text_file(&to, &to_lines, to_format)?;
+    if !copy {
+        from_lines.retain(|line| !assigns(line, key));
+        write_text_file(&from, &from_lines, from_format)?;
+    }
+    Ok(())
+}
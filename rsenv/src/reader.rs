@@ -0,0 +1,43 @@
+use std::path::{Path, PathBuf};
+
+use tracing::instrument;
+
+use crate::errors::TreeResult;
+use crate::sops::read_env_lines;
+use crate::util::path::{ensure_file_exists, PathCache};
+
+/// Source of the files of a hierarchy, see `build_env_with_reader`.
+pub trait EnvReader {
+    /// Identity of the file at `path`, under which it is read, compared and reported.
+    fn resolve(&mut self, path: &Path) -> TreeResult<PathBuf>;
+
+    /// Lines of a file returned by `resolve`, decrypted if it is SOPS-encrypted.
+    fn read_lines(&mut self, file: &Path) -> TreeResult<Vec<String>>;
+}
+
+/// Reads the files from the working tree, identified by their canonical paths.
+#[derive(Debug, Default)]
+pub struct WorkTree {
+    paths: PathCache,
+}
+
+impl WorkTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl EnvReader for WorkTree {
+    #[instrument(level = "trace", skip(self))]
+    fn resolve(&mut self, path: &Path) -> TreeResult<PathBuf> {
+        if self.paths.is_symlink(path)? {
+            eprintln!("Warning: The file {} is a symbolic link.", path.display());
+        }
+        self.paths.to_canonical(path)
+    }
+
+    fn read_lines(&mut self, file: &Path) -> TreeResult<Vec<String>> {
+        ensure_file_exists(file)?;
+        read_env_lines(file)
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use regex::Regex;
+use toml::{Table, Value};
+use tracing::instrument;
+
+use crate::errors::{TreeError, TreeResult};
+use crate::util::value::unquote;
+use crate::SourcedValue;
+
+/// Type hint of a variable in a `Schema`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    /// An integer, e.g. `8080` or `-1`
+    Int,
+    /// `true`/`false`, `yes`/`no`, `on`/`off` or `1`/`0`, case-insensitive
+    Bool,
+    /// A URL with scheme, e.g. `postgres://db:5432/app`
+    Url,
+}
+
+impl ValueType {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "int" => Some(ValueType::Int),
+            "bool" => Some(ValueType::Bool),
+            "url" => Some(ValueType::Url),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ValueType::Int => "int",
+            ValueType::Bool => "bool",
+            ValueType::Url => "url",
+        }
+    }
+
+    fn matches(self, value: &str) -> bool {
+        match self {
+            ValueType::Int => value.parse::<i64>().is_ok(),
+            ValueType::Bool => matches!(
+                value.to_ascii_lowercase().as_str(),
+                "true" | "false" | "yes" | "no" | "on" | "off" | "1" | "0"
+            ),
+            ValueType::Url => {
+                static URL: OnceLock<Regex> = OnceLock::new();
+                URL.get_or_init(|| Regex::new(r"^[A-Za-z][A-Za-z0-9+.-]*://\S+$").unwrap()).is_match(value)
+            }
+        }
+    }
+}
+
+/// Rules for a single variable.
+#[derive(Debug, Clone, Default)]
+pub struct KeyRule {
+    pub required: bool,
+    pub value_type: Option<ValueType>,
+    /// Anchored, so the whole value must match
+    pub pattern: Option<Regex>,
+}
+
+/// Expectations on a built environment, see `Schema::from_file` for the format.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    pub keys: BTreeMap<String, KeyRule>,
+    /// Whether variables which are not declared in `keys` are accepted
+    pub allow_unknown: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// A required variable is not set
+    Missing,
+    /// A variable is not declared and the schema does not allow unknown ones
+    Unknown,
+    WrongType(ValueType),
+    /// The value does not match the pattern of its rule
+    PatternMismatch(String),
+}
+
+/// A variable of a built environment not matching its `Schema`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaViolation {
+    pub key: String,
+    /// File which set the offending value, `None` for missing variables
+    pub file: Option<PathBuf>,
+    pub kind: ViolationKind,
+}
+
+impl SchemaViolation {
+    /// Renders the violation like `Display`, showing paths with `show_file`.
+    pub fn display_with(&self, show_file: impl Fn(&Path) -> String) -> String {
+        let message = match &self.kind {
+            ViolationKind::Missing => format!("required variable {} is not set", self.key),
+            ViolationKind::Unknown => format!("{} is not declared in the schema", self.key),
+            ViolationKind::WrongType(value_type) => format!("{} is not of type {}", self.key, value_type.name()),
+            ViolationKind::PatternMismatch(pattern) => format!("{} does not match {}", self.key, pattern),
+        };
+        match &self.file {
+            Some(file) => format!("{}: {}", show_file(file), message),
+            None => message,
+        }
+    }
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.display_with(|p| p.display().to_string()))
+    }
+}
+
+impl Schema {
+    /// Reads a schema from a TOML file like
+    ///
+    /// ```toml
+    /// allow_unknown = true
+    ///
+    /// [keys.DATABASE_URL]
+    /// required = true
+    /// type = "url"
+    ///
+    /// [keys.PORT]
+    /// type = "int"
+    ///
+    /// [keys.LOG_LEVEL]
+    /// pattern = "debug|info|warn|error"
+    /// ```
+    ///
+    /// `allow_unknown` defaults to true, every rule field is optional. Types are `int`, `bool`
+    /// and `url`.
+    #[instrument(level = "debug")]
+    pub fn from_file(path: &Path) -> TreeResult<Schema> {
+        let invalid = |reason: String| TreeError::InvalidFormat { path: path.to_path_buf(), reason };
+        let content = std::fs::read_to_string(path).map_err(TreeError::FileReadError)?;
+        let document: Table = content.parse().map_err(|e: toml::de::Error| invalid(e.message().to_string()))?;
+
+        let allow_unknown = match document.get("allow_unknown") {
+            None => true,
+            Some(value) => value.as_bool().ok_or_else(|| invalid("allow_unknown must be a boolean".to_string()))?,
+        };
+        let mut keys = BTreeMap::new();
+        let declared = match document.get("keys") {
+            None => Table::new(),
+            Some(Value::Table(declared)) => declared.clone(),
+            Some(_) => return Err(invalid("keys must be a table".to_string())),
+        };
+        for (key, rule) in declared {
+            let rule_error = |reason: &str| invalid(format!("{}: {}", key, reason));
+            if !rule.is_table() {
+                return Err(rule_error("rule must be a table"));
+            }
+            let required = match rule.get("required") {
+                None => false,
+                Some(value) => value.as_bool().ok_or_else(|| rule_error("required must be a boolean"))?,
+            };
+            let value_type = match rule.get("type") {
+                None => None,
+                Some(value) => Some(value.as_str().and_then(ValueType::parse)
+                    .ok_or_else(|| rule_error("type must be one of int, bool, url"))?),
+            };
+            let pattern = match rule.get("pattern") {
+                None => None,
+                Some(value) => {
+                    let pattern = value.as_str().ok_or_else(|| rule_error("pattern must be a string"))?;
+                    // anchored, so the pattern describes the whole value
+                    Some(Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| rule_error(&e.to_string()))?)
+                }
+            };
+            keys.insert(key, KeyRule { required, value_type, pattern });
+        }
+        Ok(Schema { keys, allow_unknown })
+    }
+
+    /// Checks built variables against the schema. Values are compared without surrounding
+    /// quotes, as the shell sees them.
+    pub fn validate(&self, variables: &BTreeMap<String, SourcedValue>) -> Vec<SchemaViolation> {
+        let mut violations = Vec::new();
+        for (key, rule) in &self.keys {
+            if rule.required && !variables.contains_key(key) {
+                violations.push(SchemaViolation { key: key.clone(), file: None, kind: ViolationKind::Missing });
+            }
+        }
+        for (key, sourced) in variables {
+            let violation = |kind| SchemaViolation { key: key.clone(), file: sourced.source.file().map(Path::to_path_buf), kind };
+            let Some(rule) = self.keys.get(key) else {
+                if !self.allow_unknown {
+                    violations.push(violation(ViolationKind::Unknown));
+                }
+                continue;
+            };
+            let value = unquote(&sourced.value);
+            if let Some(value_type) = rule.value_type.filter(|t| !t.matches(value)) {
+                violations.push(violation(ViolationKind::WrongType(value_type)));
+            }
+            if let Some(pattern) = rule.pattern.as_ref().filter(|p| !p.is_match(value)) {
+                violations.push(violation(ViolationKind::PatternMismatch(pattern.as_str().to_string())));
+            }
+        }
+        violations
+    }
+}
@@ -0,0 +1,77 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use tracing::{debug, instrument};
+
+use crate::capabilities::require_program;
+use crate::errors::{TreeError, TreeResult};
+use crate::util::file::{normalize_lines, read_lines};
+
+/// File name suffix marking a SOPS-encrypted env file.
+pub const ENCRYPTED_SUFFIX: &str = ".enc.env";
+
+/// Whether an env file is SOPS-encrypted: its name ends with `.enc.env` or it contains the
+/// metadata SOPS adds to dotenv files (`sops_version=...`).
+pub fn is_sops_encrypted(path: &Path, lines: &[String]) -> bool {
+    let by_name = path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(ENCRYPTED_SUFFIX));
+    by_name || lines.iter().any(|line| line.starts_with("sops_version="))
+}
+
+/// Reads the lines of an env file, decrypting it with `sops` first if it is encrypted.
+///
+/// Decryption happens in memory only, the plaintext is never written to disk.
+#[instrument(level = "debug")]
+pub fn read_env_lines(path: &Path) -> TreeResult<Vec<String>> {
+    let lines = read_lines(path)?;
+    if !is_sops_encrypted(path, &lines) {
+        return Ok(lines);
+    }
+    debug!("Decrypting {:?} with sops", path);
+    let sops = require_program("sops")?;
+    let output = Command::new(sops)
+        .args(["--decrypt", "--input-type", "dotenv", "--output-type", "dotenv"])
+        .arg(path)
+        .output()
+        .map_err(TreeError::FileReadError)?;
+    decrypted_lines(path, output)
+}
+
+/// Same as `read_env_lines` for the `content` of `path` which is not read from the working
+/// tree, e.g. a file of a past git revision. The content is passed to `sops` on stdin.
+#[instrument(level = "debug", skip(content))]
+pub fn decrypt_env_content(path: &Path, content: &str) -> TreeResult<Vec<String>> {
+    let (lines, _) = normalize_lines(content);
+    if !is_sops_encrypted(path, &lines) {
+        return Ok(lines);
+    }
+    debug!("Decrypting {:?} with sops", path);
+    let sops = require_program("sops")?;
+    let mut child = Command::new(sops)
+        .args(["--decrypt", "--input-type", "dotenv", "--output-type", "dotenv", "/dev/stdin"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(TreeError::FileReadError)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content.as_bytes()).map_err(TreeError::FileReadError)?;
+    }
+    decrypted_lines(path, child.wait_with_output().map_err(TreeError::FileReadError)?)
+}
+
+fn decrypted_lines(path: &Path, output: Output) -> TreeResult<Vec<String>> {
+    if !output.status.success() {
+        return Err(TreeError::InvalidFormat {
+            path: path.to_path_buf(),
+            reason: format!("sops --decrypt failed: {}", String::from_utf8_lossy(&output.stderr).trim()),
+        });
+    }
+    let plaintext = String::from_utf8(output.stdout).map_err(|e| TreeError::InvalidFormat {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    })?;
+    Ok(normalize_lines(&plaintext).0)
+}
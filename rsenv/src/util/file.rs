@@ -0,0 +1,149 @@
+use crate::errors::{TreeError, TreeResult};
+use crate::util::path::PathExt;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+pub const BOM: char = '\u{feff}';
+
+/// Encoding details of a text file which must survive a rewrite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TextFormat {
+    pub bom: bool,
+    pub crlf: bool,
+    pub trailing_newline: bool,
+}
+
+/// Splits file content into lines, stripping a UTF-8 BOM and normalizing CRLF/CR line endings.
+pub fn normalize_lines(contents: &str) -> (Vec<String>, TextFormat) {
+    let (contents, bom) = match contents.strip_prefix(BOM) {
+        Some(rest) => (rest, true),
+        None => (contents, false),
+    };
+    let format = TextFormat {
+        bom,
+        crlf: contents.contains("\r\n"),
+        trailing_newline: contents.ends_with('\n') || contents.ends_with('\r'),
+    };
+    let normalized = contents.replace("\r\n", "\n").replace('\r', "\n");
+    let lines = normalized.lines().map(|s| s.to_string()).collect();
+    (lines, format)
+}
+
+/// Joins lines back into file content using the given format.
+pub fn denormalize_lines(lines: &[String], format: TextFormat) -> String {
+    let newline = if format.crlf { "\r\n" } else { "\n" };
+    let mut contents = String::new();
+    if format.bom {
+        contents.push(BOM);
+    }
+    contents.push_str(&lines.join(newline));
+    if format.trailing_newline && !lines.is_empty() {
+        contents.push_str(newline);
+    }
+    contents
+}
+
+/// Reads a text file as normalized lines (no BOM, no carriage returns).
+pub fn read_lines(path: &Path) -> TreeResult<Vec<String>> {
+    let (lines, _) = read_text_file(path)?;
+    Ok(lines)
+}
+
+/// Reads a text file as normalized lines together with its original format.
+pub fn read_text_file(path: &Path) -> TreeResult<(Vec<String>, TextFormat)> {
+    let contents = std::fs::read_to_string(path).map_err(TreeError::FileReadError)?;
+    Ok(normalize_lines(&contents))
+}
+
+/// Writes lines to a file, restoring BOM, line endings and trailing newline from `format`.
+pub fn write_text_file(path: &Path, lines: &[String], format: TextFormat) -> TreeResult<()> {
+    std::fs::write(path, denormalize_lines(lines, format)).map_err(TreeError::FileReadError)
+}
+
+/// How directory walks treat entries which cannot be read (e.g. permission denied).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WalkPolicy {
+    /// Skip the entry and record it as a `WalkIssue`
+    #[default]
+    SkipWithWarning,
+    /// Abort the walk with an error
+    Strict,
+}
+
+/// An entry skipped during a directory walk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalkIssue {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// A file read during a directory walk together with its normalized lines.
+pub type DirFile = (PathBuf, Vec<String>);
+
+/// Walks `dir` recursively and reads all files as normalized lines.
+///
+/// Unreadable entries either abort the walk or are collected as issues, depending on `policy`.
+pub fn read_dir_files(dir: &Path, policy: WalkPolicy) -> TreeResult<(Vec<DirFile>, Vec<WalkIssue>)> {
+    let mut files = Vec::new();
+    let mut issues = Vec::new();
+
+    for entry in WalkDir::new(dir) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let path = e.path().unwrap_or(dir).to_path_buf();
+                if policy == WalkPolicy::Strict {
+                    return Err(TreeError::PathResolution { path, reason: e.to_string() });
+                }
+                issues.push(WalkIssue { path, reason: e.to_string() });
+                continue;
+            }
+        };
+
+        if entry.file_type().is_file() {
+            match entry.path().to_utf8_str().and_then(|_| read_lines(entry.path())) {
+                Ok(lines) => files.push((entry.path().to_path_buf(), lines)),
+                Err(e) if policy == WalkPolicy::Strict => return Err(e),
+                Err(e) => issues.push(WalkIssue {
+                    path: entry.path().to_path_buf(),
+                    reason: e.to_string(),
+                }),
+            }
+        }
+    }
+    Ok((files, issues))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_lines_strips_bom_and_crlf() {
+        let (lines, format) = normalize_lines("\u{feff}# rsenv: a.env\r\nexport A=1\r\n");
+        assert_eq!(lines, vec!["# rsenv: a.env", "export A=1"]);
+        assert!(format.bom);
+        assert!(format.crlf);
+        assert!(format.trailing_newline);
+    }
+
+    #[test]
+    fn test_normalize_lines_handles_bare_cr() {
+        let (lines, format) = normalize_lines("export A=1\rexport B=2");
+        assert_eq!(lines, vec!["export A=1", "export B=2"]);
+        assert!(!format.crlf);
+        assert!(!format.trailing_newline);
+    }
+
+    #[test]
+    fn test_round_trip_preserves_format() {
+        for contents in [
+            "\u{feff}# rsenv: a.env\r\nexport A=1\r\n",
+            "# rsenv: a.env\nexport A=1",
+            "# rsenv: a.env\nexport A=1\n",
+        ] {
+            let (lines, format) = normalize_lines(contents);
+            assert_eq!(denormalize_lines(&lines, format), contents);
+        }
+    }
+}
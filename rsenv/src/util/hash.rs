@@ -0,0 +1,24 @@
+/// 64-bit FNV-1a hash as lowercase hex.
+///
+/// Used for content fingerprints that are persisted to disk, so it must stay stable across
+/// Rust releases (unlike `std::hash::DefaultHasher`).
+pub fn fnv1a_hex(data: &[u8]) -> String {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let hash = data.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    });
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a_hex_known_values() {
+        assert_eq!(fnv1a_hex(b""), "cbf29ce484222325");
+        assert_eq!(fnv1a_hex(b"a"), "af63dc4c8601ec8c");
+    }
+}
@@ -1,2 +1,5 @@
 pub mod testing;
-pub mod path;
\ No newline at end of file
+pub mod path;
+pub mod file;
+pub mod hash;
+pub mod value;
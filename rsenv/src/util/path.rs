@@ -1,11 +1,15 @@
 use crate::errors::{TreeError, TreeResult};
+use std::collections::HashMap;
 use std::ffi::OsStr;
-use std::path::{Path, PathBuf};
+use std::fs::symlink_metadata;
+use std::path::{Component, Path, PathBuf};
+use tracing::debug;
 
 pub trait PathExt {
     fn is_env_file(&self) -> bool;
     fn to_canonical(&self) -> TreeResult<PathBuf>;
     fn to_string_lossy_cached(&self) -> String;
+    fn to_utf8_str(&self) -> TreeResult<&str>;
 }
 
 impl PathExt for Path {
@@ -23,6 +27,54 @@ impl PathExt for Path {
     fn to_string_lossy_cached(&self) -> String {
         self.to_string_lossy().into_owned()
     }
+
+    /// Non-UTF-8 paths are rejected with `TreeError::InvalidPathEncoding` instead of being
+    /// converted lossily, which would silently produce a different path.
+    fn to_utf8_str(&self) -> TreeResult<&str> {
+        self.to_str().ok_or_else(|| TreeError::InvalidPathEncoding(self.to_path_buf()))
+    }
+}
+
+/// Memoizes canonicalization and symlink checks for one operation, e.g. a build, which
+/// resolves the same parents repeatedly. Each resolution walks all path components, which is
+/// slow on network file systems.
+///
+/// Cached canonical paths are checked for existence on use, so a file renamed in the middle of
+/// the operation is resolved again instead of being reported under its old name.
+#[derive(Debug, Default)]
+pub struct PathCache {
+    canonical: HashMap<PathBuf, PathBuf>,
+    symlinks: HashMap<PathBuf, bool>,
+}
+
+impl PathCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Same as `PathExt::to_canonical`, answered from the cache if possible.
+    pub fn to_canonical(&mut self, path: &Path) -> TreeResult<PathBuf> {
+        if let Some(canonical) = self.canonical.get(path) {
+            if canonical.exists() {
+                return Ok(canonical.clone());
+            }
+            debug!("Cached path vanished, resolving again: {:?}", path);
+        }
+        let canonical = path.to_canonical()?;
+        self.canonical.insert(path.to_path_buf(), canonical.clone());
+        self.canonical.insert(canonical.clone(), canonical.clone());
+        Ok(canonical)
+    }
+
+    /// Whether `path` itself is a symbolic link.
+    pub fn is_symlink(&mut self, path: &Path) -> TreeResult<bool> {
+        if let Some(is_symlink) = self.symlinks.get(path) {
+            return Ok(*is_symlink);
+        }
+        let is_symlink = symlink_metadata(path).map_err(TreeError::FileReadError)?.file_type().is_symlink();
+        self.symlinks.insert(path.to_path_buf(), is_symlink);
+        Ok(is_symlink)
+    }
 }
 
 pub fn ensure_file_exists(path: &Path) -> TreeResult<()> {
@@ -38,8 +90,52 @@ pub fn ensure_file_exists(path: &Path) -> TreeResult<()> {
     }
 }
 
+/// Fails with `TreeError::OutsideProject` unless `path` lies below `root` after resolving
+/// symlinks of both. `referenced_by` names the file which led to `path`, for the error message.
+///
+/// A path which does not exist yet, e.g. a file about to be written, is resolved via its
+/// nearest existing ancestor, see `normalize_existing_prefix`. `..` below that ancestor cannot
+/// be resolved and is refused.
+pub fn ensure_within(path: &Path, root: &Path, referenced_by: Option<&Path>) -> TreeResult<()> {
+    let canonical = normalize_existing_prefix(path);
+    let root = root.to_canonical()?;
+    let unresolved = canonical.components().any(|component| component == Component::ParentDir);
+    if canonical.starts_with(&root) && !unresolved {
+        Ok(())
+    } else {
+        Err(TreeError::OutsideProject {
+            path: canonical,
+            root,
+            referenced_by: referenced_by.map(Path::to_path_buf),
+        })
+    }
+}
+
+/// Resolves symlinks of the longest existing ancestor of `path` and appends the rest, so paths
+/// can be compared even if they do not exist yet. Without this, `/var/...` and its canonical
+/// `/private/var/...` on macOS are considered unrelated.
+pub fn normalize_existing_prefix(path: &Path) -> PathBuf {
+    let mut existing = path;
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return rest.iter().rev().fold(canonical, |acc, part| acc.join(part));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+/// Path of `to` relative to the directory `from`. Both sides are normalized with
+/// `normalize_existing_prefix` first, so mixing canonical and symlinked paths is fine.
 pub fn get_relative_path(from: &Path, to: &Path) -> TreeResult<PathBuf> {
-    pathdiff::diff_paths(to, from).ok_or_else(|| TreeError::PathResolution {
+    let (from, to) = (normalize_existing_prefix(from), normalize_existing_prefix(to));
+    pathdiff::diff_paths(&to, &from).ok_or_else(|| TreeError::PathResolution {
         path: to.to_path_buf(),
         reason: "Could not compute relative path".to_string(),
     })
@@ -89,6 +185,43 @@ pub fn relativize_paths(leaf_nodes: Vec<String>, start: &str) -> Vec<String> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_get_relative_path_through_symlinked_dir() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let real = tempdir.path().join("private").join("var");
+        std::fs::create_dir_all(real.join("envs")).unwrap();
+        let linked = tempdir.path().join("var");
+        std::os::unix::fs::symlink(&real, &linked).unwrap();
+
+        // `to` does not exist yet, `from` goes through the symlink
+        let to = real.canonicalize().unwrap().join("envs").join("new.env");
+        let relative = get_relative_path(&linked.join("envs"), &to).unwrap();
+        assert_eq!(relative, PathBuf::from("new.env"));
+    }
+
+    #[test]
+    fn test_path_cache_resolves_renamed_file_again() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let dir = tempdir.path().canonicalize().unwrap();
+        std::fs::create_dir(dir.join("old")).unwrap();
+        std::fs::write(dir.join("old").join("a.env"), "").unwrap();
+        std::os::unix::fs::symlink(dir.join("old"), dir.join("link")).unwrap();
+        let mut cache = PathCache::new();
+
+        let linked = dir.join("link").join("a.env");
+        assert_eq!(cache.to_canonical(&linked).unwrap(), dir.join("old").join("a.env"));
+        assert!(cache.is_symlink(&dir.join("link")).unwrap());
+
+        // mid-run: the directory moves and the link follows it
+        std::fs::rename(dir.join("old"), dir.join("new")).unwrap();
+        std::fs::remove_file(dir.join("link")).unwrap();
+        std::os::unix::fs::symlink(dir.join("new"), dir.join("link")).unwrap();
+        assert_eq!(cache.to_canonical(&linked).unwrap(), dir.join("new").join("a.env"));
+
+        std::fs::remove_file(dir.join("new").join("a.env")).unwrap();
+        assert!(cache.to_canonical(&linked).is_err());
+    }
+
     #[test]
     fn test_relativize_path_with_matching_path() {
         let path = "/some/dir/tests/foo/bar";
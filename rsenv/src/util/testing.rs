@@ -1,5 +1,8 @@
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Once;
+use tempfile::TempDir;
 use tracing::{debug, info};
 use tracing_subscriber::{
     filter::filter_fn,
@@ -61,6 +64,59 @@ fn setup_test_logging() {
     }
 }
 
+/// A generated env hierarchy living in a temporary directory, removed on drop.
+pub struct TestProject {
+    pub dir: TempDir,
+    pub root: PathBuf,
+    pub leaves: Vec<PathBuf>,
+}
+
+impl TestProject {
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+/// Creates a complete env hierarchy for tests: a single `root.env` and `depth` levels below,
+/// where every node has `fan_out` children. Level `n` lives in subdirectory `level<n>`, so parent
+/// references are relative paths across directories.
+///
+/// Every node exports `VAR_<level>` (overriding its ancestors) and its own `NODE_<name>`.
+pub fn create_test_project(depth: usize, fan_out: usize) -> TestProject {
+    let dir = TempDir::new().expect("Failed to create temp dir");
+    let root = dir.path().join("root.env");
+    fs::write(&root, "# rsenv:\nexport VAR_0=root\nexport NODE_root=root\n")
+        .expect("Failed to write root.env");
+
+    let mut current_level = vec![("root".to_string(), PathBuf::from("root.env"))];
+    for level in 1..=depth {
+        let level_dir = dir.path().join(format!("level{}", level));
+        fs::create_dir_all(&level_dir).expect("Failed to create level dir");
+
+        let mut next_level = Vec::new();
+        for (parent_name, parent_relative) in &current_level {
+            for i in 0..fan_out {
+                let name = format!("{}_{}", parent_name, i);
+                let relative = PathBuf::from(format!("level{}", level)).join(format!("{}.env", name));
+                let contents = format!(
+                    "# rsenv: ../{parent}\nexport VAR_{level}={name}\nexport NODE_{name}={name}\n",
+                    parent = parent_relative.display(),
+                    level = level,
+                    name = name,
+                );
+                fs::write(dir.path().join(&relative), contents).expect("Failed to write env file");
+                next_level.push((name, relative));
+            }
+        }
+        current_level = next_level;
+    }
+
+    let leaves = current_level.into_iter()
+        .map(|(_, relative)| dir.path().join(relative))
+        .collect();
+    TestProject { dir, root, leaves }
+}
+
 // test
 #[cfg(test)]
 mod tests {
@@ -70,4 +126,17 @@ mod tests {
     fn test_init_test_setup() {
         init_test_setup();
     }
+
+    #[test]
+    fn test_create_test_project() {
+        let project = create_test_project(2, 3);
+        assert!(project.root.exists());
+        assert_eq!(project.leaves.len(), 9);
+        assert!(project.leaves.iter().all(|leaf| leaf.exists()));
+
+        let (variables, files, _) = crate::build_env(&project.leaves[0]).unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(variables.get("VAR_0"), Some(&"root".to_string()));
+        assert_eq!(variables.get("VAR_2"), Some(&"root_0_0".to_string()));
+    }
 }
\ No newline at end of file
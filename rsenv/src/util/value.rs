@@ -0,0 +1,9 @@
+/// Removes one level of surrounding quotes, as the shell does when sourcing `export K="v"`.
+pub fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
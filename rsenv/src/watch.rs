@@ -0,0 +1,76 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use notify::{RecursiveMode, Watcher};
+use tracing::{debug, instrument};
+
+use crate::errors::{TreeError, TreeResult};
+use crate::{build_env_with_options, BuildOptions};
+
+/// Events arriving within this interval after a change are handled by a single rebuild, so
+/// saving many files at once (save-all, git checkout) rebuilds once.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Builds `leaf` and rebuilds it whenever a file of its hierarchy changes, calling `on_build`
+/// with every result which differs from the previous one. Stops when `on_build` returns false.
+///
+/// The directories of the files are watched instead of the files themselves, because editors
+/// often save by replacing a file. The watched set follows changes of the `# rsenv:` links.
+#[instrument(level = "debug", skip(on_build))]
+pub fn watch_env(
+    leaf: &Path,
+    options: &BuildOptions,
+    mut on_build: impl FnMut(&TreeResult<BTreeMap<String, String>>) -> bool,
+) -> TreeResult<()> {
+    let watch_error = |e: notify::Error| TreeError::InternalError(format!("Cannot watch files: {}", e));
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(watch_error)?;
+    let mut watched_dirs: HashSet<PathBuf> = HashSet::new();
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut previous: Option<String> = None;
+
+    loop {
+        let result = build_env_with_options(leaf, options).map(|(variables, built_files, _)| {
+            files = built_files;
+            variables
+        });
+        // an unreadable file keeps the previously watched files, so fixing it triggers a rebuild
+        let dirs: HashSet<PathBuf> = files.iter().filter_map(|f| f.parent().map(Path::to_path_buf)).collect();
+        for dir in watched_dirs.difference(&dirs) {
+            let _ = watcher.unwatch(dir);
+        }
+        for dir in dirs.difference(&watched_dirs) {
+            watcher.watch(dir, RecursiveMode::NonRecursive).map_err(watch_error)?;
+        }
+        watched_dirs = dirs;
+        debug!("Watching {:?}", watched_dirs);
+
+        // report only once watching, so no change made in reaction to the build gets lost
+        let fingerprint = format!("{:?}", result);
+        if previous.as_ref() != Some(&fingerprint) {
+            if !on_build(&result) {
+                return Ok(());
+            }
+            previous = Some(fingerprint);
+        }
+
+        wait_for_change(&rx, &files)?;
+    }
+}
+
+/// Blocks until an event concerns one of `files`, then drains events until it is quiet.
+fn wait_for_change(rx: &mpsc::Receiver<notify::Result<notify::Event>>, files: &[PathBuf]) -> TreeResult<()> {
+    loop {
+        let event = rx.recv().map_err(|e| TreeError::InternalError(e.to_string()))?;
+        let relevant = match event {
+            Ok(event) => event.paths.iter().any(|p| files.contains(p)),
+            Err(e) => return Err(TreeError::InternalError(format!("Cannot watch files: {}", e))),
+        };
+        if relevant {
+            while rx.recv_timeout(DEBOUNCE).is_ok() {}
+            return Ok(());
+        }
+    }
+}
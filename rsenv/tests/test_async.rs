@@ -0,0 +1,46 @@
+#![cfg(feature = "async")]
+
+use std::path::Path;
+
+use rstest::rstest;
+use rsenv::async_api::{build_env_async, build_env_stream, build_trees_async, BuildEvent};
+use rsenv::build_env;
+use rsenv::errors::TreeResult;
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("Failed to build runtime")
+}
+
+#[rstest]
+fn given_leaf_when_building_async_then_matches_sync_result() -> TreeResult<()> {
+    let leaf = Path::new("./tests/resources/environments/complex/level4.env");
+    let expected = build_env(leaf)?;
+    let (variables, files, is_dag) = runtime().block_on(build_env_async(leaf))?;
+    assert_eq!((variables, files, is_dag), expected);
+    Ok(())
+}
+
+#[rstest]
+fn given_leaf_when_streaming_build_then_reports_each_file_before_finishing() {
+    let leaf = Path::new("./tests/resources/environments/complex/level4.env");
+    let events = runtime().block_on(async {
+        let mut rx = build_env_stream(leaf);
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event);
+        }
+        events
+    });
+    let file_events = events.iter().filter(|e| matches!(e, BuildEvent::FileRead(_))).count();
+    assert_eq!(file_events, 5);
+    assert!(matches!(events.last(), Some(BuildEvent::Finished(Ok(_)))));
+}
+
+#[rstest]
+fn given_directory_when_building_trees_async_then_returns_trees() -> TreeResult<()> {
+    let trees = runtime().block_on(build_trees_async(Path::new("./tests/resources/environments/tree")))?;
+    assert_eq!(trees.len(), 1);
+    Ok(())
+}
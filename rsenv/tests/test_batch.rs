@@ -0,0 +1,50 @@
+use std::fs;
+
+use rstest::rstest;
+use rsenv::batch::{build_all, builds_to_json, find_leaves};
+use rsenv::errors::TreeResult;
+use rsenv::util::testing::create_test_project;
+use rsenv::BuildOptions;
+
+#[rstest]
+fn given_generated_project_when_finding_leaves_then_returns_only_leaves() -> TreeResult<()> {
+    let project = create_test_project(2, 3);
+    let mut expected: Vec<_> = project.leaves.iter().map(|l| l.canonicalize()).collect::<Result<_, _>>()?;
+    expected.sort();
+
+    assert_eq!(find_leaves(project.path())?, (expected, Vec::new()));
+    Ok(())
+}
+
+#[rstest]
+fn given_broken_leaf_when_building_all_then_reports_it_and_builds_the_others() -> TreeResult<()> {
+    let project = create_test_project(2, 2);
+    fs::write(project.path().join("broken.env"), "# rsenv: missing.env\nexport BROKEN=1\n")?;
+
+    let (builds, issues) = build_all(project.path(), &BuildOptions::default())?;
+
+    assert!(issues.is_empty());
+
+    assert_eq!(builds.len(), 5);
+    let failed: Vec<_> = builds.iter().filter(|b| b.result.is_err()).collect();
+    assert_eq!(failed.len(), 1);
+    assert!(failed[0].leaf.ends_with("broken.env"));
+
+    let json = builds_to_json(&builds, |leaf| leaf.file_name().unwrap().to_string_lossy().into_owned());
+    assert!(json["broken.env"]["error"].as_str().unwrap().contains("missing.env"));
+    assert_eq!(json["root_0_0.env"]["variables"]["VAR_0"], "root");
+    Ok(())
+}
+
+#[rstest]
+fn given_unreadable_file_when_building_all_then_reports_issue() -> TreeResult<()> {
+    let project = create_test_project(1, 1);
+    fs::write(project.path().join("binary.env"), [0xff, 0xfe, 0x00, 0xc3])?;
+
+    let (builds, issues) = build_all(project.path(), &BuildOptions::default())?;
+
+    assert!(builds.iter().all(|b| b.result.is_ok()));
+    assert_eq!(issues.len(), 1);
+    assert!(issues[0].path.ends_with("binary.env"));
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use std::fs;
+
+use rstest::rstest;
+use tempfile::tempdir;
+use rsenv::cache::{cached_envrc_stanza, hierarchy_hash};
+use rsenv::errors::TreeResult;
+use rsenv::BuildOptions;
+
+#[rstest]
+fn given_hierarchy_when_parent_changes_then_hash_changes() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let parent = tempdir.path().join("parent.env");
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&parent, "export A=1\n")?;
+    fs::write(&leaf, "# rsenv: parent.env\nexport B=2\n")?;
+
+    let before = hierarchy_hash(&leaf, &BuildOptions::default())?;
+    assert_eq!(hierarchy_hash(&leaf, &BuildOptions::default())?, before);
+
+    fs::write(&parent, "export A=2\n")?;
+    assert_ne!(hierarchy_hash(&leaf, &BuildOptions::default())?, before);
+    Ok(())
+}
+
+#[rstest]
+fn given_leaf_when_creating_stanza_then_sources_cache_keyed_by_hash() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let leaf = tempdir.path().join("it's.env");
+    fs::write(&leaf, "export A=1\n")?;
+
+    let stanza = cached_envrc_stanza(&leaf)?;
+
+    assert!(stanza.starts_with(&format!("rsenv_leaf='{}'\\''s.env'\n", tempdir.path().canonicalize()?.join("it").display())));
+    assert!(stanza.contains("rsenv_hash=\"$(rsenv hash \"$rsenv_leaf\")\""));
+    assert!(stanza.contains("mv \"$rsenv_tmp\" \"$rsenv_cache\""));
+    assert!(stanza.contains("    source \"$rsenv_cache\"\n"));
+    Ok(())
+}
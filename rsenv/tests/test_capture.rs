@@ -0,0 +1,46 @@
+use std::fs;
+
+use rstest::rstest;
+use tempfile::tempdir;
+use rsenv::build_env;
+use rsenv::capture::{capture_env, CaptureReport};
+use rsenv::errors::TreeResult;
+
+fn environment(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+#[rstest]
+fn given_existing_leaf_when_capturing_then_updates_in_place_and_appends_new() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&leaf, "# rsenv: parent.env\nexport AWS_REGION=us-east-1\nexport OTHER=1\n")?;
+    fs::write(tempdir.path().join("parent.env"), "export PARENT=p\n")?;
+    let env = environment(&[("AWS_REGION", "eu-west-1"), ("AWS_PROFILE", "dev team"), ("HOME", "/home/user")]);
+
+    let report = capture_env(&leaf, env, &["AWS_*".to_string()])?;
+
+    assert_eq!(report, CaptureReport {
+        added: vec!["AWS_PROFILE".to_string()],
+        updated: vec!["AWS_REGION".to_string()],
+    });
+    assert_eq!(fs::read_to_string(&leaf)?, "# rsenv: parent.env\nexport AWS_REGION=eu-west-1\nexport OTHER=1\n\
+        # captured from environment (AWS_*)\nexport AWS_PROFILE=\"dev team\"\n");
+    let (variables, _, _) = build_env(&leaf)?;
+    assert_eq!(variables.get("PARENT").map(String::as_str), Some("p"));
+    Ok(())
+}
+
+#[rstest]
+fn given_missing_leaf_when_capturing_then_creates_it_only_if_something_matched() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let leaf = tempdir.path().join("new.env");
+
+    let report = capture_env(&leaf, environment(&[("HOME", "/home/user")]), &["AWS_*".to_string()])?;
+    assert_eq!(report, CaptureReport::default());
+    assert!(!leaf.exists());
+
+    capture_env(&leaf, environment(&[("AWS_REGION", "eu-west-1")]), &["AWS_REGION".to_string()])?;
+    assert_eq!(fs::read_to_string(&leaf)?, "# captured from environment (AWS_REGION)\nexport AWS_REGION=eu-west-1\n");
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use std::collections::BTreeMap;
+
+use rstest::rstest;
+use rsenv::ci::{format_github_env, format_gitlab_dotenv, github_masks};
+use rsenv::errors::{TreeError, TreeResult};
+
+fn variables(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+    pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+#[rstest]
+fn given_variables_when_formatting_github_env_then_unquotes_and_uses_heredoc_for_multiline() {
+    let vars = variables(&[("A", "\"quoted value\""), ("CERT", "line1\nline2")]);
+
+    let contents = format_github_env(&vars);
+
+    let mut lines = contents.lines();
+    assert_eq!(lines.next(), Some("A=quoted value"));
+    let heredoc = lines.next().unwrap();
+    let delimiter = heredoc.strip_prefix("CERT<<").unwrap();
+    assert_eq!(lines.collect::<Vec<_>>(), vec!["line1", "line2", delimiter]);
+}
+
+#[rstest]
+fn given_secret_names_when_creating_github_masks_then_masks_only_their_values() {
+    let vars = variables(&[("API_KEY", "'abc'"), ("MONKEY", "banana"), ("DB_PASSWORD", "p1\np2")]);
+
+    let masks = github_masks(&vars);
+
+    assert_eq!(masks, vec!["::add-mask::abc", "::add-mask::p1", "::add-mask::p2"]);
+}
+
+#[rstest]
+fn given_multiline_value_when_formatting_gitlab_dotenv_then_fails() -> TreeResult<()> {
+    let vars = variables(&[("A", "\"a\""), ("B", "b")]);
+    assert_eq!(format_gitlab_dotenv(&vars)?, "A=a\nB=b\n");
+
+    let vars = variables(&[("CERT", "line1\nline2")]);
+    assert!(matches!(format_gitlab_dotenv(&vars), Err(TreeError::UnsupportedValue { key, .. }) if key == "CERT"));
+    Ok(())
+}
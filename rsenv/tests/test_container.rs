@@ -0,0 +1,23 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use rstest::rstest;
+use rsenv::container::{compose_override, run_in_container, Container};
+use rsenv::errors::{TreeError, TreeResult};
+
+#[rstest]
+fn given_multiline_value_when_running_in_container_then_fails_before_starting_docker() {
+    let variables = BTreeMap::from([("CERT".to_string(), "\"line1\nline2\"".to_string())]);
+    let container = Container::Docker { image: "alpine".to_string() };
+
+    let result = run_in_container(&container, &variables, &["env".to_string()]);
+
+    assert!(matches!(result, Err(TreeError::UnsupportedValue { key, .. }) if key == "CERT"));
+}
+
+#[rstest]
+fn given_service_when_creating_compose_override_then_references_env_file() -> TreeResult<()> {
+    let contents = compose_override("web", Path::new("/tmp/rsenv.env"))?;
+    assert_eq!(contents, "services:\n  web:\n    env_file:\n      - \"/tmp/rsenv.env\"\n");
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use std::fs;
+use std::path::Path;
+
+use rstest::rstest;
+use tempfile::tempdir;
+use rsenv::diff::{diff_env, EnvChange};
+use rsenv::errors::{TreeError, TreeResult};
+use rsenv::BuildOptions;
+
+#[rstest]
+fn given_two_leaves_when_diffing_env_then_reports_changes_of_whole_hierarchies() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    fs::write(tempdir.path().join("base.env"), "export SHARED=same\nexport REGION=eu\n")?;
+    let int = tempdir.path().join("int.env");
+    let prod = tempdir.path().join("prod.env");
+    fs::write(&int, "# rsenv: base.env\nexport URL=int.example.com\nexport DEBUG=1\n")?;
+    fs::write(&prod, "# rsenv: base.env\nexport URL=example.com\nexport REPLICAS=3\n")?;
+
+    let changes = diff_env(&int, &prod, &BuildOptions::default())?;
+
+    assert_eq!(changes, vec![
+        EnvChange::Removed { key: "DEBUG".into(), value: "1".into() },
+        EnvChange::Added { key: "REPLICAS".into(), value: "3".into() },
+        EnvChange::Changed { key: "URL".into(), old: "int.example.com".into(), new: "example.com".into() },
+    ]);
+    assert!(diff_env(&int, &int, &BuildOptions::default())?.is_empty());
+    Ok(())
+}
+
+#[rstest]
+fn given_missing_leaf_when_diffing_env_then_fails() {
+    let result = diff_env(Path::new("./tests/resources/environments/parallel/int.env"), Path::new("missing.env"), &BuildOptions::default());
+    assert!(matches!(result, Err(TreeError::FileNotFound(_))));
+}
@@ -1,27 +1,76 @@
+use std::fs;
 use std::io::Write;
 use std::path::Path;
 use std::process::Command;
 
 use rstest::rstest;
+use tempfile::tempdir;
 
 use rsenv::builder::TreeBuilder;
+use rsenv::cli::output::SecretMask;
 use rsenv::edit::{
-    create_branches, create_vimscript, open_files_in_editor, select_file_with_suffix,
+    create_branches, create_vimscript, open_files_in_editor, preview_environment, select_file_with_suffix,
 };
 use rsenv::errors::TreeResult;
-use rsenv::get_files;
+use rsenv::{get_files, BuildOptions, ValueMode};
 
 #[rstest]
 #[ignore = "Interactive via Makefile"]
 fn given_directory_when_selecting_file_with_suffix_then_returns_valid_file() -> TreeResult<()> {
     let dir = Path::new("./tests/resources/data");
     let suffix = ".env";
-    let result = select_file_with_suffix(dir, suffix)?;
+    let result = select_file_with_suffix(dir, suffix, &BuildOptions::default(), &SecretMask::new(&[], false))?;
     println!("Selected: {}", result.display());
     assert!(result.to_string_lossy().ends_with(suffix));
     Ok(())
 }
 
+#[rstest]
+fn given_env_file_when_previewing_then_shows_built_variables_or_error() {
+    let options = BuildOptions::default();
+    let secrets = SecretMask::new(&[], false);
+    let preview = preview_environment(Path::new("./tests/resources/environments/graph/level31.env"), &options, &secrets);
+    assert!(preview.contains("export root=root\n"));
+    let preview = preview_environment(Path::new("./tests/resources/environments/graph/missing.env"), &options, &secrets);
+    assert!(preview.starts_with("Cannot build environment: "));
+}
+
+#[rstest]
+#[case(false, "export API_TOKEN=********\nexport REGION=********\nexport USER=alice\n")]
+#[case(true, "export API_TOKEN=t0ken\nexport REGION=eu\nexport USER=alice\n")]
+fn given_secret_variables_when_previewing_then_masks_them_unless_shown(
+    #[case] show: bool,
+    #[case] expected: &str,
+) -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&leaf, "export API_TOKEN=t0ken\nexport REGION=eu\nexport USER=alice\n")?;
+
+    let preview = preview_environment(&leaf, &BuildOptions::default(), &SecretMask::new(&["REG*".to_string()], show));
+
+    assert_eq!(preview, expected);
+    Ok(())
+}
+
+#[rstest]
+fn given_build_options_when_previewing_then_builds_with_them() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let secrets = SecretMask::new(&[], false);
+    fs::write(tempdir.path().join("outside.env"), "export OUTSIDE=o\n")?;
+    fs::create_dir(tempdir.path().join("project"))?;
+    let leaf = tempdir.path().join("project/leaf.env");
+    fs::write(&leaf, "# rsenv: ../outside.env\nexport PADDED= x \n")?;
+
+    let options = BuildOptions { values: ValueMode::Trim, ..Default::default() };
+    assert_eq!(preview_environment(&leaf, &options, &secrets), "export OUTSIDE=o\nexport PADDED=x\n");
+
+    let options = BuildOptions { confine_to: Some(tempdir.path().join("project")), ..Default::default() };
+    let preview = preview_environment(&leaf, &options, &secrets);
+    assert!(preview.starts_with("Cannot build environment: Refusing to access"));
+    assert!(!preview.contains("OUTSIDE=o"));
+    Ok(())
+}
+
 #[rstest]
 #[ignore = "Interactive via Makefile"]
 fn given_valid_files_when_opening_in_editor_then_opens_successfully() -> TreeResult<()> {
@@ -0,0 +1,36 @@
+use std::collections::BTreeMap;
+
+use rstest::rstest;
+use rsenv::errors::{TreeError, TreeResult};
+use rsenv::export::{format_variables, ExportFormat};
+
+fn variables() -> BTreeMap<String, String> {
+    BTreeMap::from([
+        ("GREETING".to_string(), "\"it's a test\"".to_string()),
+        ("PATH_LIKE".to_string(), "C:\\tools".to_string()),
+    ])
+}
+
+#[rstest]
+#[case(ExportFormat::Posix, "export GREETING=\"it's a test\"\nexport PATH_LIKE=C:\\tools\n")]
+#[case(ExportFormat::Dotenv, "GREETING=\"it's a test\"\nPATH_LIKE=C:\\tools\n")]
+#[case(ExportFormat::Fish, "set -gx GREETING 'it\\'s a test'\nset -gx PATH_LIKE 'C:\\\\tools'\n")]
+#[case(ExportFormat::Powershell, "$env:GREETING = 'it''s a test'\n$env:PATH_LIKE = 'C:\\tools'\n")]
+#[case(ExportFormat::Docker, "GREETING=it's a test\nPATH_LIKE=C:\\tools\n")]
+fn given_variables_when_formatting_then_uses_syntax_of_format(
+    #[case] format: ExportFormat,
+    #[case] expected: &str,
+) -> TreeResult<()> {
+    assert_eq!(format_variables(&variables(), format)?, expected);
+    Ok(())
+}
+
+#[rstest]
+fn given_multiline_value_when_formatting_for_docker_then_fails() {
+    let variables = BTreeMap::from([("CERT".to_string(), "\"line1\nline2\"".to_string())]);
+
+    let result = format_variables(&variables, ExportFormat::Docker);
+
+    assert!(matches!(result, Err(TreeError::UnsupportedValue { key, .. }) if key == "CERT"));
+    assert!(format_variables(&variables, ExportFormat::Fish).is_ok());
+}
@@ -0,0 +1,55 @@
+use std::fs;
+
+use rstest::rstest;
+use tempfile::tempdir;
+use rsenv::errors::{TreeError, TreeResult};
+use rsenv::format::{format_env_file, late_parent_declaration};
+use rsenv::{build_env, build_env_with_options, BuildOptions};
+
+fn lines(content: &str) -> Vec<String> {
+    content.lines().map(String::from).collect()
+}
+
+#[rstest]
+#[case("# rsenv: a.env\nexport A=a\n", None)]
+#[case("# comment\n\n# rsenv: a.env\nA=a\n", None)]
+#[case("export A=a\n# rsenv: a.env\n", Some(1))]
+#[case("# header\nA=a\n\n# rsenv: a.env\n", Some(3))]
+fn given_file_when_checking_header_then_finds_late_declaration(
+    #[case] content: &str,
+    #[case] expected: Option<usize>,
+) {
+    assert_eq!(late_parent_declaration(&lines(content)), expected);
+}
+
+#[rstest]
+fn given_late_declaration_when_formatting_then_moves_it_to_the_top() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(tempdir.path().join("parent.env"), "export P=p\n")?;
+    fs::write(&leaf, "# leaf settings\nexport A=a\n\n# rsenv: parent.env\nexport B=b\n")?;
+    let (before, _, _) = build_env(&leaf)?;
+
+    assert!(format_env_file(&leaf)?);
+    assert_eq!(fs::read_to_string(&leaf)?, "# rsenv: parent.env\n# leaf settings\nexport A=a\n\nexport B=b\n");
+    assert!(!format_env_file(&leaf)?);
+    let (after, _, _) = build_env(&leaf)?;
+    assert_eq!(after, before);
+    Ok(())
+}
+
+#[rstest]
+fn given_late_declaration_when_building_strict_then_fails() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(tempdir.path().join("parent.env"), "export A=a\n# rsenv: root.env\n")?;
+    fs::write(tempdir.path().join("root.env"), "export R=r\n")?;
+    fs::write(&leaf, "# rsenv: parent.env\nexport L=l\n")?;
+    let options = BuildOptions { strict_header: true, ..Default::default() };
+
+    let result = build_env_with_options(&leaf, &options);
+
+    assert!(matches!(result, Err(TreeError::InvalidFormat { path, .. }) if path.ends_with("parent.env")));
+    assert!(build_env(&leaf).is_ok());
+    Ok(())
+}
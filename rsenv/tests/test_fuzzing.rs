@@ -0,0 +1,32 @@
+#![cfg(feature = "fuzzing")]
+
+use std::fs;
+
+use rstest::rstest;
+use walkdir::WalkDir;
+use rsenv::fuzzing::{link_roundtrip, parse_env};
+
+/// The test resources are the seed corpus of the fuzz targets, they must pass the harness.
+fn seeds() -> Vec<Vec<u8>> {
+    let mut seeds: Vec<Vec<u8>> = WalkDir::new("./tests/resources/environments")
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| fs::read(entry.path()).unwrap())
+        .collect();
+    seeds.extend([
+        b"".to_vec(),
+        b"\xef\xbb\xbf# rsenv: a.env\r\nexport A=1\r\n".to_vec(),
+        b"# rsenv: a.env[x]\nexport =\nexport\nA==\n# rsenv:\n".to_vec(),
+        b"\r\r\n\n# rsenv: a.env b.env[2]".to_vec(),
+    ]);
+    seeds
+}
+
+#[rstest]
+fn given_seed_corpus_when_running_fuzz_targets_then_invariants_hold() {
+    for seed in seeds() {
+        parse_env(&seed);
+        link_roundtrip(&seed);
+    }
+}
@@ -0,0 +1,109 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use rstest::rstest;
+use tempfile::tempdir;
+use rsenv::errors::{TreeError, TreeResult};
+use rsenv::git::build_env_at_rev;
+use rsenv::BuildOptions;
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["-c", "user.name=rsenv", "-c", "user.email=rsenv@example.com"])
+        .args(args)
+        .status()
+        .expect("failed to run git");
+    assert!(status.success());
+}
+
+#[rstest]
+fn given_changed_hierarchy_when_building_at_past_rev_then_uses_committed_files() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let root = tempdir.path();
+    fs::create_dir(root.join("envs"))?;
+    fs::write(root.join("base.env"), "export BASE=old\nexport SHARED=base\n")?;
+    fs::write(root.join("envs/prod.env"), "# rsenv: ../base.env\nexport PROD=old\nexport SHARED=prod\n")?;
+    git(root, &["init", "-q"]);
+    git(root, &["add", "."]);
+    git(root, &["commit", "-q", "-m", "initial"]);
+
+    fs::write(root.join("envs/prod.env"), "export PROD=new\n")?;
+    fs::remove_file(root.join("base.env"))?;
+
+    let (variables, files, is_dag) = build_env_at_rev(&root.join("envs/prod.env"), "HEAD", &BuildOptions::default())?;
+
+    assert_eq!(variables.get("PROD"), Some(&"old".to_string()));
+    assert_eq!(variables.get("BASE"), Some(&"old".to_string()));
+    assert_eq!(variables.get("SHARED"), Some(&"prod".to_string()));
+    assert_eq!(files.len(), 2);
+    assert!(files[1].ends_with("base.env"));
+    assert!(!is_dag);
+    Ok(())
+}
+
+#[rstest]
+fn given_unknown_rev_when_building_then_returns_error() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    fs::write(tempdir.path().join("local.env"), "export LOCAL=local\n")?;
+    git(tempdir.path(), &["init", "-q"]);
+
+    let result = build_env_at_rev(&tempdir.path().join("local.env"), "does-not-exist", &BuildOptions::default());
+
+    assert!(matches!(result, Err(TreeError::PathResolution { .. })));
+    Ok(())
+}
+
+#[rstest]
+fn given_prioritized_parents_at_rev_when_building_then_highest_priority_wins() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let root = tempdir.path();
+    fs::write(root.join("high.env"), "export SHARED=high\n")?;
+    fs::write(root.join("low.env"), "export SHARED=low\n")?;
+    fs::write(root.join("leaf.env"), "# rsenv: high.env[20] low.env[10]\nexport LEAF=leaf\n")?;
+    git(root, &["init", "-q"]);
+    git(root, &["add", "."]);
+    git(root, &["commit", "-q", "-m", "initial"]);
+
+    let (variables, _, is_dag) = build_env_at_rev(&root.join("leaf.env"), "HEAD", &BuildOptions::default())?;
+
+    assert_eq!(variables.get("SHARED"), Some(&"high".to_string()));
+    assert!(is_dag);
+    Ok(())
+}
+
+#[rstest]
+fn given_deep_hierarchy_at_rev_when_building_with_max_depth_then_returns_error() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let root = tempdir.path();
+    fs::write(root.join("a.env"), "export A=a\n")?;
+    fs::write(root.join("b.env"), "# rsenv: a.env\nexport B=b\n")?;
+    fs::write(root.join("c.env"), "# rsenv: b.env\nexport C=c\n")?;
+    git(root, &["init", "-q"]);
+    git(root, &["add", "."]);
+    git(root, &["commit", "-q", "-m", "initial"]);
+    let options = BuildOptions { max_depth: 2, ..Default::default() };
+
+    let result = build_env_at_rev(&root.join("c.env"), "HEAD", &options);
+
+    assert!(matches!(result, Err(TreeError::HierarchyTooDeep { limit: 2, .. })));
+    Ok(())
+}
+
+#[rstest]
+fn given_cycle_at_rev_when_building_then_returns_cyclic_dependency() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let root = tempdir.path();
+    fs::write(root.join("a.env"), "# rsenv: b.env\nexport A=a\n")?;
+    fs::write(root.join("b.env"), "# rsenv: a.env\nexport B=b\n")?;
+    git(root, &["init", "-q"]);
+    git(root, &["add", "."]);
+    git(root, &["commit", "-q", "-m", "initial"]);
+
+    let result = build_env_at_rev(&root.join("a.env"), "HEAD", &BuildOptions::default());
+
+    assert!(matches!(result, Err(TreeError::CyclicDependency { .. })));
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use std::fs;
+use std::path::Path;
+
+use rstest::rstest;
+use tempfile::tempdir;
+use rsenv::errors::{TreeError, TreeResult};
+use rsenv::graph::{dependency_graph, render_graph, EnvGraph, GraphFormat};
+use rsenv::util::file::WalkPolicy;
+use rsenv::util::path::PathExt;
+use rsenv::BuildOptions;
+
+fn write_dag(dir: &Path) -> TreeResult<()> {
+    fs::write(dir.join("base.env"), "export BASE=base\n")?;
+    fs::write(dir.join("shared.env"), "export SHARED=shared\n")?;
+    fs::write(dir.join("leaf.env"), "# rsenv: base.env shared.env\nexport LEAF=leaf\n")?;
+    fs::write(dir.join("other.env"), "# rsenv: base.env\nexport OTHER=other\n")?;
+    fs::write(dir.join("lonely.env"), "export LONELY=lonely\n")?;
+    Ok(())
+}
+
+#[rstest]
+fn given_dag_when_building_graph_then_maps_linked_files_to_parents() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let dir = tempdir.path().to_canonical()?;
+    write_dag(&dir)?;
+
+    let (parents_of, issues) = dependency_graph(&dir, WalkPolicy::Strict, &BuildOptions::default())?;
+
+    assert!(issues.is_empty());
+    assert_eq!(parents_of.len(), 4, "lonely.env is not part of the graph");
+    assert_eq!(parents_of[&dir.join("leaf.env")], vec![dir.join("base.env"), dir.join("shared.env")]);
+    assert!(parents_of[&dir.join("base.env")].is_empty());
+    Ok(())
+}
+
+#[rstest]
+fn given_dag_when_rendering_dot_then_highlights_files_with_several_parents() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let dir = tempdir.path().to_canonical()?;
+    write_dag(&dir)?;
+    let (parents_of, _) = dependency_graph(&dir, WalkPolicy::Strict, &BuildOptions::default())?;
+
+    let dot = render_graph(&parents_of, GraphFormat::Dot, |f| f.file_name().unwrap().to_string_lossy().to_string());
+
+    assert_eq!(dot, "\
+digraph rsenv {
+    node [shape=box];
+    \"base.env\";
+    \"leaf.env\" [style=filled, fillcolor=lightyellow];
+    \"other.env\";
+    \"shared.env\";
+    \"base.env\" -> \"leaf.env\";
+    \"shared.env\" -> \"leaf.env\";
+    \"base.env\" -> \"other.env\";
+}
+");
+    Ok(())
+}
+
+#[rstest]
+fn given_dag_when_rendering_mermaid_then_numbers_nodes_and_marks_dag_class() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let dir = tempdir.path().to_canonical()?;
+    write_dag(&dir)?;
+    let (parents_of, _) = dependency_graph(&dir, WalkPolicy::Strict, &BuildOptions::default())?;
+
+    let mermaid = render_graph(&parents_of, GraphFormat::Mermaid, |f| f.file_name().unwrap().to_string_lossy().to_string());
+
+    assert_eq!(mermaid, "\
+graph TD
+    n0[\"base.env\"]
+    n1[\"leaf.env\"]:::dag
+    n2[\"other.env\"]
+    n3[\"shared.env\"]
+    n0 --> n1
+    n3 --> n1
+    n0 --> n2
+    classDef dag fill:#ffffe0
+");
+    Ok(())
+}
+
+#[rstest]
+fn given_missing_parent_when_building_graph_then_fails() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    fs::write(tempdir.path().join("leaf.env"), "# rsenv: missing.env\n")?;
+
+    assert!(dependency_graph(tempdir.path(), WalkPolicy::Strict, &BuildOptions::default()).is_err());
+    Ok(())
+}
+
+fn names(files: Vec<&Path>) -> Vec<String> {
+    files.iter().map(|f| f.file_name().unwrap().to_string_lossy().into_owned()).collect()
+}
+
+#[rstest]
+fn given_dag_when_querying_env_graph_then_answers_relations() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let dir = tempdir.path().to_canonical()?;
+    write_dag(&dir)?;
+    fs::write(dir.join("child.env"), "# rsenv: leaf.env\n")?;
+
+    let (graph, issues) = EnvGraph::from_directory(&dir, WalkPolicy::Strict, &BuildOptions::default())?;
+
+    assert!(issues.is_empty());
+    assert!(!graph.contains(&dir.join("lonely.env")));
+    assert_eq!(names(graph.roots()), vec!["base.env", "shared.env"]);
+    assert_eq!(names(graph.leaves()), vec!["child.env", "other.env"]);
+    assert_eq!(names(graph.parents(&dir.join("leaf.env"))), vec!["base.env", "shared.env"]);
+    assert_eq!(names(graph.ancestors(&dir.join("child.env"))), vec!["leaf.env", "base.env", "shared.env"]);
+    assert_eq!(names(graph.descendants(&dir.join("base.env"))), vec!["leaf.env", "other.env", "child.env"]);
+    // paths are canonicalized for the lookup
+    fs::create_dir(dir.join("sub"))?;
+    assert_eq!(names(graph.children(&dir.join("sub/../shared.env"))), vec!["leaf.env"]);
+
+    let order = names(graph.topological_order()?);
+    assert_eq!(order, vec!["base.env", "other.env", "shared.env", "leaf.env", "child.env"]);
+    Ok(())
+}
+
+#[rstest]
+fn given_cycle_when_ordering_env_graph_then_fails_with_cycle() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let dir = tempdir.path().to_canonical()?;
+    fs::write(dir.join("a.env"), "# rsenv: b.env\n")?;
+    fs::write(dir.join("b.env"), "# rsenv: a.env\n")?;
+    fs::write(dir.join("c.env"), "# rsenv: a.env\n")?;
+
+    let (graph, _) = EnvGraph::from_directory(&dir, WalkPolicy::Strict, &BuildOptions::default())?;
+
+    match graph.topological_order() {
+        Err(TreeError::CyclicDependency { path }) => {
+            assert_eq!(path.len(), 3);
+            assert_eq!(path.first(), path.last());
+        }
+        other => panic!("expected CyclicDependency, got {:?}", other),
+    }
+    Ok(())
+}
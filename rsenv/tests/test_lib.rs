@@ -10,8 +10,11 @@ use tempfile::tempdir;
 use fs_extra::{copy_items, dir};
 use tracing::debug;
 use rsenv::errors::{TreeError, TreeResult};
-use rsenv::{build_env, build_env_vars, extract_env, is_dag, link, link_all, print_files, unlink};
+use rsenv::{build_env, build_env_vars, build_env_vars_with_prefix, build_env_json, build_env_with_options, build_env_with_hook, build_env_with_overrides, build_env_with_sources, expand_env_vars, extract_env, is_dag, is_dag_with_policy, link, link_all, link_all_with_policy, link_with_policy, print_files, unlink, BuildOptions, OnExisting, SourcedValue, ValueMode, ValueSource, VariableAction};
+use rsenv::util::file::WalkPolicy;
+use rsenv::util::path::PathExt;
 use rsenv::util::testing;
+use rsenv::util::testing::create_test_project;
 
 #[ctor::ctor]
 fn init() {
@@ -108,7 +111,6 @@ export var2=test_var2\n";
 
 #[rstest]
 fn given_invalid_parent_when_building_env_vars_then_returns_error() -> TreeResult<()> {
-    let original_dir = env::current_dir()?;
     let result = build_env_vars(Path::new("./tests/resources/environments/graph2/error.env"));
     match result {
         Ok(_) => panic!("Expected an error, but got OK"),
@@ -117,7 +119,6 @@ fn given_invalid_parent_when_building_env_vars_then_returns_error() -> TreeResul
             assert!(re.is_match(&e.to_string()));
         }
     }
-    env::set_current_dir(original_dir)?;  // error occurs after change directory in extract_env
     Ok(())
 }
 
@@ -192,6 +193,39 @@ fn given_graph_structure_when_checking_dag_then_returns_true() -> TreeResult<()>
     Ok(())
 }
 
+#[rstest]
+fn given_unreadable_file_when_checking_dag_then_reports_issue_unless_strict() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    fs::write(tempdir.path().join("child.env"), "# rsenv: a.env b.env\n")?;
+    fs::write(tempdir.path().join("binary.dat"), [0xff, 0xfe, 0x00, 0xc3])?;
+
+    let (is_dag, issues) = is_dag_with_policy(tempdir.path(), WalkPolicy::SkipWithWarning)?;
+    assert!(is_dag);
+    assert_eq!(issues.len(), 1);
+    assert!(issues[0].path.ends_with("binary.dat"));
+
+    assert!(is_dag_with_policy(tempdir.path(), WalkPolicy::Strict).is_err());
+    Ok(())
+}
+
+#[rstest]
+fn given_non_utf8_file_name_when_building_env_then_returns_invalid_path_encoding() -> TreeResult<()> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let tempdir = tempdir()?;
+    let leaf = tempdir.path().join(OsStr::from_bytes(b"le\xffaf.env"));
+    fs::write(&leaf, "export A=a\n")?;
+
+    let result = build_env(&leaf);
+    assert!(matches!(result, Err(TreeError::InvalidPathEncoding(_))));
+
+    let (_, issues) = is_dag_with_policy(tempdir.path(), WalkPolicy::SkipWithWarning)?;
+    assert_eq!(issues.len(), 1);
+    assert!(issues[0].reason.contains("not valid UTF-8"));
+    Ok(())
+}
+
 #[rstest]
 #[ignore = "Only for interactive exploration"]
 fn given_symlinked_file_when_extracting_env_then_handles_symlink_correctly() -> TreeResult<()> {
@@ -237,3 +271,585 @@ fn given_symlinked_file_when_extracting_env_then_outputs_warning()-> TreeResult<
     fs::remove_file("./tests/resources/environments/complex/symlink.env")?;
     Ok(())
 }
+
+#[rstest]
+fn given_crlf_bom_file_when_extracting_env_then_parses_parent_and_variables() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    fs::write(tempdir.path().join("parent.env"), "export PARENT=parent\r\n")?;
+    let child = tempdir.path().join("child.env");
+    fs::write(&child, "\u{feff}# rsenv: parent.env\r\nexport CHILD=child\r\n")?;
+
+    let (variables, parents) = extract_env(&child)?;
+    assert_eq!(variables.get("CHILD"), Some(&"child".to_string()));
+    assert_eq!(parents.len(), 1);
+    assert!(parents[0].ends_with("parent.env"));
+
+    let (variables, _, _) = build_env(&child)?;
+    assert_eq!(variables.get("PARENT"), Some(&"parent".to_string()));
+    Ok(())
+}
+
+#[rstest]
+fn given_crlf_bom_file_when_linking_and_unlinking_then_preserves_format() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let parent = tempdir.path().join("parent.env");
+    fs::write(&parent, "export PARENT=parent\r\n")?;
+    let child = tempdir.path().join("child.env");
+    fs::write(&child, "\u{feff}# rsenv: old.env\r\nexport CHILD=child\r\n")?;
+
+    link(&parent, &child)?;
+    assert_eq!(fs::read_to_string(&child)?, "\u{feff}# rsenv: parent.env\r\nexport CHILD=child\r\n");
+
+    unlink(&child)?;
+    assert_eq!(fs::read_to_string(&child)?, "\u{feff}# rsenv:\r\nexport CHILD=child\r\n");
+    Ok(())
+}
+
+#[rstest]
+fn given_prefix_when_building_vars_then_prefixes_names_and_rewrites_references() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&leaf, "export HOST=localhost\nexport URL=http://${HOST}:$PORT/$HOME\nexport PORT=80\n")?;
+
+    let env_vars = build_env_vars_with_prefix(&leaf, "MYAPP_", true)?;
+    assert_eq!(env_vars, "export MYAPP_HOST=localhost
+export MYAPP_PORT=80
+export MYAPP_URL=http://${MYAPP_HOST}:$MYAPP_PORT/$HOME\n");
+
+    let env_vars = build_env_vars_with_prefix(&leaf, "MYAPP_", false)?;
+    assert!(env_vars.contains("export MYAPP_URL=http://${HOST}:$PORT/$HOME\n"));
+    Ok(())
+}
+
+#[rstest]
+fn given_hook_when_building_env_then_transforms_and_vetoes_variables() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let dir = tempdir.path().to_canonical()?;
+    fs::write(dir.join("base.env"), "export Region=eu\nexport DEBUG=1\n")?;
+    fs::write(dir.join("leaf.env"), "# rsenv: base.env\nexport Name=leaf\n")?;
+
+    let mut seen = Vec::new();
+    let variables = build_env_with_hook(&dir.join("leaf.env"), &BuildOptions::default(), |name, value, file| {
+        seen.push((name.to_string(), file.file_name().unwrap().to_string_lossy().into_owned()));
+        match name {
+            "DEBUG" => VariableAction::Drop,
+            _ => VariableAction::Replace { name: name.to_lowercase(), value: value.to_string() },
+        }
+    })?;
+
+    assert_eq!(variables, BTreeMap::from([
+        ("name".to_string(), "leaf".to_string()),
+        ("region".to_string(), "eu".to_string()),
+    ]));
+    assert_eq!(seen, vec![
+        ("DEBUG".to_string(), "base.env".to_string()),
+        ("Name".to_string(), "leaf.env".to_string()),
+        ("Region".to_string(), "base.env".to_string()),
+    ]);
+    Ok(())
+}
+
+#[rstest]
+fn given_relative_parents_when_building_from_several_threads_then_each_resolves_its_own() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let dir = tempdir.path().to_canonical()?;
+    for name in ["a", "b", "c", "d"] {
+        fs::create_dir_all(dir.join(name).join("sub"))?;
+        fs::write(dir.join(name).join("base.env"), format!("export BASE={}\n", name))?;
+        fs::write(dir.join(name).join("sub").join("leaf.env"), "# rsenv: ../base.env\n")?;
+    }
+
+    let handles: Vec<_> = ["a", "b", "c", "d"].into_iter()
+        .map(|name| {
+            let leaf = dir.join(name).join("sub").join("leaf.env");
+            std::thread::spawn(move || {
+                (0..20).map(|_| build_env(&leaf).map(|(variables, _, _)| variables["BASE"].clone()))
+                    .collect::<TreeResult<Vec<String>>>()
+                    .map(|values| (name, values))
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        let (name, values) = handle.join().expect("build thread panicked")?;
+        assert!(values.iter().all(|value| value == name));
+    }
+    Ok(())
+}
+
+#[rstest]
+fn given_cyclic_hierarchy_when_building_env_then_returns_cycle_path() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let dir = tempdir.path().to_canonical()?;
+    fs::write(dir.join("leaf.env"), "# rsenv: a.env\nexport LEAF=leaf\n")?;
+    fs::write(dir.join("a.env"), "# rsenv: b.env\nexport A=a\n")?;
+    fs::write(dir.join("b.env"), "# rsenv: a.env\nexport B=b\n")?;
+
+    match build_env(&dir.join("leaf.env")) {
+        Err(TreeError::CyclicDependency { path }) => {
+            assert_eq!(path, vec![dir.join("a.env"), dir.join("b.env"), dir.join("a.env")]);
+        }
+        other => panic!("Expected CyclicDependency, got {:?}", other),
+    }
+    fs::write(dir.join("self.env"), "# rsenv: self.env\n")?;
+    assert!(matches!(build_env(&dir.join("self.env")), Err(TreeError::CyclicDependency { path }) if path.len() == 2));
+    Ok(())
+}
+
+#[rstest]
+fn given_diamond_hierarchy_when_building_env_then_reads_shared_parent_once() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let dir = tempdir.path().to_canonical()?;
+    fs::write(dir.join("leaf.env"), "# rsenv: left.env right.env\n")?;
+    fs::write(dir.join("left.env"), "# rsenv: base.env\n")?;
+    fs::write(dir.join("right.env"), "# rsenv: base.env\n")?;
+    fs::write(dir.join("base.env"), "export BASE=base\n")?;
+
+    let (variables, files, _) = build_env(&dir.join("leaf.env"))?;
+
+    assert_eq!(variables.get("BASE").map(String::as_str), Some("base"));
+    assert_eq!(files.len(), 4);
+    Ok(())
+}
+
+#[rstest]
+fn given_hierarchy_deeper_than_limit_when_building_env_then_returns_traversal_path() -> TreeResult<()> {
+    let project = create_test_project(3, 1);
+    let leaf = &project.leaves[0];
+
+    let options = BuildOptions { max_depth: 3, ..Default::default() };
+    match build_env_with_options(leaf, &options) {
+        Err(TreeError::HierarchyTooDeep { limit, path }) => {
+            assert_eq!(limit, 3);
+            assert_eq!(path.len(), 4);
+            assert!(path[0].ends_with("root_0_0_0.env"));
+            assert!(path[3].ends_with("root.env"));
+        }
+        other => panic!("Expected HierarchyTooDeep, got {:?}", other),
+    }
+
+    let options = BuildOptions { max_depth: 4, ..Default::default() };
+    let (_, files, _) = build_env_with_options(leaf, &options)?;
+    assert_eq!(files.len(), 4);
+    Ok(())
+}
+
+#[rstest]
+#[case(ValueMode::Verbatim, Some(" padded "), Some(""), "")]
+#[case(ValueMode::Trim, Some("padded"), Some(""), "")]
+#[case(ValueMode::DropEmpty, Some("padded"), None, "parent")]
+fn given_value_mode_when_building_env_then_normalizes_values(
+    #[case] values: ValueMode,
+    #[case] padded: Option<&str>,
+    #[case] empty: Option<&str>,
+    #[case] overridden: &str,
+) -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    fs::write(tempdir.path().join("parent.env"), "export OVERRIDDEN=parent\n")?;
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&leaf, "# rsenv: parent.env\nexport PADDED= padded \nexport EMPTY=\nexport OVERRIDDEN=\"\"\n")?;
+
+    let options = BuildOptions { values, ..Default::default() };
+    let (variables, _, _) = build_env_with_options(&leaf, &options)?;
+
+    assert_eq!(variables.get("PADDED").map(String::as_str), padded);
+    assert_eq!(variables.get("EMPTY").map(String::as_str), empty);
+    let expected = if overridden.is_empty() { "\"\"" } else { overridden };
+    assert_eq!(variables.get("OVERRIDDEN").map(String::as_str), Some(expected));
+    Ok(())
+}
+
+#[rstest]
+fn given_parent_outside_confinement_when_building_env_then_names_referencing_file() -> TreeResult<()> {
+    let outside = tempdir()?;
+    let project = tempdir()?;
+    fs::write(outside.path().join("secret.env"), "export SECRET=secret\n")?;
+    symlink(outside.path().join("secret.env"), project.path().join("linked.env"))?;
+    let leaf = project.path().join("leaf.env");
+    fs::write(&leaf, "# rsenv: linked.env\nexport LEAF=leaf\n")?;
+
+    let options = BuildOptions { confine_to: Some(project.path().to_path_buf()), ..Default::default() };
+    match build_env_with_options(&leaf, &options) {
+        Err(TreeError::OutsideProject { path, referenced_by, .. }) => {
+            assert!(path.ends_with("secret.env"));
+            assert!(referenced_by.unwrap().ends_with("leaf.env"));
+        }
+        other => panic!("Expected OutsideProject, got {:?}", other),
+    }
+
+    fs::write(&leaf, "export LEAF=leaf\n")?;
+    let (variables, _, _) = build_env_with_options(&leaf, &options)?;
+    assert_eq!(variables.len(), 1);
+    Ok(())
+}
+
+#[rstest]
+#[case(OnExisting::Replace, "# rsenv: b.env\n")]
+#[case(OnExisting::Append, "# rsenv: a.env b.env\n")]
+fn given_child_with_parent_when_linking_with_policy_then_applies_policy(
+    #[case] on_existing: OnExisting,
+    #[case] expected: &str,
+) -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let child = tempdir.path().join("child.env");
+    fs::write(tempdir.path().join("a.env"), "export A=a\n")?;
+    fs::write(tempdir.path().join("b.env"), "export B=b\n")?;
+    fs::write(&child, "# rsenv: a.env\nexport C=c\n")?;
+
+    link_with_policy(&tempdir.path().join("b.env"), &child, on_existing)?;
+    link_with_policy(&tempdir.path().join("b.env"), &child, on_existing)?;
+
+    assert!(fs::read_to_string(&child)?.starts_with(expected));
+    Ok(())
+}
+
+#[rstest]
+#[case(OnExisting::Append)]
+#[case(OnExisting::Abort)]
+fn given_prioritized_parent_when_linking_it_again_then_leaves_child_unchanged(
+    #[case] on_existing: OnExisting,
+) -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let child = tempdir.path().join("child.env");
+    fs::write(tempdir.path().join("a.env"), "export A=a\n")?;
+    fs::write(tempdir.path().join("b.env"), "export B=b\n")?;
+    let content = "# rsenv: a.env[10] ./b.env[20]\nexport C=c\n";
+    fs::write(&child, content)?;
+
+    link_with_policy(&tempdir.path().join("b.env"), &child, on_existing)?;
+
+    assert_eq!(fs::read_to_string(&child)?, content);
+    Ok(())
+}
+
+#[rstest]
+fn given_prioritized_parents_when_appending_then_new_parent_wins() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let child = tempdir.path().join("child.env");
+    fs::write(tempdir.path().join("a.env"), "export SHARED=a\n")?;
+    fs::write(tempdir.path().join("b.env"), "export SHARED=b\n")?;
+    fs::write(tempdir.path().join("c.env"), "export SHARED=c\n")?;
+    fs::write(&child, "# rsenv: b.env[20] a.env[10]\nexport C=c\n")?;
+
+    link_with_policy(&tempdir.path().join("c.env"), &child, OnExisting::Append)?;
+
+    assert!(fs::read_to_string(&child)?.starts_with("# rsenv: b.env[20] a.env[10] c.env[30]\n"));
+    let (variables, _, _) = build_env(&child)?;
+    assert_eq!(variables.get("SHARED").map(String::as_str), Some("c"));
+    Ok(())
+}
+
+#[rstest]
+fn given_env_var_parent_when_appending_then_matches_expanded_path() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let shared = tempdir.path().join("shared");
+    fs::create_dir(&shared)?;
+    fs::write(shared.join("base.env"), "export BASE=base\n")?;
+    fs::write(tempdir.path().join("local.env"), "export LOCAL=local\n")?;
+    let child = tempdir.path().join("child.env");
+    fs::write(&child, "# rsenv: ${RSENV_TEST_LINK_DIR}/base.env\nexport C=c\n")?;
+    env::set_var("RSENV_TEST_LINK_DIR", "shared");
+
+    link_with_policy(&shared.join("base.env"), &child, OnExisting::Append)?;
+    assert!(fs::read_to_string(&child)?.starts_with("# rsenv: ${RSENV_TEST_LINK_DIR}/base.env\n"));
+
+    link_with_policy(&tempdir.path().join("local.env"), &child, OnExisting::Append)?;
+    assert!(fs::read_to_string(&child)?.starts_with("# rsenv: ${RSENV_TEST_LINK_DIR}/base.env local.env\n"));
+    Ok(())
+}
+
+#[rstest]
+fn given_prioritized_parents_when_linking_or_unlinking_then_replaces_declaration() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let child = tempdir.path().join("child.env");
+    fs::write(tempdir.path().join("a.env"), "export A=a\n")?;
+    fs::write(tempdir.path().join("b.env"), "export B=b\n")?;
+    fs::write(&child, "# rsenv: a.env[10] b.env[20]\nexport C=c\n")?;
+
+    link(&tempdir.path().join("a.env"), &child)?;
+    assert_eq!(fs::read_to_string(&child)?, "# rsenv: a.env\nexport C=c\n");
+
+    fs::write(&child, "# rsenv: a.env[10] b.env[20]\nexport C=c\n")?;
+    unlink(&child)?;
+    assert_eq!(fs::read_to_string(&child)?, "# rsenv:\nexport C=c\n");
+    Ok(())
+}
+
+#[rstest]
+fn given_conflicting_chain_when_linking_all_with_abort_then_changes_nothing() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let nodes: Vec<PathBuf> = ["root.env", "middle.env", "leaf.env"].iter()
+        .map(|name| tempdir.path().join(name))
+        .collect();
+    fs::write(tempdir.path().join("other.env"), "export O=o\n")?;
+    fs::write(&nodes[0], "export R=r\n")?;
+    fs::write(&nodes[1], "export M=m\n")?;
+    fs::write(&nodes[2], "# rsenv: other.env\nexport L=l\n")?;
+
+    let result = link_all_with_policy(&nodes, OnExisting::Abort, &BuildOptions::default());
+
+    assert!(matches!(result, Err(TreeError::ParentExists { .. })));
+    assert_eq!(fs::read_to_string(&nodes[1])?, "export M=m\n");
+    fs::write(&nodes[2], "export L=l\n")?;
+    link_all_with_policy(&nodes, OnExisting::Abort, &BuildOptions::default())?;
+    assert!(fs::read_to_string(&nodes[2])?.starts_with("# rsenv: middle.env\n"));
+    Ok(())
+}
+
+#[rstest]
+#[case("# rsenv: base.env region.env\n", "region")]
+#[case("# rsenv: base.env[20] region.env[10]\n", "base")]
+#[case("# rsenv: region.env[10] base.env[20]\n", "base")]
+fn given_parent_priorities_when_building_env_then_highest_priority_wins(
+    #[case] declaration: &str,
+    #[case] expected: &str,
+) -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    fs::write(tempdir.path().join("base.env"), "export SHARED=base\n")?;
+    fs::write(tempdir.path().join("region.env"), "export SHARED=region\n")?;
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&leaf, format!("{}export LEAF=leaf\n", declaration))?;
+
+    let (variables, _, is_dag) = build_env(&leaf)?;
+
+    assert!(is_dag);
+    assert_eq!(variables.get("SHARED").map(String::as_str), Some(expected));
+    Ok(())
+}
+
+#[rstest]
+#[case("# rsenv: base.env[10] region.env\n")]
+#[case("# rsenv: base.env[high]\n")]
+fn given_invalid_parent_priorities_when_extracting_env_then_returns_invalid_format(
+    #[case] declaration: &str,
+) -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    fs::write(tempdir.path().join("base.env"), "export SHARED=base\n")?;
+    fs::write(tempdir.path().join("region.env"), "export SHARED=region\n")?;
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&leaf, declaration)?;
+
+    let result = extract_env(&leaf);
+
+    assert!(matches!(result, Err(TreeError::InvalidFormat { .. })), "got {:?}", result);
+    Ok(())
+}
+
+#[rstest]
+#[case("$RSENV_TEST_EXPAND_DIR/base.env", "shared/base.env")]
+#[case("${RSENV_TEST_EXPAND_DIR}/base.env", "shared/base.env")]
+#[case("${RSENV_TEST_EXPAND_UNSET:-fallback}/base.env", "fallback/base.env")]
+#[case("${RSENV_TEST_EXPAND_EMPTY:-${RSENV_TEST_EXPAND_DIR}}/base.env", "shared/base.env")]
+#[case("${RSENV_TEST_EXPAND_DIR:?set it}/base.env", "shared/base.env")]
+#[case("$RSENV_TEST_EXPAND_UNSET/base.env", "/base.env")]
+#[case("cost$/base.env", "cost$/base.env")]
+fn given_variable_references_when_expanding_then_behaves_like_posix_shell(
+    #[case] input: &str,
+    #[case] expected: &str,
+) -> TreeResult<()> {
+    env::set_var("RSENV_TEST_EXPAND_DIR", "shared");
+    env::set_var("RSENV_TEST_EXPAND_EMPTY", "");
+
+    assert_eq!(expand_env_vars(input)?, expected);
+    Ok(())
+}
+
+#[rstest]
+#[case("${RSENV_TEST_EXPAND_UNSET:?CONFIG_DIR must point to the shared configs}", "CONFIG_DIR must point to the shared configs")]
+#[case("${RSENV_TEST_EXPAND_UNSET:?}", "parameter null or not set")]
+#[case("${RSENV_TEST_EXPAND_UNSET", "missing closing brace")]
+#[case("${RSENV_TEST_EXPAND_UNSET/a/b}", "unsupported expansion")]
+fn given_invalid_or_required_references_when_expanding_then_fails(
+    #[case] input: &str,
+    #[case] reason_start: &str,
+) -> TreeResult<()> {
+    match expand_env_vars(input) {
+        Err(TreeError::VariableExpansion { reason, .. }) => assert!(reason.starts_with(reason_start), "got {}", reason),
+        other => panic!("expected VariableExpansion, got {:?}", other),
+    }
+    Ok(())
+}
+
+#[rstest]
+fn given_parent_path_with_variables_when_building_env_then_resolves_or_reports_them() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    fs::create_dir(tempdir.path().join("fallback"))?;
+    fs::write(tempdir.path().join("fallback/base.env"), "export BASE=fallback\n")?;
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&leaf, "# rsenv: ${RSENV_TEST_PARENT_UNSET:-fallback}/base.env\nexport LEAF=leaf\n")?;
+
+    let (variables, _, _) = build_env(&leaf)?;
+    assert_eq!(variables.get("BASE").map(String::as_str), Some("fallback"));
+
+    fs::write(&leaf, "# rsenv: ${RSENV_TEST_PARENT_UNSET:?configure RSENV_TEST_PARENT_UNSET}/base.env\n")?;
+    match extract_env(&leaf) {
+        Err(TreeError::InvalidFormat { reason, .. }) => assert!(reason.contains("configure RSENV_TEST_PARENT_UNSET")),
+        other => panic!("expected InvalidFormat, got {:?}", other),
+    }
+    Ok(())
+}
+
+#[rstest]
+fn given_dag_when_building_env_then_lists_files_child_first_in_declaration_order() -> TreeResult<()> {
+    let (_, files, _) = build_env(Path::new("./tests/resources/environments/graph/level31.env"))?;
+
+    let names: Vec<_> = files.iter().map(|f| f.file_name().unwrap().to_string_lossy().into_owned()).collect();
+    assert_eq!(names, vec!["level31.env", "level21.env", "level11.env", "root.env", "level12.env", "level13.env"]);
+    Ok(())
+}
+
+#[rstest]
+#[case("./tests/resources/environments/complex/level4.env")]
+#[case("./tests/resources/environments/graph/level31.env")]
+#[case("./tests/resources/environments/graph2/level22.env")]
+#[case("./tests/resources/environments/parallel/a_test.env")]
+fn given_hierarchy_when_building_env_then_file_order_is_stable_and_complete(#[case] leaf: &str) -> TreeResult<()> {
+    let (_, files, _) = build_env(Path::new(leaf))?;
+    let (_, again, _) = build_env(Path::new(leaf))?;
+
+    assert_eq!(files, again);
+    assert_eq!(files[0], Path::new(leaf).canonicalize()?);
+    let parents: Vec<Vec<PathBuf>> = files.iter().map(|f| extract_env(f).map(|(_, p)| p)).collect::<TreeResult<_>>()?;
+    for (index, file) in files.iter().enumerate() {
+        assert!(!files[..index].contains(file), "{:?} listed twice", file);
+        if index > 0 {
+            // listed after the first file referencing it
+            let first_child = parents.iter().position(|p| p.contains(file)).expect("unreferenced file");
+            assert!(first_child < index);
+        }
+    }
+    for parent in parents.iter().flatten() {
+        assert!(files.contains(parent), "{:?} missing", parent);
+    }
+    Ok(())
+}
+
+#[rstest]
+fn given_overrides_when_building_env_then_they_win_without_changing_files() -> TreeResult<()> {
+    let leaf = Path::new("./tests/resources/environments/complex/level4.env");
+    let before = fs::read_to_string(leaf)?;
+    let (built, _, _) = build_env(leaf)?;
+    let overrides = BTreeMap::from([
+        ("VAR_7".to_string(), "what-if".to_string()),
+        ("NEW_VAR".to_string(), "new".to_string()),
+    ]);
+
+    let sourced = build_env_with_overrides(leaf, &BuildOptions::default(), &overrides)?;
+
+    assert_eq!(sourced["VAR_7"], SourcedValue { value: "what-if".to_string(), source: ValueSource::Override });
+    assert_eq!(sourced["NEW_VAR"].source, ValueSource::Override);
+    assert!(sourced["VAR_1"].source.file().is_some());
+    assert_eq!(sourced.len(), built.len() + 1);
+    assert_eq!(fs::read_to_string(leaf)?, before);
+    Ok(())
+}
+
+#[rstest]
+fn given_build_options_when_building_env_with_overrides_then_applies_them() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let dir = tempdir.path().to_canonical()?;
+    fs::write(dir.join("outside.env"), "export OUTSIDE=o\n")?;
+    fs::create_dir(dir.join("project"))?;
+    let leaf = dir.join("project/leaf.env");
+    fs::write(&leaf, "export PADDED= x \n")?;
+    let overrides = BTreeMap::from([("EXTRA".to_string(), "e".to_string())]);
+
+    let options = BuildOptions { values: ValueMode::Trim, ..Default::default() };
+    let sourced = build_env_with_overrides(&leaf, &options, &overrides)?;
+    assert_eq!(sourced["PADDED"], SourcedValue { value: "x".to_string(), source: ValueSource::File(leaf.clone()) });
+
+    fs::write(&leaf, "# rsenv: ../outside.env\n")?;
+    let options = BuildOptions { confine_to: Some(dir.join("project")), ..Default::default() };
+    let result = build_env_with_overrides(&leaf, &options, &overrides);
+    assert!(matches!(result, Err(TreeError::OutsideProject { .. })));
+    Ok(())
+}
+
+#[rstest]
+fn given_dag_when_building_env_with_sources_then_records_winning_file() -> TreeResult<()> {
+    let leaf = Path::new("./tests/resources/environments/graph/level31.env");
+    let (variables, _, _) = build_env(leaf)?;
+
+    let sourced = build_env_with_sources(leaf, &BuildOptions::default())?;
+
+    let file_of = |key: &str| sourced[key].source.file().unwrap().file_name().unwrap().to_string_lossy().into_owned();
+    assert_eq!(file_of("var13"), "level31.env");
+    assert_eq!(file_of("var21"), "level21.env");
+    assert_eq!(file_of("root"), "root.env");
+    let values: BTreeMap<_, _> = sourced.into_iter().map(|(k, s)| (k, s.value)).collect();
+    assert_eq!(values, variables);
+
+    let json = build_env_json(leaf)?;
+    assert_eq!(json["root"]["value"], "root");
+    assert!(json["root"]["file"].as_str().unwrap().ends_with("graph/root.env"));
+    Ok(())
+}
+
+#[rstest]
+fn given_plain_dotenv_lines_when_building_env_then_they_take_part_in_hierarchy() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    fs::write(tempdir.path().join("parent.env"), "SHARED=parent\nPARENT_ONLY=p\n")?;
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&leaf, "# rsenv: parent.env\nSHARED=leaf\nexport EXPORTED=e\nnot an assignment\nFOO-BAR=x\n")?;
+
+    let (variables, _, _) = build_env(&leaf)?;
+
+    assert_eq!(variables, BTreeMap::from([
+        ("EXPORTED".to_string(), "e".to_string()),
+        ("PARENT_ONLY".to_string(), "p".to_string()),
+        ("SHARED".to_string(), "leaf".to_string()),
+    ]));
+    assert_eq!(build_env_vars(&leaf)?, "export EXPORTED=e\nexport PARENT_ONLY=p\nexport SHARED=leaf\n");
+    Ok(())
+}
+
+#[rstest]
+fn given_values_containing_equals_signs_when_building_env_then_keeps_them_whole() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&leaf, "TOKEN=abc==\nexport URL=postgres://db/app?sslmode=require\n")?;
+
+    let (variables, _, _) = build_env(&leaf)?;
+
+    assert_eq!(variables, BTreeMap::from([
+        ("TOKEN".to_string(), "abc==".to_string()),
+        ("URL".to_string(), "postgres://db/app?sslmode=require".to_string()),
+    ]));
+    Ok(())
+}
+
+#[rstest]
+fn given_value_file_directive_when_building_env_then_reads_value_from_file() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    fs::create_dir(tempdir.path().join("certs"))?;
+    fs::write(tempdir.path().join("certs/ca.pem"), "\"-----BEGIN CERTIFICATE-----\"\n")?;
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&leaf, "# rsenv-file: CERT=certs/ca.pem\nexport OTHER=assigned\n# rsenv-file: OTHER=certs/ca.pem\nexport CERT=assigned\n")?;
+
+    let (variables, _, _) = build_env(&leaf)?;
+
+    assert_eq!(variables, BTreeMap::from([
+        ("CERT".to_string(), "assigned".to_string()),
+        ("OTHER".to_string(), "\"-----BEGIN CERTIFICATE-----\"".to_string()),
+    ]));
+    Ok(())
+}
+
+#[rstest]
+fn given_value_file_outside_project_when_building_confined_then_refuses() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let project = tempdir.path().join("project");
+    fs::create_dir(&project)?;
+    fs::write(tempdir.path().join("secret"), "secret\n")?;
+    let leaf = project.join("leaf.env");
+    fs::write(&leaf, "# rsenv-file: SECRET=../secret\n")?;
+
+    let options = BuildOptions { confine_to: Some(project.clone()), ..Default::default() };
+    let result = build_env_with_options(&leaf, &options);
+
+    assert!(matches!(result, Err(TreeError::OutsideProject { .. })));
+    assert!(matches!(build_env(&project.join("leaf.env")), Ok((variables, _, _)) if variables["SECRET"] == "secret"));
+    fs::write(&leaf, "# rsenv-file: SECRET\n")?;
+    assert!(matches!(build_env(&leaf), Err(TreeError::InvalidFormat { .. })));
+    Ok(())
+}
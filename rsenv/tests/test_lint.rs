@@ -0,0 +1,111 @@
+use std::fs;
+use std::path::Path;
+
+use rstest::rstest;
+use tempfile::tempdir;
+use rsenv::diagnostics::DiagnosticKind;
+use rsenv::errors::TreeResult;
+use rsenv::lint::{findings_to_json, lint_directory, LintFinding, LintKind};
+use rsenv::util::file::WalkPolicy;
+use rsenv::util::path::PathExt;
+use rsenv::{BuildOptions, ValueMode};
+
+fn kinds(findings: &[LintFinding], dir: &Path) -> Vec<(String, Option<usize>, LintKind)> {
+    findings.iter()
+        .map(|f| (f.file.strip_prefix(dir).unwrap().display().to_string(), f.line, f.kind.clone()))
+        .collect()
+}
+
+#[rstest]
+fn given_linked_files_when_linting_then_reports_each_problem() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let dir = tempdir.path().to_canonical()?;
+    fs::write(dir.join("base.env"), "export SHARED=base\nexport REGION=eu\n")?;
+    fs::write(dir.join("leaf.env"), "# rsenv: base.env\nexport REGION=eu\nexport A=1\nexport A=2\n")?;
+    fs::write(dir.join("broken.env"), "# rsenv: base.env missing.env\nexport B=b\n")?;
+    fs::write(dir.join("lonely.env"), "export L=l\n")?;
+    fs::write(dir.join("notes.txt"), "export N=n\nexport N=n\n")?;
+
+    let (findings, issues) = lint_directory(&dir, WalkPolicy::Strict, &BuildOptions::default())?;
+
+    assert!(issues.is_empty());
+    assert_eq!(kinds(&findings, &dir), vec![
+        ("broken.env".to_string(), Some(1), LintKind::BrokenParent("missing.env".to_string())),
+        ("broken.env".to_string(), Some(1), LintKind::Diagnostic(DiagnosticKind::ImplicitParentOrder)),
+        ("leaf.env".to_string(), Some(2), LintKind::RedundantOverride("REGION".to_string())),
+        ("leaf.env".to_string(), Some(4), LintKind::Diagnostic(DiagnosticKind::DuplicateKey("A".to_string()))),
+        ("lonely.env".to_string(), None, LintKind::OrphanFile),
+    ]);
+    Ok(())
+}
+
+#[rstest]
+fn given_value_mode_when_linting_then_compares_normalized_values() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let dir = tempdir.path().to_canonical()?;
+    fs::write(dir.join("base.env"), "export X=x\n")?;
+    fs::write(dir.join("leaf.env"), "# rsenv: base.env\nexport X= x \n")?;
+    let redundant = |findings: &[LintFinding]| findings.iter().any(|f| f.kind == LintKind::RedundantOverride("X".to_string()));
+
+    let (findings, _) = lint_directory(&dir, WalkPolicy::Strict, &BuildOptions::default())?;
+    assert!(!redundant(&findings));
+
+    let options = BuildOptions { values: ValueMode::Trim, ..Default::default() };
+    let (findings, _) = lint_directory(&dir, WalkPolicy::Strict, &options)?;
+    assert!(redundant(&findings));
+    Ok(())
+}
+
+#[rstest]
+fn given_file_problems_when_linting_then_reports_same_diagnostics_as_build() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let dir = tempdir.path().to_canonical()?;
+    fs::write(dir.join("base.env"), "export BASE=base\n")?;
+    fs::write(dir.join("leaf.env"), "export URL=${BASE}/${MISSING}\nexport EMPTY=\n# rsenv: base.env\n")?;
+
+    let (findings, _) = lint_directory(&dir, WalkPolicy::Strict, &BuildOptions::default())?;
+
+    assert_eq!(kinds(&findings, &dir), vec![
+        ("leaf.env".to_string(), Some(1), LintKind::Diagnostic(DiagnosticKind::UnexpandedVariable {
+            key: "URL".to_string(),
+            reference: "MISSING".to_string(),
+        })),
+        ("leaf.env".to_string(), Some(2), LintKind::Diagnostic(DiagnosticKind::EmptyValue("EMPTY".to_string()))),
+        ("leaf.env".to_string(), Some(3), LintKind::Diagnostic(DiagnosticKind::LateParentDeclaration)),
+    ]);
+    let json = findings_to_json(&findings, |f| f.display().to_string());
+    assert_eq!(json[2]["kind"], "late-parent-declaration");
+    Ok(())
+}
+
+#[rstest]
+fn given_cyclic_files_when_linting_then_reports_cycle_once() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let dir = tempdir.path().to_canonical()?;
+    fs::write(dir.join("a.env"), "# rsenv: b.env\nexport X=1\n")?;
+    fs::write(dir.join("b.env"), "# rsenv: c.env\nexport X=1\n")?;
+    fs::write(dir.join("c.env"), "# rsenv: a.env\nexport X=1\n")?;
+    fs::write(dir.join("leaf.env"), "# rsenv: a.env\nexport Y=2\n")?;
+
+    let (findings, _) = lint_directory(&dir, WalkPolicy::Strict, &BuildOptions::default())?;
+
+    let cycle = vec![dir.join("a.env"), dir.join("b.env"), dir.join("c.env"), dir.join("a.env")];
+    assert_eq!(findings, vec![LintFinding { file: dir.join("a.env"), line: None, kind: LintKind::Cycle(cycle) }]);
+    let json = findings_to_json(&findings, |f| f.file_name().unwrap().to_string_lossy().into_owned());
+    assert_eq!(json[0]["kind"], "cycle");
+    assert_eq!(json[0]["files"], serde_json::json!(["a.env", "b.env", "c.env", "a.env"]));
+    assert_eq!(json[0]["line"], serde_json::Value::Null);
+    Ok(())
+}
+
+#[rstest]
+fn given_standalone_files_when_linting_then_reports_no_orphans() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    fs::write(tempdir.path().join("a.env"), "export A=a\n")?;
+    fs::write(tempdir.path().join("b.env"), "export B=b\n")?;
+
+    let (findings, _) = lint_directory(tempdir.path(), WalkPolicy::Strict, &BuildOptions::default())?;
+
+    assert!(findings.is_empty());
+    Ok(())
+}
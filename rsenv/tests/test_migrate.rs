@@ -0,0 +1,62 @@
+use std::fs;
+
+use rstest::rstest;
+use tempfile::tempdir;
+use rsenv::{build_env, BuildOptions};
+use rsenv::errors::{TreeError, TreeResult};
+use rsenv::migrate::{migrate_direnv, migrate_dotenv_vault};
+
+#[rstest]
+fn given_envrc_with_dotenv_when_migrating_then_creates_hierarchy() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let envrc = tempdir.path().join(".envrc");
+    fs::write(tempdir.path().join(".env"), "# base\nDB_HOST=localhost\nexport DB_PORT=5432\n")?;
+    fs::write(&envrc, "dotenv\nexport APP=demo\nlayout python3\n")?;
+    let output = tempdir.path().join("envs/local.env");
+
+    let report = migrate_direnv(&envrc, &output, &BuildOptions::default())?;
+
+    assert_eq!(report.created.len(), 2);
+    assert_eq!(report.manual, vec!["line 3: layout python3"]);
+    let (variables, files, _) = build_env(&output)?;
+    assert_eq!(files.len(), 2);
+    assert_eq!(variables.get("APP"), Some(&"demo".to_string()));
+    assert_eq!(variables.get("DB_HOST"), Some(&"localhost".to_string()));
+    assert_eq!(variables.get("DB_PORT"), Some(&"5432".to_string()));
+    Ok(())
+}
+
+#[rstest]
+fn given_dotenv_vault_project_when_migrating_then_converts_stages() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    fs::write(tempdir.path().join(".env"), "STAGE=development\n")?;
+    fs::write(tempdir.path().join(".env.production"), "STAGE=production\nKEY=\"-----BEGIN\n")?;
+    fs::write(tempdir.path().join(".env.vault"), "DOTENV_VAULT_PRODUCTION=\"...\"\n")?;
+    fs::write(tempdir.path().join(".env.keys"), "DOTENV_KEY_PRODUCTION=\"...\"\n")?;
+    fs::write(tempdir.path().join("README.md"), "# demo\n")?;
+    let output_dir = tempdir.path().join("environments");
+
+    let report = migrate_dotenv_vault(tempdir.path(), &output_dir, &BuildOptions::default())?;
+
+    assert_eq!(report.created, vec![output_dir.join("development.env"), output_dir.join("production.env")]);
+    assert_eq!(report.manual.len(), 3);
+    let (variables, _, _) = build_env(&output_dir.join("production.env"))?;
+    assert_eq!(variables.get("STAGE"), Some(&"production".to_string()));
+    assert!(!variables.contains_key("KEY"));
+    Ok(())
+}
+
+#[rstest]
+fn given_existing_target_when_migrating_then_refuses_to_overwrite() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let envrc = tempdir.path().join(".envrc");
+    let output = tempdir.path().join("local.env");
+    fs::write(&envrc, "export APP=demo\n")?;
+    fs::write(&output, "export KEEP=me\n")?;
+
+    let result = migrate_direnv(&envrc, &output, &BuildOptions::default());
+
+    assert!(matches!(result, Err(TreeError::InvalidFormat { .. })));
+    assert_eq!(fs::read_to_string(&output)?, "export KEEP=me\n");
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use std::fs;
+use std::path::Path;
+
+use rstest::rstest;
+use tempfile::tempdir;
+use rsenv::build_env;
+use rsenv::errors::TreeResult;
+use rsenv::optimize::{optimize, OptimizeOptions};
+
+#[rstest]
+fn given_small_parents_when_optimizing_then_inlines_them_into_leaf() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let root = tempdir.path().join("root.env");
+    let small = tempdir.path().join("small.env");
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&root, "export R1=r1\nexport R2=r2\nexport R3=r3\nexport R4=r4\nexport SHARED=root\n")?;
+    fs::write(&small, "# rsenv: root.env\nexport SMALL=small\nexport SHARED=small\n")?;
+    fs::write(&leaf, "# rsenv: small.env\nexport LEAF=leaf\nexport SMALL=leaf\n")?;
+    let (expected, _, _) = build_env(&leaf)?;
+
+    let report = optimize(&leaf, &OptimizeOptions::default())?;
+
+    assert_eq!(report.inlined.len(), 1);
+    let contents = fs::read_to_string(&leaf)?;
+    assert!(contents.starts_with("# rsenv: root.env\n# inlined from: small.env\nexport SHARED=small\n"));
+    let (variables, files, _) = build_env(&leaf)?;
+    assert_eq!(variables, expected);
+    assert_eq!(files.len(), 2);
+    Ok(())
+}
+
+#[rstest]
+fn given_chain_with_large_values_when_optimizing_then_builds_same_variables() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let cert = "x".repeat(4096);
+    fs::write(tempdir.path().join("root.env"), "export ROOT=root\nexport SHARED=root\n")?;
+    fs::write(tempdir.path().join("middle.env"), format!("# rsenv: root.env\nCERT=\"{}\"\nSHARED='middle'\n", cert))?;
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&leaf, format!("# rsenv: middle.env\nexport KEY=\"{}\"\nexport ROOT=leaf\n", cert))?;
+    let (before, _, _) = build_env(&leaf)?;
+
+    let report = optimize(&leaf, &OptimizeOptions::default())?;
+
+    assert_eq!(report.inlined.len(), 2);
+    assert_eq!(report.externalized.len(), 2);
+    let (after, files, _) = build_env(&leaf)?;
+    assert_eq!(after, before);
+    assert_eq!(files, vec![leaf.canonicalize()?]);
+    let contents = fs::read_to_string(&leaf)?;
+    assert!(contents.contains("# rsenv-file: CERT=leaf.values/CERT\n"));
+    assert!(contents.contains("# rsenv-file: KEY=leaf.values/KEY\n"));
+    assert_eq!(fs::read_to_string(tempdir.path().join("leaf.values/KEY"))?, format!("\"{}\"", cert));
+    Ok(())
+}
+
+#[rstest]
+fn given_large_value_when_optimizing_then_externalizes_it() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let leaf = tempdir.path().join("leaf.env");
+    let cert = "x".repeat(64);
+    fs::write(&leaf, format!("export CERT=\"{}\"\nexport SMALL=small\n", cert))?;
+    let (before, _, _) = build_env(&leaf)?;
+
+    let options = OptimizeOptions { max_value_len: 32, ..Default::default() };
+    let report = optimize(&leaf, &options)?;
+
+    let value_file = tempdir.path().join("leaf.values/CERT");
+    assert_eq!(report.externalized, vec![("CERT".to_string(), value_file.canonicalize()?)]);
+    assert_eq!(fs::read_to_string(&leaf)?, "# rsenv-file: CERT=leaf.values/CERT\nexport SMALL=small\n");
+    let (after, _, _) = build_env(&leaf)?;
+    assert_eq!(after, before);
+    Ok(())
+}
+
+#[rstest]
+fn given_nothing_to_optimize_when_optimizing_then_leaves_file_untouched() -> TreeResult<()> {
+    let leaf = Path::new("./tests/resources/environments/complex/level4.env");
+    let before = fs::read_to_string(leaf)?;
+    let options = OptimizeOptions { max_inline_vars: 0, ..Default::default() };
+    let report = optimize(leaf, &options)?;
+    assert!(report.inlined.is_empty());
+    assert!(report.externalized.is_empty());
+    assert_eq!(fs::read_to_string(leaf)?, before);
+    Ok(())
+}
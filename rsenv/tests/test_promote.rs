@@ -0,0 +1,58 @@
+use std::fs;
+
+use rstest::rstest;
+use tempfile::tempdir;
+use rsenv::build_env;
+use rsenv::BuildOptions;
+use rsenv::errors::{TreeError, TreeResult};
+use rsenv::promote::promote_variable;
+
+#[rstest]
+fn given_child_variable_when_promoting_then_moves_it_into_parent() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let base = tempdir.path().join("base.env");
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&base, "# shared settings\r\nexport REGION=eu\r\n")?;
+    fs::write(&leaf, "# rsenv: base.env\nexport LOG=old\n# verbose logging\nexport LOG=debug\nexport LEAF=leaf\n")?;
+
+    promote_variable("LOG", &leaf, &base, false, &BuildOptions::default())?;
+
+    assert_eq!(fs::read_to_string(&base)?, "# shared settings\r\nexport REGION=eu\r\nexport LOG=debug\r\n");
+    assert_eq!(fs::read_to_string(&leaf)?, "# rsenv: base.env\n# verbose logging\nexport LEAF=leaf\n");
+    let (variables, _, _) = build_env(&leaf)?;
+    assert_eq!(variables.get("LOG").map(String::as_str), Some("debug"));
+    Ok(())
+}
+
+#[rstest]
+fn given_parent_variable_when_copying_down_then_replaces_child_assignment() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let base = tempdir.path().join("base.env");
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&base, "REGION=us\n")?;
+    fs::write(&leaf, "# rsenv: base.env\nexport REGION=eu\nexport LEAF=leaf\n")?;
+
+    promote_variable("REGION", &base, &leaf, true, &BuildOptions::default())?;
+
+    assert_eq!(fs::read_to_string(&base)?, "REGION=us\n");
+    assert_eq!(fs::read_to_string(&leaf)?, "# rsenv: base.env\nREGION=us\nexport LEAF=leaf\n");
+    Ok(())
+}
+
+#[rstest]
+fn given_unrelated_files_or_missing_key_when_promoting_then_fails_without_changes() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let base = tempdir.path().join("base.env");
+    let leaf = tempdir.path().join("leaf.env");
+    let other = tempdir.path().join("other.env");
+    fs::write(&base, "export REGION=eu\n")?;
+    fs::write(&leaf, "# rsenv: base.env\nexport LEAF=leaf\n")?;
+    fs::write(&other, "export OTHER=other\n")?;
+
+    assert!(matches!(promote_variable("LEAF", &leaf, &other, false, &BuildOptions::default()), Err(TreeError::UnrelatedFiles { .. })));
+    assert!(matches!(promote_variable("LEAF", &leaf, &leaf, false, &BuildOptions::default()), Err(TreeError::UnrelatedFiles { .. })));
+    assert!(matches!(promote_variable("MISSING", &leaf, &base, false, &BuildOptions::default()), Err(TreeError::VariableNotFound { .. })));
+    assert_eq!(fs::read_to_string(&leaf)?, "# rsenv: base.env\nexport LEAF=leaf\n");
+    assert_eq!(fs::read_to_string(&other)?, "export OTHER=other\n");
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use std::fs;
+
+use rstest::rstest;
+use tempfile::tempdir;
+use rsenv::errors::{TreeError, TreeResult};
+use rsenv::schema::{Schema, SchemaViolation, ValueType, ViolationKind};
+use rsenv::util::path::PathExt;
+use rsenv::{build_env_with_sources, BuildOptions};
+
+#[rstest]
+fn given_schema_when_validating_built_env_then_reports_violations_with_source_file() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let dir = tempdir.path().to_canonical()?;
+    fs::write(dir.join("base.env"), "export PORT=eighty\nexport DEBUG=yes\nexport EXTRA=1\n")?;
+    fs::write(dir.join("leaf.env"), "# rsenv: base.env\nexport LOG_LEVEL=\"trace\"\nexport URL=https://example.com\n")?;
+    let schema = dir.join("schema.toml");
+    fs::write(&schema, r#"
+        allow_unknown = false
+
+        [keys.DATABASE_URL]
+        required = true
+        type = "url"
+
+        [keys]
+        URL = { type = "url" }
+        PORT = { type = "int" }
+        DEBUG = { type = "bool" }
+        LOG_LEVEL = { pattern = "debug|info" }
+    "#)?;
+
+    let sourced = build_env_with_sources(&dir.join("leaf.env"), &BuildOptions::default())?;
+    let violations = Schema::from_file(&schema)?.validate(&sourced);
+
+    let violation = |key: &str, file: Option<&str>, kind| SchemaViolation {
+        key: key.to_string(),
+        file: file.map(|f| dir.join(f)),
+        kind,
+    };
+    assert_eq!(violations, vec![
+        violation("DATABASE_URL", None, ViolationKind::Missing),
+        violation("EXTRA", Some("base.env"), ViolationKind::Unknown),
+        violation("LOG_LEVEL", Some("leaf.env"), ViolationKind::PatternMismatch("^(?:debug|info)$".to_string())),
+        violation("PORT", Some("base.env"), ViolationKind::WrongType(ValueType::Int)),
+    ]);
+    Ok(())
+}
+
+#[rstest]
+#[case("[keys.A]\ntype = \"float\"\n")]
+#[case("[keys.A]\npattern = \"(\"\n")]
+#[case("[keys.A]\nrequired = \"yes\"\n")]
+#[case("keys = []\n")]
+#[case("keys = { A = 1 }\n")]
+#[case("not toml")]
+fn given_invalid_schema_when_reading_then_fails(#[case] content: &str) -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let schema = tempdir.path().join("schema.toml");
+    fs::write(&schema, content)?;
+
+    assert!(matches!(Schema::from_file(&schema), Err(TreeError::InvalidFormat { .. })));
+    Ok(())
+}
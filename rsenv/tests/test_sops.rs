@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use rstest::rstest;
+use tempfile::{tempdir, TempDir};
+use rsenv::diagnostics::{file_diagnostics, DiagnosticKind};
+use rsenv::errors::{TreeError, TreeResult};
+use rsenv::format::{format_env_file, needs_formatting};
+use rsenv::sops::{is_sops_encrypted, read_env_lines};
+
+/// Puts a stand-in for sops first on `PATH`, which strips the ENC[] wrapper and the metadata.
+/// Installed once, because rsenv searches `PATH` once per process.
+#[cfg(unix)]
+fn stub_sops() -> &'static Path {
+    use std::os::unix::fs::PermissionsExt;
+
+    static BIN: OnceLock<TempDir> = OnceLock::new();
+    BIN.get_or_init(|| {
+        let bin = tempdir().unwrap();
+        let sops = bin.path().join("sops");
+        fs::write(&sops, "#!/bin/sh\nfor f; do :; done\nsed -e 's/ENC\\[\\(.*\\)\\]/\\1/' \"$f\" | grep -v '^sops_'\n").unwrap();
+        fs::set_permissions(&sops, fs::Permissions::from_mode(0o755)).unwrap();
+        let path = format!("{}:{}", bin.path().display(), std::env::var("PATH").unwrap_or_default());
+        std::env::set_var("PATH", path);
+        bin
+    }).path()
+}
+
+#[cfg(unix)]
+fn encrypted_file(dir: &Path) -> TreeResult<PathBuf> {
+    let path = dir.join("secrets.enc.env");
+    fs::write(&path, "API_KEY=ENC[s3cret]\nEMPTY=ENC[]\nsops_version=3.9.0\n")?;
+    Ok(path)
+}
+
+#[rstest]
+#[case("secrets.enc.env", &[], true)]
+#[case("secrets.env", &["API_KEY=ENC[AES256_GCM,data:abc]", "sops_version=3.9.0"], true)]
+#[case("secrets.env", &["export API_KEY=plain"], false)]
+fn given_env_file_when_checking_for_sops_then_detects_by_suffix_or_metadata(
+    #[case] name: &str,
+    #[case] lines: &[&str],
+    #[case] expected: bool,
+) {
+    let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+    assert_eq!(is_sops_encrypted(Path::new(name), &lines), expected);
+}
+
+#[rstest]
+#[cfg(unix)]
+fn given_encrypted_file_when_reading_then_returns_decrypted_lines() -> TreeResult<()> {
+    stub_sops();
+    let tempdir = tempdir()?;
+    let path = encrypted_file(tempdir.path())?;
+
+    assert_eq!(read_env_lines(&path)?, vec!["API_KEY=s3cret".to_string(), "EMPTY=".to_string()]);
+    Ok(())
+}
+
+#[rstest]
+#[cfg(unix)]
+fn given_encrypted_file_when_collecting_diagnostics_then_checks_decrypted_values() -> TreeResult<()> {
+    stub_sops();
+    let tempdir = tempdir()?;
+    let path = encrypted_file(tempdir.path())?;
+
+    let diagnostics = file_diagnostics(&path, &BTreeMap::new())?;
+
+    let kinds: Vec<_> = diagnostics.iter().map(|d| &d.kind).collect();
+    assert_eq!(kinds, vec![&DiagnosticKind::EmptyValue("EMPTY".to_string())]);
+    Ok(())
+}
+
+#[rstest]
+fn given_encrypted_file_when_formatting_then_refuses() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let path = tempdir.path().join("secrets.enc.env");
+    let content = "API_KEY=ENC[s3cret]\n# rsenv: base.env\nsops_version=3.9.0\n";
+    fs::write(&path, content)?;
+
+    assert!(matches!(needs_formatting(&path), Err(TreeError::InvalidFormat { .. })));
+    assert!(matches!(format_env_file(&path), Err(TreeError::InvalidFormat { .. })));
+    assert_eq!(fs::read_to_string(&path)?, content);
+    Ok(())
+}
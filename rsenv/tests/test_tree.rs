@@ -13,6 +13,8 @@ use generational_arena::Index;
 use rstest::rstest;
 use rsenv::util::path;
 use rsenv::util::path::normalize_path_separator;
+use rsenv::util::file::WalkPolicy;
+use rsenv::util::testing::create_test_project;
 
 #[rstest]
 fn given_invalid_parent_path_when_building_trees_then_returns_error() -> Result<()> {
@@ -262,3 +264,60 @@ fn given_tree_structure_when_printing_complete_tree_then_shows_all_branches() {
     }
 }
 
+
+#[rstest]
+fn given_unreadable_file_when_building_trees_then_skips_it_unless_strict() -> Result<()> {
+    let tempdir = tempfile::tempdir()?;
+    fs::write(tempdir.path().join("root.env"), "export ROOT=root\n")?;
+    fs::write(tempdir.path().join("child.env"), "# rsenv: root.env\nexport CHILD=child\n")?;
+    let unreadable = tempdir.path().join("binary.dat");
+    fs::write(&unreadable, [0xff, 0xfe, 0x00, 0xc3])?;
+
+    let mut builder = TreeBuilder::new();
+    let trees = builder.build_from_directory(tempdir.path())?;
+    assert_eq!(trees.len(), 1);
+    assert_eq!(builder.issues().len(), 1);
+    assert_eq!(builder.issues()[0].path, unreadable);
+
+    let mut builder = TreeBuilder::new().with_policy(WalkPolicy::Strict);
+    assert!(builder.build_from_directory(tempdir.path()).is_err());
+    Ok(())
+}
+
+#[rstest]
+fn given_generated_project_when_building_trees_then_returns_all_leaves() -> Result<()> {
+    let project = create_test_project(3, 2);
+    let mut builder = TreeBuilder::new();
+    let trees = builder.build_from_directory(project.path())?;
+    assert_eq!(trees.len(), 1);
+    assert_eq!(trees[0].depth(), 4);
+
+    let mut leaf_nodes = trees[0].leaf_nodes();
+    leaf_nodes.sort();
+    let mut expected: Vec<String> = project.leaves.iter()
+        .map(|p| p.canonicalize().unwrap().to_string_lossy().to_string())
+        .collect();
+    expected.sort();
+    assert_eq!(leaf_nodes, expected);
+    Ok(())
+}
+
+#[rstest]
+fn given_cycle_below_root_when_building_trees_then_returns_cycle_path() -> Result<()> {
+    let tempdir = tempfile::tempdir()?;
+    let dir = tempdir.path().canonicalize()?;
+    fs::write(dir.join("root.env"), "export ROOT=root\n")?;
+    fs::write(dir.join("a.env"), "# rsenv: root.env\n# rsenv: c.env\nexport A=a\n")?;
+    fs::write(dir.join("b.env"), "# rsenv: a.env\nexport B=b\n")?;
+    fs::write(dir.join("c.env"), "# rsenv: b.env\nexport C=c\n")?;
+
+    let result = TreeBuilder::new().build_from_directory(&dir);
+
+    match result {
+        Err(rsenv::errors::TreeError::CyclicDependency { path }) => {
+            assert_eq!(path, vec![dir.join("a.env"), dir.join("c.env"), dir.join("b.env"), dir.join("a.env")]);
+        }
+        other => panic!("Expected CyclicDependency, got {:?}", other),
+    }
+    Ok(())
+}
@@ -6,8 +6,9 @@ use rstest::{fixture, rstest};
 use tempfile::tempdir;
 use fs_extra::{copy_items, dir};
 use rsenv::build_env_vars;
-use rsenv::envrc::{delete_section, update_dot_envrc, END_SECTION_DELIMITER, START_SECTION_DELIMITER};
-use rsenv::errors::TreeResult;
+use rsenv::diff::EnvChange;
+use rsenv::envrc::{delete_section, repair_dot_envrc, update_dot_envrc, update_dot_envrc_with_options, EnvrcOptions, END_SECTION_DELIMITER, HASH_PREFIX, START_SECTION_DELIMITER};
+use rsenv::errors::{TreeError, TreeResult};
 
 #[fixture]
 fn temp_dir() -> PathBuf {
@@ -92,4 +93,66 @@ fn given_multiple_updates_when_updating_envrc_then_maintains_single_section(temp
     assert_eq!(file_contents.matches(END_SECTION_DELIMITER).count(), 1);
 
     Ok(())
-}
\ No newline at end of file
+}
+#[rstest]
+fn given_manually_edited_section_when_updating_then_requires_force(temp_dir: PathBuf) -> TreeResult<()> {
+    let path = temp_dir.join("dot.envrc");
+    let data = build_env_vars(Path::new("./tests/resources/environments/complex/level4.env"))?;
+    update_dot_envrc(&path, &data)?;
+
+    // Given: a hand edit inside the managed section
+    let edited = get_file_contents(&path)?.replace("export VAR_6=var_64", "export VAR_6=edited");
+    fs::write(&path, edited)?;
+
+    // When/Then: a regular update refuses to overwrite it
+    let result = update_dot_envrc(&path, &data);
+    assert!(matches!(result, Err(TreeError::ManagedSectionModified(_))));
+    assert!(get_file_contents(&path)?.contains("export VAR_6=edited"));
+
+    // When/Then: a forced update overwrites it
+    update_dot_envrc_with_options(&path, &data, &EnvrcOptions { force: true })?;
+    let file_contents = get_file_contents(&path)?;
+    assert!(file_contents.contains("export VAR_6=var_64"));
+    assert!(file_contents.contains(HASH_PREFIX));
+    Ok(())
+}
+
+#[rstest]
+fn given_existing_section_when_updating_then_returns_changed_variables(temp_dir: PathBuf) -> TreeResult<()> {
+    let path = temp_dir.join("dot.envrc");
+    let data1 = build_env_vars(Path::new("./tests/resources/environments/complex/level4.env"))?;
+    let data2 = build_env_vars(Path::new("./tests/resources/environments/complex/a/level3.env"))?;
+
+    let changes = update_dot_envrc_with_options(&path, &data1, &EnvrcOptions::default())?;
+    assert!(changes.iter().all(|c| matches!(c, EnvChange::Added { .. })));
+
+    let changes = update_dot_envrc_with_options(&path, &data2, &EnvrcOptions::default())?;
+    assert_eq!(changes, vec![
+        EnvChange::Changed { key: "VAR_6".into(), old: "var_64".into(), new: "var_63  # new variable added".into() },
+        EnvChange::Removed { key: "VAR_7".into(), value: "var_74".into() },
+    ]);
+    Ok(())
+}
+
+#[rstest]
+fn given_duplicate_sections_when_updating_then_fails_and_repair_keeps_newest(temp_dir: PathBuf) -> TreeResult<()> {
+    let path = temp_dir.join("dot.envrc");
+    update_dot_envrc(&path, "export A=old\n")?;
+    let contents = get_file_contents(&path)?;
+    let start = contents.find(START_SECTION_DELIMITER).unwrap();
+    let newest = contents[start..].replace("export A=old", "export A=new");
+    fs::write(&path, format!("{}\nexport USER_LINE=1\n{}", contents.trim_end(), newest))?;
+
+    let result = update_dot_envrc(&path, "export A=other\n");
+    assert!(matches!(result, Err(TreeError::DuplicateManagedSection { count: 2, .. })));
+
+    assert_eq!(repair_dot_envrc(&path)?, 1);
+    let contents = get_file_contents(&path)?;
+    assert_eq!(contents.matches(START_SECTION_DELIMITER).count(), 1);
+    assert!(contents.contains("export USER_LINE=1"));
+    assert!(contents.contains("export A=new"));
+    assert!(!contents.contains("export A=old"));
+    assert_eq!(repair_dot_envrc(&path)?, 0);
+    update_dot_envrc(&path, "export A=other\n")?;
+    Ok(())
+}
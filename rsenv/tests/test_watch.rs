@@ -0,0 +1,40 @@
+use std::fs;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use rstest::rstest;
+use tempfile::tempdir;
+use rsenv::errors::TreeResult;
+use rsenv::watch::watch_env;
+use rsenv::BuildOptions;
+
+#[rstest]
+fn given_watched_leaf_when_parent_changes_then_rebuilds() -> TreeResult<()> {
+    let tempdir = tempdir()?;
+    let parent = tempdir.path().join("parent.env");
+    let leaf = tempdir.path().join("leaf.env");
+    fs::write(&parent, "export SHARED=before\n")?;
+    fs::write(&leaf, "# rsenv: parent.env\nexport LEAF=leaf\n")?;
+
+    let (tx, rx) = mpsc::channel();
+    let watched_leaf = leaf.clone();
+    let watcher = thread::spawn(move || {
+        let mut builds = 0;
+        watch_env(&watched_leaf, &BuildOptions::default(), |result| {
+            let shared = result.as_ref().ok().and_then(|v| v.get("SHARED").cloned());
+            tx.send(shared).unwrap();
+            builds += 1;
+            builds < 2
+        })
+    });
+
+    let timeout = Duration::from_secs(10);
+    assert_eq!(rx.recv_timeout(timeout).unwrap().as_deref(), Some("before"));
+    // unrelated files in the same directory do not trigger a rebuild
+    fs::write(tempdir.path().join("other.env"), "export OTHER=1\n")?;
+    fs::write(&parent, "export SHARED=after\n")?;
+    assert_eq!(rx.recv_timeout(timeout).unwrap().as_deref(), Some("after"));
+    watcher.join().unwrap()?;
+    Ok(())
+}